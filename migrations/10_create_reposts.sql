@@ -0,0 +1,20 @@
+-- A repost without a quote is a plain one. A quote is a post of its own,
+-- which the repost ties to the post it quotes
+CREATE TABLE "reposts" (
+    repost_id uuid primary key default gen_random_uuid(),
+    user_id uuid not null references "users"(user_id) on delete cascade,
+    post_id uuid not null references "posts"(post_id) on delete cascade,
+    quote_post_id uuid unique references "posts"(post_id) on delete cascade,
+    created_at timestamptz not null default now()
+);
+
+-- Quoting the same post again is fine, reposting it plainly twice is not
+CREATE UNIQUE INDEX reposts_user_id_post_id_key ON "reposts" (user_id, post_id)
+    WHERE quote_post_id IS NULL;
+CREATE INDEX reposts_post_id_idx ON "reposts" (post_id);
+CREATE INDEX reposts_user_id_created_at_idx ON "reposts" (user_id, created_at DESC);
+
+-- Kept up to date along with the reposts, the same as reply_count
+ALTER TABLE "posts"
+    ADD COLUMN repost_count integer not null default 0,
+    ADD COLUMN quote_count integer not null default 0;
@@ -0,0 +1,13 @@
+-- Liking a post again is a no-op, so the pair is all there is to a like
+CREATE TABLE "likes" (
+    user_id uuid not null references "users"(user_id) on delete cascade,
+    post_id uuid not null references "posts"(post_id) on delete cascade,
+    created_at timestamptz not null default now(),
+    primary key (user_id, post_id)
+);
+
+CREATE INDEX likes_post_id_created_at_idx ON "likes" (post_id, created_at DESC);
+CREATE INDEX likes_user_id_created_at_idx ON "likes" (user_id, created_at DESC);
+
+-- Kept up to date along with the likes, the same as reply_count
+ALTER TABLE "posts" ADD COLUMN like_count integer not null default 0;
@@ -0,0 +1,30 @@
+-- Everything a post is shown with, so that queries can read it as a single
+-- row rather than list and join it all over again
+CREATE VIEW "post_view" AS
+SELECT
+    "posts".post_id,
+    "posts".author_id,
+    "users".username,
+    "posts".body,
+    "posts".in_reply_to,
+    "posts".conversation_id,
+    "quote".post_id AS quoted_post_id,
+    "posts".reply_count,
+    "posts".repost_count,
+    "posts".quote_count,
+    "posts".like_count,
+    extract(epoch from "posts".created_at)::bigint AS created_at,
+    extract(epoch from "posts".edited_at)::bigint AS edited_at
+FROM "posts"
+JOIN "users" ON "users".user_id = "posts".author_id
+LEFT JOIN "reposts" AS "quote" ON "quote".quote_post_id = "posts".post_id;
+
+-- A view cannot take whoever is looking as a parameter, so whether they liked
+-- the post is asked for apart from it
+CREATE FUNCTION "liked_by"(post_id uuid, user_id uuid) RETURNS boolean
+LANGUAGE sql STABLE
+AS $$
+    SELECT EXISTS (
+        SELECT 1 FROM "likes" WHERE "likes".post_id = $1 AND "likes".user_id = $2
+    )
+$$;
@@ -0,0 +1,10 @@
+-- Anyone may type in any address when signing up, so refusing one that is
+-- already claimed would both tell who has an account and let a squatter keep
+-- its owner out. An address only belongs to one account once it is verified,
+-- and until then any number of accounts may claim it
+DROP INDEX users_email_lower_key;
+
+CREATE UNIQUE INDEX users_verified_email_lower_key ON "users" (lower(email))
+    WHERE email_verified_at IS NOT NULL;
+
+CREATE INDEX users_email_lower_idx ON "users" (lower(email));
@@ -0,0 +1,46 @@
+-- A deleted post stays in its thread for as long as anything below it does,
+-- which `reply_count` cannot tell, as it leaves deleted replies out whether
+-- or not they still hold live ones up. This counts the direct replies which
+-- are shown in threads instead: those not deleted, and those with a reply of
+-- their own still shown
+ALTER TABLE "posts" ADD COLUMN thread_reply_count integer not null default 0;
+
+-- Posts are shown when they are live or above one which is
+WITH RECURSIVE "shown" AS (
+    SELECT post_id, in_reply_to
+    FROM "posts"
+    WHERE deleted_at IS NULL
+    UNION
+    SELECT "posts".post_id, "posts".in_reply_to
+    FROM "posts"
+    JOIN "shown" ON "posts".post_id = "shown".in_reply_to
+)
+UPDATE "posts"
+SET thread_reply_count = "counts".count
+FROM (
+    SELECT in_reply_to, count(*) AS count
+    FROM "shown"
+    WHERE in_reply_to IS NOT NULL
+    GROUP BY in_reply_to
+) AS "counts"
+WHERE "posts".post_id = "counts".in_reply_to;
+
+CREATE OR REPLACE VIEW "post_view" AS
+SELECT
+    "posts".post_id,
+    "posts".author_id,
+    "users".username,
+    "posts".body,
+    "posts".in_reply_to,
+    "posts".conversation_id,
+    "quote".post_id AS quoted_post_id,
+    "posts".reply_count,
+    "posts".repost_count,
+    "posts".quote_count,
+    "posts".like_count,
+    extract(epoch from "posts".created_at)::bigint AS created_at,
+    extract(epoch from "posts".edited_at)::bigint AS edited_at,
+    "posts".thread_reply_count
+FROM "posts"
+JOIN "users" ON "users".user_id = "posts".author_id
+LEFT JOIN "reposts" AS "quote" ON "quote".quote_post_id = "posts".post_id;
@@ -0,0 +1,10 @@
+-- Usernames which only look alike, such as `rn` and `m` or a Latin `o` and a
+-- Greek `ο` in otherwise different names, share the same confusable skeleton,
+-- and only one account may have each
+--
+-- The skeleton is worked out by the server, which fills it in for older
+-- accounts when it starts. Those which already look like another account's
+-- are left without one, and keep their username
+ALTER TABLE "users" ADD COLUMN username_skeleton text;
+
+CREATE UNIQUE INDEX users_username_skeleton_key ON "users" (username_skeleton);
@@ -0,0 +1,27 @@
+-- Usernames are stored normalized from now on, but older ones may still differ
+-- from each other only by case
+--
+-- Such clashes have to be settled before the index can be built. Users have no
+-- creation time to tell the earliest account by, so the one whose username is
+-- already in lowercase keeps it, or else the first by ID. The others get their
+-- ID appended, which no one else can have, and remember what they were called
+-- so that they can be told to pick a new username
+ALTER TABLE "users" ADD COLUMN renamed_from text;
+
+WITH "clashes" AS (
+    SELECT
+        user_id,
+        row_number() OVER (
+            PARTITION BY lower(username)
+            ORDER BY username = lower(username) DESC, user_id
+        ) AS rank
+    FROM "users"
+)
+UPDATE "users"
+SET
+    renamed_from = username,
+    username = lower(username) || '_' || replace(user_id::text, '-', '')
+FROM "clashes"
+WHERE "users".user_id = "clashes".user_id AND "clashes".rank > 1;
+
+CREATE UNIQUE INDEX users_username_lower_key ON "users" (lower(username));
@@ -0,0 +1,16 @@
+CREATE TABLE "totp" (
+    user_id uuid primary key references "users"(user_id) on delete cascade,
+    secret bytea not null,
+    -- Enrollment only takes effect once the user has proven they set the
+    -- secret up by entering a first code
+    confirmed boolean not null default false,
+    -- The time step of the last accepted code, so that no code can be used
+    -- twice
+    last_used_step bigint
+);
+
+CREATE TABLE "totp_recovery_codes" (
+    user_id uuid not null references "users"(user_id) on delete cascade,
+    code_hash text not null,
+    primary key (user_id, code_hash)
+);
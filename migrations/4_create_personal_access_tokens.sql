@@ -0,0 +1,15 @@
+CREATE TABLE "personal_access_tokens" (
+    token_id uuid primary key default gen_random_uuid(),
+    user_id uuid not null references "users"(user_id) on delete cascade,
+    name text not null,
+    -- Tokens are random enough for a fast hash to do, and only their hashes
+    -- are kept
+    token_hash text unique not null,
+    scopes text[] not null,
+    created_at timestamptz not null default now(),
+    -- Tokens without an expiry last until they are revoked
+    expires_at timestamptz,
+    last_used_at timestamptz
+);
+
+CREATE INDEX personal_access_tokens_user_id_idx ON "personal_access_tokens" (user_id);
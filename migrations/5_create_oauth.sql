@@ -0,0 +1,35 @@
+CREATE TABLE "oauth_clients" (
+    client_id uuid primary key default gen_random_uuid(),
+    owner_id uuid not null references "users"(user_id) on delete cascade,
+    name text not null,
+    -- Public clients, such as native and browser apps, cannot keep a secret
+    -- and rely on PKCE alone
+    secret_hash text,
+    redirect_uris text[] not null,
+    created_at timestamptz not null default now()
+);
+
+CREATE INDEX oauth_clients_owner_id_idx ON "oauth_clients" (owner_id);
+
+CREATE TABLE "oauth_authorization_codes" (
+    code_hash text primary key,
+    client_id uuid not null references "oauth_clients"(client_id) on delete cascade,
+    user_id uuid not null references "users"(user_id) on delete cascade,
+    redirect_uri text not null,
+    scopes text[] not null,
+    code_challenge text not null,
+    expires_at timestamptz not null
+);
+
+-- One row per grant, whose tokens are both replaced whenever it is refreshed
+CREATE TABLE "oauth_tokens" (
+    token_id uuid primary key default gen_random_uuid(),
+    client_id uuid not null references "oauth_clients"(client_id) on delete cascade,
+    user_id uuid not null references "users"(user_id) on delete cascade,
+    scopes text[] not null,
+    access_token_hash text unique not null,
+    access_expires_at timestamptz not null,
+    refresh_token_hash text unique not null,
+    refresh_expires_at timestamptz not null,
+    created_at timestamptz not null default now()
+);
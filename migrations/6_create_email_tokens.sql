@@ -0,0 +1,21 @@
+-- Accounts created before emails were asked for have none, and have no way to
+-- recover their password until they add one
+ALTER TABLE "users"
+    ADD COLUMN email text,
+    ADD COLUMN email_verified_at timestamptz;
+
+CREATE UNIQUE INDEX users_email_lower_key ON "users" (lower(email));
+
+CREATE TABLE "email_tokens" (
+    -- Tokens are random enough for a fast hash to do, and only their hashes
+    -- are kept
+    token_hash text primary key,
+    user_id uuid not null references "users"(user_id) on delete cascade,
+    purpose text not null check (purpose in ('verify_email', 'reset_password')),
+    -- The address the token was sent to, so that verifying it does not vouch
+    -- for whatever address the user has since switched to
+    email text not null,
+    expires_at timestamptz not null
+);
+
+CREATE INDEX email_tokens_user_id_idx ON "email_tokens" (user_id);
@@ -0,0 +1,15 @@
+CREATE TABLE "passkeys" (
+    passkey_id uuid primary key default gen_random_uuid(),
+    user_id uuid not null references "users"(user_id) on delete cascade,
+    -- Authenticators pick credential IDs themselves, and no two accounts may
+    -- share one
+    credential_id bytea unique not null,
+    name text not null,
+    -- The public key, signature counter and flags, as serialized by
+    -- webauthn-rs
+    credential text not null,
+    created_at timestamptz not null default now(),
+    last_used_at timestamptz
+);
+
+CREATE INDEX passkeys_user_id_idx ON "passkeys" (user_id);
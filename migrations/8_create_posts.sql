@@ -0,0 +1,11 @@
+CREATE TABLE "posts" (
+    post_id uuid primary key default gen_random_uuid(),
+    author_id uuid not null references "users"(user_id) on delete cascade,
+    -- The limit counts characters, the same way the API does
+    body text not null check (char_length(body) between 1 and 280),
+    created_at timestamptz not null default now(),
+    -- Left unset until the post is first edited
+    edited_at timestamptz
+);
+
+CREATE INDEX posts_author_id_created_at_idx ON "posts" (author_id, created_at DESC);
@@ -0,0 +1,23 @@
+-- Deleted posts are kept as tombstones, without a body, so that the replies
+-- they got still hang together
+ALTER TABLE "posts"
+    ALTER COLUMN body DROP NOT NULL,
+    ADD COLUMN deleted_at timestamptz,
+    ADD CONSTRAINT posts_deleted_check CHECK ((body IS NULL) = (deleted_at IS NOT NULL));
+
+-- The root of a conversation is its own conversation. Parents are never
+-- deleted for good, save for when their author's account is
+ALTER TABLE "posts"
+    ADD COLUMN in_reply_to uuid references "posts"(post_id) on delete set null,
+    ADD COLUMN conversation_id uuid,
+    ADD COLUMN depth integer not null default 0,
+    -- The direct replies which are not deleted, kept up to date along with
+    -- them so that threads can be ranked without counting
+    ADD COLUMN reply_count integer not null default 0;
+
+UPDATE "posts" SET conversation_id = post_id;
+
+ALTER TABLE "posts" ALTER COLUMN conversation_id SET NOT NULL;
+
+CREATE INDEX posts_in_reply_to_idx ON "posts" (in_reply_to);
+CREATE INDEX posts_conversation_id_idx ON "posts" (conversation_id);
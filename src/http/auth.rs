@@ -1,24 +1,75 @@
-use axum::{http, response, routing::get, Extension, Router};
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Path, TypedHeader},
+    headers::{Cookie, UserAgent},
+    http, response,
+    routing::{delete, get, post},
+    Extension, Router,
+};
 use sqlx::PgPool;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 use thiserror::Error;
+use uuid::Uuid;
+use webauthn_rs::prelude::{
+    PasskeyAuthentication, PublicKeyCredential, RequestChallengeResponse, Webauthn, WebauthnError,
+};
 
 use crate::{
-    http::{json, session},
-    password,
+    http::{api_error, json, passkeys, rate_limit, session, tokens, totp},
+    password, username,
 };
 
+/// Session data tied to who is logged in, which must not survive the session
+/// being handed over to another login
+const IDENTITY_KEYS: &[&str] = &[
+    "user_id",
+    "pending_user_id",
+    "pending_username",
+    "passkey_user_id",
+    "passkey_username",
+    "passkey_authentication",
+    passkeys::REGISTRATION_KEY,
+];
+
+/// How long a login that got the password right may take to provide the
+/// second factor
+const SECOND_FACTOR_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// How long the authenticator may take to sign the challenge of a passkey
+/// login
+const PASSKEY_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
 pub(in crate::http) fn router() -> Router
 {
-    Router::new().route("/auth", get(fetch_auth_session).post(create_auth_session))
+    Router::new()
+        .route(
+            "/auth",
+            get(fetch_auth_session)
+                .post(create_auth_session)
+                .delete(delete_auth_session),
+        )
+        .route(
+            "/auth/sessions",
+            get(fetch_auth_sessions).delete(delete_other_auth_sessions),
+        )
+        .route("/auth/sessions/:id", delete(revoke_auth_session))
+        .route("/auth/totp", post(create_totp_auth_session))
+        .route("/auth/webauthn", post(create_webauthn_auth_session))
+        .route("/auth/webauthn/challenge", post(create_webauthn_challenge))
 }
 
 async fn fetch_auth_session(user_id: session::extractor::UserId) -> Result<String>
 {
     match user_id {
-        session::extractor::UserId::Found(user_id) => Ok(user_id.to_string()),
-        session::extractor::UserId::NotFound => Err(Error::MustBeAuthenticated),
+        session::extractor::UserId::Found(user_id, auth_method)
+            if auth_method.allows(tokens::Scope::Read) =>
+        {
+            Ok(user_id.to_string())
+        }
+        _ => Err(Error::MustBeAuthenticated),
     }
 }
 
@@ -32,54 +83,519 @@ struct CreateAuthSession
 async fn create_auth_session(
     pg_pool: Extension<PgPool>,
     session_store: Extension<session::Store>,
+    Extension(session_expiry): Extension<session::Expiry>,
+    cookie_builder: Extension<session::CookieBuilder>,
+    password_hasher: Extension<password::Hasher>,
+    login_limiter: Extension<rate_limit::LoginLimiter>,
+    cookie: Option<TypedHeader<Cookie>>,
+    user_agent: Option<TypedHeader<UserAgent>>,
+    rate_limit::ClientIp(ip): rate_limit::ClientIp,
     json::extractor::Json(req): json::extractor::Json<CreateAuthSession>,
-) -> Result<(http::HeaderMap, http::StatusCode)>
+) -> Result<(http::HeaderMap, Login)>
 {
     let CreateAuthSession { username, password } = req;
+    let username = lookup_username(username);
+
+    login_limiter.attempt(ip, &username).await?;
 
-    let user = sqlx::query!(
-        r#"select user_id, password from users where username = $1"#,
+    let user = sqlx::query_as!(
+        User,
+        r#"select user_id, password from users where lower(username) = lower($1)"#,
         username
     )
     .fetch_optional(&*pg_pool)
     .await?;
 
+    let user = authenticate(&password_hasher, user, password.clone()).await?;
+
+    // The password is only ever known at login, so this is where hashes
+    // computed with outdated parameters or peppers get upgraded
+    if password_hasher.needs_rehash(&user.password)? {
+        let password = password_hasher.hash(password).await?;
+
+        let _pg_query_res = sqlx::query!(
+            r#"
+                UPDATE "users"
+                SET password = $1
+                WHERE user_id = $2
+            "#,
+            password,
+            user.user_id
+        )
+        .execute(&*pg_pool)
+        .await?;
+    }
+
+    let totp_enabled = totp::is_enabled(&pg_pool, user.user_id).await?;
+
+    let previous_session = load_previous_session(&session_store, cookie.as_ref()).await?;
+    let expiry = if totp_enabled {
+        session::Expiry::new(SECOND_FACTOR_TIMEOUT, SECOND_FACTOR_TIMEOUT)
+    } else {
+        session_expiry
+    };
+    let mut session = rotate_session(&session_store, previous_session, expiry).await?;
+    session
+        .insert(
+            "user_agent",
+            user_agent.map(|TypedHeader(user_agent)| String::from(user_agent.as_str())),
+        )
+        .await?;
+    session.insert("ip", ip).await?;
+
+    // The password alone only gets a short-lived session which is not logged
+    // in, until `POST /auth/totp` completes it. Attempts are not forgotten
+    // until then either, or knowing the password would allow for guessing
+    // codes without limit
+    if totp_enabled {
+        session.insert("pending_user_id", user.user_id).await?;
+        session.insert("pending_username", &username).await?;
+        let headers = store_session_cookie(&session_store, &cookie_builder, session).await?;
+
+        return Ok((headers, Login::SecondFactorRequired));
+    }
+
+    login_limiter.record_success(ip, &username).await?;
+
+    session.insert("user_id", user.user_id).await?;
+    let headers = store_session_cookie(&session_store, &cookie_builder, session).await?;
+
+    Ok((headers, Login::Complete))
+}
+
+/// Where a login stands after the password step
+enum Login
+{
+    Complete,
+    SecondFactorRequired,
+}
+
+impl response::IntoResponse for Login
+{
+    fn into_response(self) -> response::Response
+    {
+        match self {
+            Login::Complete => http::StatusCode::NO_CONTENT.into_response(),
+            Login::SecondFactorRequired => (
+                http::StatusCode::ACCEPTED,
+                axum::Json(json!({ "secondFactor": "totp" })),
+            )
+                .into_response(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum SecondFactor
+{
+    Code(String),
+    RecoveryCode(String),
+}
+
+async fn create_totp_auth_session(
+    pg_pool: Extension<PgPool>,
+    session_store: Extension<session::Store>,
+    Extension(session_expiry): Extension<session::Expiry>,
+    cookie_builder: Extension<session::CookieBuilder>,
+    login_limiter: Extension<rate_limit::LoginLimiter>,
+    cookie: Option<TypedHeader<Cookie>>,
+    rate_limit::ClientIp(ip): rate_limit::ClientIp,
+    json::extractor::Json(req): json::extractor::Json<SecondFactor>,
+) -> Result<(http::HeaderMap, http::StatusCode)>
+{
+    let pending_session = load_previous_session(&session_store, cookie.as_ref())
+        .await?
+        .ok_or(Error::NoPendingLogin)?;
+    let user_id = pending_session
+        .get::<Uuid>("pending_user_id")
+        .await
+        .ok_or(Error::NoPendingLogin)?;
+    let username = pending_session
+        .get::<String>("pending_username")
+        .await
+        .ok_or(Error::NoPendingLogin)?;
+
+    login_limiter.attempt(ip, &username).await?;
+
+    let is_valid = match req {
+        SecondFactor::Code(code) => totp::use_code(&pg_pool, user_id, &code).await?,
+        SecondFactor::RecoveryCode(recovery_code) => {
+            totp::use_recovery_code(&pg_pool, user_id, &recovery_code).await?
+        }
+    };
+    if !is_valid {
+        return Err(Error::InvalidSecondFactor);
+    }
+
+    login_limiter.record_success(ip, &username).await?;
+
+    // Completing the login rotates the session ID once more, along with
+    // trading the short lifetime of the pending session for a full one
+    let mut session = rotate_session(&session_store, Some(pending_session), session_expiry).await?;
+    session.insert("user_id", user_id).await?;
+    let headers = store_session_cookie(&session_store, &cookie_builder, session).await?;
+
+    Ok((headers, http::StatusCode::NO_CONTENT))
+}
+
+#[derive(Deserialize)]
+struct CreateWebauthnChallenge
+{
+    username: String,
+}
+
+/// Starts a passwordless login, handing back the options to pass to
+/// `navigator.credentials.get()`
+///
+/// Passkeys are only looked up for the given username, so unknown users and
+/// users without any passkey are both refused as invalid credentials
+async fn create_webauthn_challenge(
+    pg_pool: Extension<PgPool>,
+    session_store: Extension<session::Store>,
+    cookie_builder: Extension<session::CookieBuilder>,
+    webauthn: Extension<Arc<Webauthn>>,
+    login_limiter: Extension<rate_limit::LoginLimiter>,
+    cookie: Option<TypedHeader<Cookie>>,
+    user_agent: Option<TypedHeader<UserAgent>>,
+    rate_limit::ClientIp(ip): rate_limit::ClientIp,
+    json::extractor::Json(req): json::extractor::Json<CreateWebauthnChallenge>,
+) -> Result<(http::HeaderMap, axum::Json<RequestChallengeResponse>)>
+{
+    let username = lookup_username(req.username);
+
+    login_limiter.check(ip, &username).await?;
+
+    let user_id = sqlx::query!(
+        r#"select user_id from users where lower(username) = lower($1)"#,
+        username
+    )
+    .fetch_optional(&*pg_pool)
+    .await?
+    .ok_or(Error::InvalidCredentials)?
+    .user_id;
+
+    let user_passkeys = passkeys::user_passkeys(&pg_pool, user_id)
+        .await?
+        .into_iter()
+        .map(|(_passkey_id, passkey)| passkey)
+        .collect::<Vec<_>>();
+    if user_passkeys.is_empty() {
+        return Err(Error::InvalidCredentials);
+    }
+
+    let (challenge, authentication) = webauthn.start_passkey_authentication(&user_passkeys)?;
+
+    // Like a login waiting for its second factor, the challenge lives in a
+    // short-lived session which is not logged in
+    let previous_session = load_previous_session(&session_store, cookie.as_ref()).await?;
+    let mut session = rotate_session(
+        &session_store,
+        previous_session,
+        session::Expiry::new(PASSKEY_TIMEOUT, PASSKEY_TIMEOUT),
+    )
+    .await?;
+    session
+        .insert(
+            "user_agent",
+            user_agent.map(|TypedHeader(user_agent)| String::from(user_agent.as_str())),
+        )
+        .await?;
+    session.insert("ip", ip).await?;
+    session.insert("passkey_user_id", user_id).await?;
+    session.insert("passkey_username", &username).await?;
+    session
+        .insert("passkey_authentication", authentication)
+        .await?;
+    let headers = store_session_cookie(&session_store, &cookie_builder, session).await?;
+
+    Ok((headers, axum::Json(challenge)))
+}
+
+/// Completes the login started by [`create_webauthn_challenge`] with what
+/// `navigator.credentials.get()` resolved to, issuing the same session cookie
+/// as a password login. Passkeys verify the user on the authenticator itself,
+/// so no second factor is asked for on top
+async fn create_webauthn_auth_session(
+    pg_pool: Extension<PgPool>,
+    session_store: Extension<session::Store>,
+    Extension(session_expiry): Extension<session::Expiry>,
+    cookie_builder: Extension<session::CookieBuilder>,
+    webauthn: Extension<Arc<Webauthn>>,
+    login_limiter: Extension<rate_limit::LoginLimiter>,
+    cookie: Option<TypedHeader<Cookie>>,
+    rate_limit::ClientIp(ip): rate_limit::ClientIp,
+    json::extractor::Json(credential): json::extractor::Json<PublicKeyCredential>,
+) -> Result<(http::HeaderMap, http::StatusCode)>
+{
+    let pending_session = load_previous_session(&session_store, cookie.as_ref())
+        .await?
+        .ok_or(Error::NoPendingLogin)?;
+    let user_id = pending_session
+        .get::<Uuid>("passkey_user_id")
+        .await
+        .ok_or(Error::NoPendingLogin)?;
+    let username = pending_session
+        .get::<String>("passkey_username")
+        .await
+        .ok_or(Error::NoPendingLogin)?;
+    let authentication = pending_session
+        .get::<PasskeyAuthentication>("passkey_authentication")
+        .await
+        .ok_or(Error::NoPendingLogin)?;
+
+    login_limiter.attempt(ip, &username).await?;
+
+    let result = webauthn
+        .finish_passkey_authentication(&credential, &authentication)
+        .map_err(|_| Error::InvalidCredentials)?;
+
+    // The passkey may have been deleted since the challenge was issued
+    let (passkey_id, mut passkey) = passkeys::user_passkeys(&pg_pool, user_id)
+        .await?
+        .into_iter()
+        .find(|(_passkey_id, passkey)| passkey.cred_id() == result.cred_id())
+        .ok_or(Error::InvalidCredentials)?;
+    let is_updated = passkey.update_credential(&result) == Some(true);
+    passkeys::record_use(&pg_pool, passkey_id, is_updated.then_some(&passkey)).await?;
+
+    login_limiter.record_success(ip, &username).await?;
+
+    let mut session = rotate_session(&session_store, Some(pending_session), session_expiry).await?;
+    session.insert("user_id", user_id).await?;
+    let headers = store_session_cookie(&session_store, &cookie_builder, session).await?;
+
+    Ok((headers, http::StatusCode::NO_CONTENT))
+}
+
+/// Hands over what the previous session held, save for who was logged in, to
+/// a session under a fresh ID, so that a cookie planted before authenticating
+/// is worthless afterwards
+async fn rotate_session(
+    session_store: &session::Store,
+    previous_session: Option<session::Session>,
+    expiry: session::Expiry,
+) -> Result<session::Session>
+{
+    match previous_session {
+        Some(mut session) => {
+            session_store.destroy_session(session.id()).await?;
+            session.retain(|key| !IDENTITY_KEYS.contains(&key));
+            session.regenerate(expiry);
+            Ok(session)
+        }
+        None => Ok(session::Session::new(expiry)),
+    }
+}
+
+/// Persists a session fresh out of [`rotate_session`], returning the headers
+/// which set its cookie
+async fn store_session_cookie(
+    session_store: &session::Store,
+    cookie_builder: &session::CookieBuilder,
+    session: session::Session,
+) -> Result<http::HeaderMap>
+{
+    let max_age = session.remaining_lifetime();
+    // SAFETY: This cannot fail as store_session propagates `None` upon a
+    // `None` field for the session's cookie value, which will never be empty
+    // as the session was either created or regenerated, both of which set a
+    // fresh cookie value
+    let cookie = session_store.store_session(session).await?.unwrap();
+
+    let mut headers = http::HeaderMap::new();
+    let header_value = cookie_builder.build(&cookie, max_age)?;
+    let _prev_value = headers.insert(http::header::SET_COOKIE, header_value);
+
+    Ok(headers)
+}
+
+struct User
+{
+    user_id: Uuid,
+    password: String,
+}
+
+/// Brings the username given at login to the form it was stored in. The
+/// username rules only apply to new usernames, so one which breaks them may
+/// still belong to an account created before they did, and is looked up as
+/// given, regardless of case
+fn lookup_username(username: String) -> String
+{
+    username::normalize(&username).unwrap_or(username)
+}
+
+/// Checks the password of the user, failing the same way whether the user
+/// does not exist or the password is wrong. Unknown users have the password
+/// checked against a dummy hash instead, so that both cases take as long
+async fn authenticate(
+    password_hasher: &password::Hasher,
+    user: Option<User>,
+    password: String,
+) -> Result<User>
+{
     match user {
         Some(user) => {
-            let password_is_correct = password::verify(password, user.password).await?;
+            let password_is_correct = password_hasher
+                .verify(password, user.password.clone())
+                .await?;
 
             if password_is_correct {
-                let mut session = session::Session::new();
-                session.insert("user_id", user.user_id).await?;
-                // SAFETY: This cannot fail as store_session propagates `None`
-                // upon a `None` field for the session's cookie value, which
-                // will never be empty as we create the session above and never
-                // mutate its cookie value
-                let cookie = session_store.store_session(session).await?.unwrap();
-
-                let mut headers = http::HeaderMap::new();
-                let header_value = http::HeaderValue::from_str(&format!(
-                    "{}={}",
-                    session::SESSION_COOKIE_NAME,
-                    cookie
-                ))
-                // SAFETY: It is known in advance that `SESSION_COOKIE_NAME` as
-                // well as the cookie propagated from the init of the session
-                // are both always going to be ASCII-only, therefore the
-                // formatted string will be ASCII-only, so creating the
-                // HeaderValue will never fail
-                .unwrap();
-                let _prev_value = headers.insert(http::header::SET_COOKIE, header_value);
-
-                Ok((headers, http::StatusCode::NO_CONTENT))
+                Ok(user)
             } else {
-                Err(Error::WrongPassword)
+                Err(Error::InvalidCredentials)
             }
         }
-        None => Err(Error::UserNotFound { username }),
+        None => {
+            password_hasher.verify_dummy(password).await?;
+
+            Err(Error::InvalidCredentials)
+        }
     }
 }
 
+/// Loads the session the client already carries, if any, treating a stale or
+/// malformed cookie the same as no cookie at all
+async fn load_previous_session(
+    session_store: &session::Store,
+    cookie: Option<&TypedHeader<Cookie>>,
+) -> Result<Option<session::Session>>
+{
+    let session_cookie = match cookie.and_then(|cookie| cookie.get(session::SESSION_COOKIE_NAME)) {
+        Some(session_cookie) => session_cookie,
+        None => return Ok(None),
+    };
+
+    match session_store.load_session(session_cookie).await {
+        Ok(session) => Ok(Some(session)),
+        Err(
+            session::Error::NoSessionFound { .. }
+            | session::Error::SessionExpired { .. }
+            | session::Error::Base64Decode(_),
+        ) => Ok(None),
+        Err(err) => Err(err)?,
+    }
+}
+
+async fn delete_auth_session(
+    session_store: Extension<session::Store>,
+    cookie_builder: Extension<session::CookieBuilder>,
+    cookie: Option<TypedHeader<Cookie>>,
+) -> Result<(http::HeaderMap, http::StatusCode)>
+{
+    let session_cookie = cookie
+        .as_ref()
+        .and_then(|cookie| cookie.get(session::SESSION_COOKIE_NAME))
+        .ok_or(Error::MustBeAuthenticated)?;
+
+    // A cookie which does not even decode cannot belong to any session, yet
+    // the client is still told to drop it
+    if let Ok(id) = session::Session::id_from_cookie(session_cookie) {
+        session_store.destroy_session(&id).await?;
+    }
+    // A session which has not been loaded since its ID changed encoding is
+    // still stored under the legacy one
+    if let Ok(legacy_id) = session::Session::legacy_id_from_cookie(session_cookie) {
+        session_store.destroy_session(&legacy_id).await?;
+    }
+
+    let mut headers = http::HeaderMap::new();
+    let header_value = cookie_builder.expired()?;
+    let _prev_value = headers.insert(http::header::SET_COOKIE, header_value);
+
+    Ok((headers, http::StatusCode::NO_CONTENT))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AuthSession
+{
+    id: String,
+    user_agent: Option<String>,
+    ip: Option<String>,
+    created_at: u64,
+    last_seen: u64,
+    current: bool,
+}
+
+async fn fetch_auth_sessions(
+    session_store: Extension<session::Store>,
+    user_id: session::extractor::UserId,
+    session::extractor::SessionId(current_id): session::extractor::SessionId,
+) -> Result<axum::Json<Vec<AuthSession>>>
+{
+    let user_id = match user_id {
+        session::extractor::UserId::Found(user_id, session::extractor::AuthMethod::Session) => {
+            user_id
+        }
+        _ => return Err(Error::MustBeAuthenticated),
+    };
+
+    let mut sessions = Vec::new();
+    for session in session_store.user_sessions(user_id).await? {
+        sessions.push(AuthSession {
+            id: String::from(session.id()),
+            user_agent: session.get::<Option<String>>("user_agent").await.flatten(),
+            ip: session.get("ip").await,
+            created_at: session::unix_timestamp(session.created_at()),
+            last_seen: session::unix_timestamp(session.last_active()),
+            current: current_id.as_deref() == Some(session.id()),
+        });
+    }
+    sessions.sort_unstable_by(|a, b| b.last_seen.cmp(&a.last_seen));
+
+    Ok(axum::Json(sessions))
+}
+
+async fn revoke_auth_session(
+    session_store: Extension<session::Store>,
+    user_id: session::extractor::UserId,
+    Path(id): Path<String>,
+) -> Result<http::StatusCode>
+{
+    let user_id = match user_id {
+        session::extractor::UserId::Found(user_id, session::extractor::AuthMethod::Session) => {
+            user_id
+        }
+        _ => return Err(Error::MustBeAuthenticated),
+    };
+
+    // Only sessions found through the user's own index may be revoked, so
+    // that knowing another user's session ID is not enough to revoke it
+    let is_own_session = session_store
+        .user_sessions(user_id)
+        .await?
+        .iter()
+        .any(|session| session.id() == id);
+    if !is_own_session {
+        return Err(Error::SessionNotFound { id });
+    }
+
+    session_store.destroy_session(&id).await?;
+
+    Ok(http::StatusCode::NO_CONTENT)
+}
+
+async fn delete_other_auth_sessions(
+    session_store: Extension<session::Store>,
+    user_id: session::extractor::UserId,
+    session::extractor::SessionId(current_id): session::extractor::SessionId,
+) -> Result<http::StatusCode>
+{
+    let user_id = match user_id {
+        session::extractor::UserId::Found(user_id, session::extractor::AuthMethod::Session) => {
+            user_id
+        }
+        _ => return Err(Error::MustBeAuthenticated),
+    };
+
+    session_store
+        .destroy_user_sessions(user_id, current_id.as_deref())
+        .await?;
+
+    Ok(http::StatusCode::NO_CONTENT)
+}
+
 type Result<T> = ::core::result::Result<T, Error>;
 
 #[derive(Debug, Error)]
@@ -91,15 +607,23 @@ enum Error
     Password(#[from] password::Error),
     #[error("{0}")]
     Session(#[from] session::Error),
-    #[error("no user with username {username} was found")]
-    UserNotFound
-    {
-        username: String
-    },
-    #[error("the provided password is wrong")]
-    WrongPassword,
+    #[error("{0}")]
+    RateLimit(#[from] rate_limit::Error),
+    #[error("{0}")]
+    Webauthn(#[from] WebauthnError),
+    #[error("invalid username or password")]
+    InvalidCredentials,
+    #[error("no login is waiting for a second factor")]
+    NoPendingLogin,
+    #[error("invalid second factor")]
+    InvalidSecondFactor,
     #[error("must be authenticated")]
     MustBeAuthenticated,
+    #[error("no session with id {id} was found")]
+    SessionNotFound
+    {
+        id: String
+    },
 }
 
 impl response::IntoResponse for Error
@@ -107,11 +631,76 @@ impl response::IntoResponse for Error
     fn into_response(self) -> response::Response
     {
         match self {
-            Error::UserNotFound { .. } => http::StatusCode::NOT_FOUND,
-            Error::WrongPassword => http::StatusCode::UNPROCESSABLE_ENTITY,
+            Error::RateLimit(err) => return err.into_response(),
+            // The body must not tell apart an unknown user from a wrong
+            // password either, so it is built from nothing but the variant
+            Error::InvalidCredentials => {
+                let payload = json!({
+                    "message": Error::InvalidCredentials.to_string(),
+                    "code": api_error::Code::INVALID_CREDENTIALS,
+                });
+
+                return (http::StatusCode::UNAUTHORIZED, axum::Json(payload)).into_response();
+            }
+            Error::InvalidSecondFactor => {
+                let payload = json!({
+                    "message": Error::InvalidSecondFactor.to_string(),
+                    "code": api_error::Code::INVALID_SECOND_FACTOR,
+                });
+
+                return (http::StatusCode::UNAUTHORIZED, axum::Json(payload)).into_response();
+            }
+            Error::NoPendingLogin => http::StatusCode::UNAUTHORIZED,
+            Error::SessionNotFound { .. } => http::StatusCode::NOT_FOUND,
             Error::MustBeAuthenticated => http::StatusCode::UNAUTHORIZED,
             _ => http::StatusCode::INTERNAL_SERVER_ERROR,
         }
         .into_response()
     }
 }
+
+#[cfg(test)]
+mod tests
+{
+    use axum::response::IntoResponse;
+
+    use super::*;
+
+    async fn into_parts(err: Error) -> (http::StatusCode, hyper::body::Bytes)
+    {
+        let response = err.into_response();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+        (status, body)
+    }
+
+    #[tokio::test]
+    async fn unknown_user_and_wrong_password_are_indistinguishable()
+    {
+        // The cheapest parameters Argon2 allows, as the cost is not under test
+        let params = argon2::Params::new(8, 1, 1, None).unwrap();
+        let password_hasher = password::Hasher::new(params, Default::default());
+        let user = User {
+            user_id: Uuid::new_v4(),
+            password: password_hasher
+                .hash(String::from("correct horse battery staple"))
+                .await
+                .unwrap(),
+        };
+
+        let wrong_password = authenticate(&password_hasher, Some(user), String::from("hunter2"))
+            .await
+            .err()
+            .unwrap();
+        let unknown_user = authenticate(&password_hasher, None, String::from("hunter2"))
+            .await
+            .err()
+            .unwrap();
+
+        let wrong_password = into_parts(wrong_password).await;
+        let unknown_user = into_parts(unknown_user).await;
+        assert_eq!(wrong_password.0, http::StatusCode::UNAUTHORIZED);
+        assert_eq!(wrong_password, unknown_user);
+    }
+}
@@ -0,0 +1,267 @@
+use std::{sync::Arc, time::Duration};
+
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::{http::tokens, mail};
+
+// Addresses cannot be any longer than this, as per RFC 5321
+const MAX_ADDRESS_LEN: usize = 254;
+
+/// What an emailed token lets its bearer do, once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(in crate::http) enum Purpose
+{
+    VerifyEmail,
+    ResetPassword,
+}
+
+impl Purpose
+{
+    fn name(self) -> &'static str
+    {
+        match self {
+            Purpose::VerifyEmail => "verify_email",
+            Purpose::ResetPassword => "reset_password",
+        }
+    }
+
+    fn prefix(self) -> &'static str
+    {
+        match self {
+            Purpose::VerifyEmail => "bbevt_",
+            Purpose::ResetPassword => "bbprt_",
+        }
+    }
+
+    /// Reset tokens hand over the account, so they get much less time to
+    /// leak than verification ones
+    fn lifetime(self) -> Duration
+    {
+        match self {
+            Purpose::VerifyEmail => Duration::from_secs(24 * 60 * 60),
+            Purpose::ResetPassword => Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// Checks that the address is a bare `local@domain` one, handing it back
+/// trimmed
+pub(in crate::http) fn parse_address(address: &str) -> Option<String>
+{
+    let address = address.trim();
+    if address.len() > MAX_ADDRESS_LEN {
+        return None;
+    }
+
+    address
+        .parse::<lettre::Address>()
+        .ok()
+        .map(|address| address.to_string())
+}
+
+/// Issues a token for the purpose, replacing any the user was sent for it
+/// before
+pub(in crate::http) async fn issue_token(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    email: &str,
+    purpose: Purpose,
+) -> sqlx::Result<String>
+{
+    let token = tokens::generate_token(purpose.prefix());
+
+    let _pg_query_res = sqlx::query!(
+        r#"DELETE FROM "email_tokens" WHERE user_id = $1 AND purpose = $2"#,
+        user_id,
+        purpose.name()
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    let _pg_query_res = sqlx::query!(
+        r#"
+            INSERT INTO "email_tokens"(token_hash, user_id, purpose, email, expires_at)
+            VALUES ($1, $2, $3, $4, now() + make_interval(secs => $5))
+        "#,
+        tokens::hash_token(&token),
+        user_id,
+        purpose.name(),
+        email,
+        purpose.lifetime().as_secs_f64()
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(token)
+}
+
+/// Resolves a live token to the user it was issued to and the address it was
+/// sent to, leaving it in place
+pub(in crate::http) async fn find_token(
+    conn: &mut PgConnection,
+    token: &str,
+    purpose: Purpose,
+) -> sqlx::Result<Option<(Uuid, String)>>
+{
+    let token = sqlx::query!(
+        r#"
+            SELECT user_id, email
+            FROM "email_tokens"
+            WHERE token_hash = $1 AND purpose = $2 AND expires_at > now()
+        "#,
+        tokens::hash_token(token),
+        purpose.name()
+    )
+    .fetch_optional(conn)
+    .await?;
+
+    Ok(token.map(|token| (token.user_id, token.email)))
+}
+
+/// Like [`find_token`], except that the token is used up, along with any
+/// other the user holds for the same purpose
+pub(in crate::http) async fn consume_token(
+    conn: &mut PgConnection,
+    token: &str,
+    purpose: Purpose,
+) -> sqlx::Result<Option<(Uuid, String)>>
+{
+    let token = sqlx::query!(
+        r#"
+            DELETE FROM "email_tokens"
+            WHERE token_hash = $1 AND purpose = $2
+            RETURNING user_id, email, expires_at > now() as "is_live!"
+        "#,
+        tokens::hash_token(token),
+        purpose.name()
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let token = match token {
+        Some(token) if token.is_live => token,
+        _ => return Ok(None),
+    };
+
+    let _pg_query_res = sqlx::query!(
+        r#"DELETE FROM "email_tokens" WHERE user_id = $1 AND purpose = $2"#,
+        token.user_id,
+        purpose.name()
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(Some((token.user_id, token.email)))
+}
+
+/// Takes the address away from every other account which claimed it without
+/// verifying it, now that it is known to be the user's, along with the links
+/// sent to it for them
+pub(in crate::http) async fn release_claims(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    email: &str,
+) -> sqlx::Result<()>
+{
+    let _pg_query_res = sqlx::query!(
+        r#"
+            UPDATE "users"
+            SET email = NULL
+            WHERE lower(email) = lower($2) AND email_verified_at IS NULL AND user_id <> $1
+        "#,
+        user_id,
+        email
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    let _pg_query_res = sqlx::query!(
+        r#"DELETE FROM "email_tokens" WHERE lower(email) = lower($2) AND user_id <> $1"#,
+        user_id,
+        email
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Writes the emails carrying tokens, with links to the frontend pages which
+/// hand the tokens back to the API
+#[derive(Debug, Clone)]
+pub(in crate::http) struct Outbox
+{
+    mailer: mail::Shared,
+    link_base_url: Arc<str>,
+}
+
+impl Outbox
+{
+    pub(in crate::http) fn new(mailer: mail::Shared, link_base_url: &str) -> Self
+    {
+        Outbox {
+            mailer,
+            link_base_url: Arc::from(link_base_url),
+        }
+    }
+
+    pub(in crate::http) fn send_verification(&self, to: String, token: &str)
+    {
+        self.send(mail::Email {
+            to,
+            subject: String::from("Verify your email address"),
+            body: format!(
+                "Follow this link within a day to verify your email address:\n\n{}/verify-email?\
+                 token={}\n\nIf you did not sign up, you may ignore this email.\n",
+                self.link_base_url, token
+            ),
+        });
+    }
+
+    /// Names the account, as an address no one has verified yet may be
+    /// claimed by more than one
+    pub(in crate::http) fn send_password_reset(&self, to: String, username: &str, token: &str)
+    {
+        self.send(mail::Email {
+            to,
+            subject: String::from("Reset your password"),
+            body: format!(
+                "Follow this link within an hour to choose a new password for @{}:\n\n{}/\
+                 reset-password?token={}\n\nIf you did not ask for this, you may ignore this \
+                 email and keep your current password.\n",
+                username, self.link_base_url, token
+            ),
+        });
+    }
+
+    /// Tells the owner of an address that someone tried to sign up with it,
+    /// in place of telling whoever tried
+    pub(in crate::http) fn send_account_exists(&self, to: String, username: &str)
+    {
+        self.send(mail::Email {
+            to,
+            subject: String::from("You already have an account"),
+            body: format!(
+                "Someone tried to sign up with this email address, which already belongs to \
+                 @{}. If it was you, you may log in, or reset your password here:\n\n{}/\
+                 reset-password\n\nIf it was not, you may ignore this email.\n",
+                username, self.link_base_url
+            ),
+        });
+    }
+
+    /// Sends the email in the background, as whether and how fast it goes
+    /// through must not show in the response, or password reset requests
+    /// would tell which addresses have an account
+    fn send(&self, email: mail::Email)
+    {
+        let mailer = self.mailer.clone();
+
+        let _handle = tokio::spawn(async move {
+            if let Err(err) = mailer.send(email).await {
+                tracing::error!("failed to send email: {}", err);
+            }
+        });
+    }
+}
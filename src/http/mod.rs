@@ -1,34 +1,166 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, path::Path, sync::Arc, time::Duration};
 
 use axum::{Extension, Router};
 use sqlx::PgPool;
-use tokio::signal;
+use tokio::{signal, sync::Notify, time};
 
 use thiserror::Error;
+use webauthn_rs::{prelude::WebauthnError, Webauthn, WebauthnBuilder};
+
+use crate::{config::Config, mail, password};
 
 mod json;
+pub mod rate_limit;
 pub mod session;
 
 mod auth;
+mod email;
+mod oauth;
+mod passkeys;
+mod posts;
+mod tokens;
+mod totp;
 mod users;
 
-fn app(pg_pool: PgPool, session_store: session::Store) -> Router
+// Signing up and changing passwords are both expensive, yet neither is
+// something anyone needs to do often
+const USERS_QUOTA: rate_limit::Quota = rate_limit::Quota::new(30, Duration::from_secs(60 * 60));
+
+// Every email sent costs something and lands in someone's inbox, which may
+// well not be the sender's
+const MAIL_QUOTA: rate_limit::Quota = rate_limit::Quota::new(10, Duration::from_secs(60 * 60));
+const MAIL_PER_ADDRESS_QUOTA: rate_limit::Quota =
+    rate_limit::Quota::new(5, Duration::from_secs(60 * 60));
+
+fn app(
+    config: &Config,
+    pg_pool: PgPool,
+    session_store: session::Store,
+    rate_limit_store: rate_limit::Store,
+    mailer: mail::Shared,
+    webauthn: Webauthn,
+) -> Router
 {
+    let session_expiry =
+        session::Expiry::new(config.session_lifetime(), config.session_idle_timeout());
+    let password_hasher = password::Hasher::new(
+        config.argon2_params().clone(),
+        config.password_peppers().clone(),
+    );
+    let password_policy = password::Policy::new(
+        config.password_min_length(),
+        config.password_max_length(),
+        config.password_min_strength(),
+        config.password_breached_list().map(Path::to_path_buf),
+    );
+    let session_cookie_builder = session::CookieBuilder::new(
+        config.session_cookie_secure(),
+        config.session_cookie_same_site(),
+        String::from(config.session_cookie_path()),
+        config.session_cookie_domain().map(String::from),
+    );
+    let outbox = email::Outbox::new(mailer, config.mail_link_base_url());
+    let login_limiter = rate_limit::LoginLimiter::new(
+        rate_limit_store.clone(),
+        rate_limit::Quota::new(
+            config.login_max_attempts_per_username(),
+            config.login_attempt_window(),
+        ),
+        rate_limit::Quota::new(
+            config.login_max_attempts_per_ip(),
+            config.login_attempt_window(),
+        ),
+        config.login_lockout(),
+        config.login_max_lockout(),
+    );
+    let address_limiter =
+        rate_limit::AddressLimiter::new(rate_limit_store.clone(), MAIL_PER_ADDRESS_QUOTA);
+
     Router::new()
         .merge(auth::router())
-        .merge(users::router())
+        .merge(oauth::router())
+        .merge(passkeys::router())
+        .merge(posts::router())
+        .merge(totp::router())
+        .merge(tokens::router())
+        .merge(users::router(
+            rate_limit::RateLimitLayer::new(
+                rate_limit_store.clone(),
+                config.trusted_proxies().clone(),
+                "users",
+                USERS_QUOTA,
+            ),
+            rate_limit::RateLimitLayer::new(
+                rate_limit_store,
+                config.trusted_proxies().clone(),
+                "mail",
+                MAIL_QUOTA,
+            ),
+        ))
         .layer(Extension(pg_pool))
         .layer(Extension(session_store))
+        .layer(Extension(session_expiry))
+        .layer(Extension(session_cookie_builder))
+        .layer(Extension(password_hasher))
+        .layer(Extension(password_policy))
+        .layer(Extension(login_limiter))
+        .layer(Extension(address_limiter))
+        .layer(Extension(config.trusted_proxies().clone()))
+        .layer(Extension(outbox))
+        .layer(Extension(Arc::new(webauthn)))
 }
 
-pub async fn serve(port: u16, pg_pool: PgPool, session_store: session::Store) -> Result<()>
+pub async fn serve(
+    config: &Config,
+    pg_pool: PgPool,
+    session_store: session::Store,
+    rate_limit_store: rate_limit::Store,
+    mailer: mail::Shared,
+) -> Result<()>
 {
-    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let addr = SocketAddr::from((config.host(), config.port()));
+
+    // Passkeys are bound to the relying party ID, which must be the domain of
+    // the origin the frontend is served from or one of its parents
+    let webauthn = WebauthnBuilder::new(config.webauthn_rp_id(), config.webauthn_rp_origin())?
+        .rp_name(config.webauthn_rp_name())
+        .build()?;
 
-    axum::Server::bind(&addr)
-        .serve(app(pg_pool, session_store).into_make_service())
-        .with_graceful_shutdown(shutdown_signal())
-        .await?;
+    users::fill_look_alike_forms(&pg_pool).await?;
+
+    let shutdown_started = Arc::new(Notify::new());
+    let server = axum::Server::bind(&addr)
+        .serve(
+            app(
+                config,
+                pg_pool,
+                session_store,
+                rate_limit_store,
+                mailer,
+                webauthn,
+            )
+            .into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown({
+            let shutdown_started = shutdown_started.clone();
+            async move {
+                shutdown_signal().await;
+                shutdown_started.notify_one();
+            }
+        });
+
+    // In-flight requests get a grace period to complete once shutdown has
+    // started, after which the server is dropped along with them
+    let grace_period = config.shutdown_grace_period();
+    tokio::select! {
+        res = server => res?,
+        _ = async {
+            shutdown_started.notified().await;
+            time::sleep(grace_period).await;
+        } => {
+            tracing::warn!("grace period of {:?} elapsed, forcing shutdown", grace_period);
+        }
+    }
 
     Ok(())
 }
@@ -66,6 +198,10 @@ pub enum Error
 {
     #[error("{0}")]
     Hyper(#[from] hyper::Error),
+    #[error("invalid `webauthn` relying party: {0}")]
+    Webauthn(#[from] WebauthnError),
+    #[error("{0}")]
+    Sqlx(#[from] sqlx::Error),
 }
 
 mod api_error
@@ -87,6 +223,32 @@ mod api_error
     // 110 - JSON Data Error
     // 120 - JSON Missing Content Type
     // 199 - JSON Unknown Error
+    // 200 - Password Too Short
+    // 210 - Password Too Long
+    // 220 - Password Too Weak
+    // 230 - Password Breached
+    // 300 - Username Too Short
+    // 310 - Username Too Long
+    // 320 - Username Invalid Characters
+    // 330 - Username Mixed Scripts
+    // 340 - Username Reserved
+    // 400 - Invalid Credentials
+    // 410 - Invalid Second Factor
+    // 500 - Token Invalid Name
+    // 510 - Token No Scopes
+    // 520 - Token Invalid Expiry
+    // 600 - OAuth Invalid Client Name
+    // 610 - OAuth Invalid Redirect URI
+    // 700 - Email Invalid
+    // 710 - Email Taken
+    // 720 - Email Token Invalid
+    // 800 - Passkey Invalid Name
+    // 810 - Passkey Invalid Credential
+    // 900 - Post Empty Body
+    // 910 - Post Body Too Long
+    // 920 - Post Invalid Reply Target
+    // 930 - Post Invalid Cursor
+    // 940 - Post Cannot Repost Own
     impl Code
     {
         #![allow(unsafe_code)]
@@ -95,5 +257,39 @@ mod api_error
         code!(JSON_DATA_ERROR, 110);
         code!(JSON_MISSING_CONTENT_TYPE, 120);
         code!(JSON_UNKNOWN_ERROR, 199);
+
+        code!(PASSWORD_TOO_SHORT, 200);
+        code!(PASSWORD_TOO_LONG, 210);
+        code!(PASSWORD_TOO_WEAK, 220);
+        code!(PASSWORD_BREACHED, 230);
+
+        code!(USERNAME_TOO_SHORT, 300);
+        code!(USERNAME_TOO_LONG, 310);
+        code!(USERNAME_INVALID_CHARACTERS, 320);
+        code!(USERNAME_MIXED_SCRIPTS, 330);
+        code!(USERNAME_RESERVED, 340);
+
+        code!(INVALID_CREDENTIALS, 400);
+        code!(INVALID_SECOND_FACTOR, 410);
+
+        code!(TOKEN_INVALID_NAME, 500);
+        code!(TOKEN_NO_SCOPES, 510);
+        code!(TOKEN_INVALID_EXPIRY, 520);
+
+        code!(OAUTH_INVALID_CLIENT_NAME, 600);
+        code!(OAUTH_INVALID_REDIRECT_URI, 610);
+
+        code!(EMAIL_INVALID, 700);
+        code!(EMAIL_TAKEN, 710);
+        code!(EMAIL_TOKEN_INVALID, 720);
+
+        code!(PASSKEY_INVALID_NAME, 800);
+        code!(PASSKEY_INVALID_CREDENTIAL, 810);
+
+        code!(POST_EMPTY_BODY, 900);
+        code!(POST_BODY_TOO_LONG, 910);
+        code!(POST_INVALID_REPLY_TARGET, 920);
+        code!(POST_INVALID_CURSOR, 930);
+        code!(POST_CANNOT_REPOST_OWN, 940);
     }
 }
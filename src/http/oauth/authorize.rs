@@ -0,0 +1,200 @@
+use axum::{extract::Query, Extension};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+
+use crate::http::{json, oauth, session, tokens};
+
+/// The parameters of an authorization request, as defined by RFC 6749 and
+/// RFC 7636. PKCE is mandatory, and only with the S256 method
+#[derive(Deserialize)]
+pub(super) struct AuthorizationRequest
+{
+    response_type: String,
+    client_id: Uuid,
+    redirect_uri: String,
+    scope: String,
+    state: Option<String>,
+    code_challenge: String,
+    code_challenge_method: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct ConsentClient
+{
+    id: Uuid,
+    name: String,
+}
+
+/// What the user is asked to consent to
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct Consent
+{
+    client: ConsentClient,
+    scopes: Vec<tokens::Scope>,
+    redirect_uri: String,
+}
+
+/// Validates the authorization request, describing it for the consent screen
+/// shown to the logged-in user
+pub(super) async fn fetch_authorization(
+    pg_pool: Extension<PgPool>,
+    user_id: session::extractor::UserId,
+    Query(req): Query<AuthorizationRequest>,
+) -> oauth::Result<axum::Json<Consent>>
+{
+    match user_id {
+        session::extractor::UserId::Found(_user_id, session::extractor::AuthMethod::Session) => (),
+        _ => return Err(oauth::Error::MustBeAuthenticated),
+    }
+
+    let (client_name, scopes) = validate(&pg_pool, &req).await?;
+
+    Ok(axum::Json(Consent {
+        client: ConsentClient {
+            id: req.client_id,
+            name: client_name,
+        },
+        scopes,
+        redirect_uri: req.redirect_uri,
+    }))
+}
+
+#[derive(Deserialize)]
+pub(super) struct CreateAuthorization
+{
+    #[serde(flatten)]
+    request: AuthorizationRequest,
+    approve: bool,
+}
+
+/// Where the consent screen sends the user once they have answered it
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct AuthorizationRedirect
+{
+    redirect_uri: String,
+}
+
+/// Records the user's answer on the consent screen, handing back the client's
+/// redirect URI with either an authorization code or an `access_denied` error
+///
+/// This only takes JSON, which cross-site forms cannot send, so the consent
+/// cannot be forged from another site. Since such a request is sent by script,
+/// it is answered with the URI to navigate to rather than a redirect, which
+/// the script would follow itself instead of the browser
+pub(super) async fn create_authorization(
+    pg_pool: Extension<PgPool>,
+    user_id: session::extractor::UserId,
+    json::extractor::Json(req): json::extractor::Json<CreateAuthorization>,
+) -> oauth::Result<axum::Json<AuthorizationRedirect>>
+{
+    let user_id = match user_id {
+        session::extractor::UserId::Found(user_id, session::extractor::AuthMethod::Session) => {
+            user_id
+        }
+        _ => return Err(oauth::Error::MustBeAuthenticated),
+    };
+    let CreateAuthorization { request, approve } = req;
+
+    let (_client_name, scopes) = validate(&pg_pool, &request).await?;
+
+    let mut params = Vec::new();
+    if approve {
+        let code = tokens::generate_token(oauth::AUTHORIZATION_CODE_PREFIX);
+
+        let _pg_query_res = sqlx::query!(
+            r#"
+                INSERT INTO "oauth_authorization_codes"(
+                    code_hash, client_id, user_id, redirect_uri, scopes, code_challenge, expires_at
+                )
+                VALUES ($1, $2, $3, $4, $5, $6, now() + make_interval(secs => $7))
+            "#,
+            tokens::hash_token(&code),
+            request.client_id,
+            user_id,
+            request.redirect_uri,
+            &oauth::scope_names(&scopes)[..],
+            request.code_challenge,
+            oauth::AUTHORIZATION_CODE_LIFETIME.as_secs_f64()
+        )
+        .execute(&*pg_pool)
+        .await?;
+
+        params.push(("code", code));
+    } else {
+        params.push(("error", String::from("access_denied")));
+    }
+    if let Some(state) = request.state {
+        params.push(("state", state));
+    }
+
+    Ok(axum::Json(AuthorizationRedirect {
+        redirect_uri: with_query(&request.redirect_uri, &params),
+    }))
+}
+
+/// Checks the request against the registered client, handing back the
+/// client's name and the requested scopes
+async fn validate(
+    pg_pool: &PgPool,
+    req: &AuthorizationRequest,
+) -> oauth::Result<(String, Vec<tokens::Scope>)>
+{
+    let client = sqlx::query!(
+        r#"select name, redirect_uris from "oauth_clients" where client_id = $1"#,
+        req.client_id
+    )
+    .fetch_optional(pg_pool)
+    .await?
+    .ok_or(oauth::Error::InvalidRequest("unknown client_id"))?;
+
+    // The redirect URI must match a registered one exactly, or codes could be
+    // sent anywhere on the client's host
+    if !client.redirect_uris.contains(&req.redirect_uri) {
+        return Err(oauth::Error::InvalidRequest(
+            "redirect_uri is not registered for the client",
+        ));
+    }
+    if req.response_type != "code" {
+        return Err(oauth::Error::UnsupportedResponseType);
+    }
+    if req.code_challenge_method != "S256" {
+        return Err(oauth::Error::InvalidRequest(
+            "code_challenge_method must be S256",
+        ));
+    }
+    // A base64url-encoded SHA-256 digest, as defined by RFC 7636
+    let is_valid_challenge = req.code_challenge.len() == 43
+        && req
+            .code_challenge
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_');
+    if !is_valid_challenge {
+        return Err(oauth::Error::InvalidRequest("invalid code_challenge"));
+    }
+
+    let scopes = oauth::parse_scope(&req.scope)?;
+
+    Ok((client.name, scopes))
+}
+
+fn with_query(uri: &str, params: &[(&str, String)]) -> String
+{
+    let mut uri = String::from(uri);
+    let mut separator = if uri.contains('?') { '&' } else { '?' };
+
+    for (key, value) in params {
+        uri.push(separator);
+        uri.push_str(key);
+        uri.push('=');
+        uri.extend(utf8_percent_encode(value, NON_ALPHANUMERIC));
+        separator = '&';
+    }
+
+    uri
+}
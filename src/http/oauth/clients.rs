@@ -0,0 +1,185 @@
+use axum::{extract::Path, http, Extension};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use serde::{Deserialize, Serialize};
+
+use crate::http::{json, oauth, session, tokens};
+
+pub(super) const MAX_NAME_LEN: usize = 100;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct Client
+{
+    id: Uuid,
+    name: String,
+    redirect_uris: Vec<String>,
+    confidential: bool,
+    created_at: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct CreatedClient
+{
+    #[serde(flatten)]
+    details: Client,
+    /// Only ever shown here, as nothing but its hash is kept
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct CreateClient
+{
+    name: String,
+    redirect_uris: Vec<String>,
+    /// Whether the client runs on a server, where it can keep a secret
+    confidential: bool,
+}
+
+pub(super) async fn create_client(
+    pg_pool: Extension<PgPool>,
+    user_id: session::extractor::UserId,
+    json::extractor::Json(req): json::extractor::Json<CreateClient>,
+) -> oauth::Result<(http::StatusCode, axum::Json<CreatedClient>)>
+{
+    let user_id = match user_id {
+        session::extractor::UserId::Found(user_id, session::extractor::AuthMethod::Session) => {
+            user_id
+        }
+        _ => return Err(oauth::Error::MustBeAuthenticated),
+    };
+    let CreateClient {
+        name,
+        redirect_uris,
+        confidential,
+    } = req;
+
+    let name = String::from(name.trim());
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+        return Err(oauth::Error::InvalidClientName);
+    }
+    if redirect_uris.is_empty() || !redirect_uris.iter().all(|uri| is_valid_redirect_uri(uri)) {
+        return Err(oauth::Error::InvalidRedirectUri);
+    }
+
+    let secret = confidential.then(|| tokens::generate_token(oauth::CLIENT_SECRET_PREFIX));
+
+    let created = sqlx::query!(
+        r#"
+            INSERT INTO "oauth_clients"(owner_id, name, secret_hash, redirect_uris)
+            VALUES ($1, $2, $3, $4)
+            RETURNING client_id, extract(epoch from created_at)::bigint as "created_at!"
+        "#,
+        user_id,
+        name,
+        secret.as_deref().map(tokens::hash_token),
+        &redirect_uris[..]
+    )
+    .fetch_one(&*pg_pool)
+    .await?;
+
+    Ok((
+        http::StatusCode::CREATED,
+        axum::Json(CreatedClient {
+            details: Client {
+                id: created.client_id,
+                name,
+                redirect_uris,
+                confidential,
+                created_at: created.created_at,
+            },
+            secret,
+        }),
+    ))
+}
+
+/// The clients the user registered, not the ones they granted access to
+pub(super) async fn fetch_clients(
+    pg_pool: Extension<PgPool>,
+    user_id: session::extractor::UserId,
+) -> oauth::Result<axum::Json<Vec<Client>>>
+{
+    let user_id = match user_id {
+        session::extractor::UserId::Found(user_id, session::extractor::AuthMethod::Session) => {
+            user_id
+        }
+        _ => return Err(oauth::Error::MustBeAuthenticated),
+    };
+
+    let clients = sqlx::query!(
+        r#"
+            SELECT
+                client_id,
+                name,
+                redirect_uris,
+                secret_hash IS NOT NULL as "confidential!",
+                extract(epoch from created_at)::bigint as "created_at!"
+            FROM "oauth_clients"
+            WHERE owner_id = $1
+            ORDER BY created_at DESC
+        "#,
+        user_id
+    )
+    .fetch_all(&*pg_pool)
+    .await?
+    .into_iter()
+    .map(|client| Client {
+        id: client.client_id,
+        name: client.name,
+        redirect_uris: client.redirect_uris,
+        confidential: client.confidential,
+        created_at: client.created_at,
+    })
+    .collect();
+
+    Ok(axum::Json(clients))
+}
+
+/// Deleting a client revokes every grant made to it along the way
+pub(super) async fn delete_client(
+    pg_pool: Extension<PgPool>,
+    user_id: session::extractor::UserId,
+    Path(client_id): Path<Uuid>,
+) -> oauth::Result<http::StatusCode>
+{
+    let user_id = match user_id {
+        session::extractor::UserId::Found(user_id, session::extractor::AuthMethod::Session) => {
+            user_id
+        }
+        _ => return Err(oauth::Error::MustBeAuthenticated),
+    };
+
+    let pg_query_res = sqlx::query!(
+        r#"DELETE FROM "oauth_clients" WHERE client_id = $1 AND owner_id = $2"#,
+        client_id,
+        user_id
+    )
+    .execute(&*pg_pool)
+    .await?;
+    if pg_query_res.rows_affected() == 0 {
+        return Err(oauth::Error::ClientNotFound { id: client_id });
+    }
+
+    Ok(http::StatusCode::NO_CONTENT)
+}
+
+/// Authorization codes are handed over through the redirect, so it has to go
+/// somewhere the code cannot be sniffed on the way: over TLS, or to the
+/// user's own machine for native apps
+fn is_valid_redirect_uri(uri: &str) -> bool
+{
+    let uri = match uri.parse::<http::Uri>() {
+        Ok(uri) => uri,
+        Err(_) => return false,
+    };
+
+    match (uri.scheme_str(), uri.host()) {
+        (Some("https"), Some(_)) => true,
+        (Some("http"), Some(host)) => matches!(host, "localhost" | "127.0.0.1" | "[::1]"),
+        _ => false,
+    }
+}
@@ -0,0 +1,299 @@
+use std::time::Duration;
+
+use axum::{
+    http, response,
+    routing::{delete, get, post},
+    Router,
+};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use percent_encoding::percent_decode_str;
+use serde_json::json;
+use thiserror::Error;
+
+use crate::http::{api_error, tokens};
+
+mod authorize;
+mod clients;
+mod token;
+
+/// Tells OAuth access tokens apart from other bearer tokens
+pub(in crate::http) const ACCESS_TOKEN_PREFIX: &str = "bboat_";
+const REFRESH_TOKEN_PREFIX: &str = "bbort_";
+const AUTHORIZATION_CODE_PREFIX: &str = "bboac_";
+const CLIENT_SECRET_PREFIX: &str = "bbocs_";
+
+const ACCESS_TOKEN_LIFETIME: Duration = Duration::from_secs(60 * 60);
+const REFRESH_TOKEN_LIFETIME: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+const AUTHORIZATION_CODE_LIFETIME: Duration = Duration::from_secs(10 * 60);
+
+pub(in crate::http) fn router() -> Router
+{
+    Router::new()
+        .route(
+            "/oauth/clients",
+            get(clients::fetch_clients).post(clients::create_client),
+        )
+        .route("/oauth/clients/:id", delete(clients::delete_client))
+        .route(
+            "/oauth/authorize",
+            get(authorize::fetch_authorization).post(authorize::create_authorization),
+        )
+        .route("/oauth/token", post(token::create_token))
+        .route("/oauth/revoke", post(token::revoke_token))
+        .route("/oauth/introspect", post(token::introspect_token))
+}
+
+/// Resolves an access token to the user who granted it and its scopes.
+/// Expired and unknown tokens resolve to nothing
+pub(in crate::http) async fn authenticate(
+    pg_pool: &PgPool,
+    access_token: &str,
+) -> sqlx::Result<Option<(Uuid, Vec<tokens::Scope>)>>
+{
+    let token = sqlx::query!(
+        r#"
+            SELECT user_id, scopes
+            FROM "oauth_tokens"
+            WHERE access_token_hash = $1 AND access_expires_at > now()
+        "#,
+        tokens::hash_token(access_token)
+    )
+    .fetch_optional(pg_pool)
+    .await?;
+
+    Ok(token.map(|token| (token.user_id, tokens::parse_scopes(&token.scopes))))
+}
+
+/// Revokes every grant the user gave, along with the codes not yet exchanged
+/// for one, so that each app has to be authorized again
+pub(in crate::http) async fn revoke_user_grants(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+) -> sqlx::Result<()>
+{
+    let _pg_query_res = sqlx::query!(
+        r#"DELETE FROM "oauth_authorization_codes" WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut *conn)
+    .await?;
+    let _pg_query_res = sqlx::query!(r#"DELETE FROM "oauth_tokens" WHERE user_id = $1"#, user_id)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Authenticates a client calling the token endpoints, handing back its ID.
+/// The credentials are sent either through HTTP Basic authentication or in the
+/// request body, but not both. Public clients have no secret to send
+async fn authenticate_client(
+    pg_pool: &PgPool,
+    headers: &http::HeaderMap,
+    client_id: Option<Uuid>,
+    client_secret: Option<String>,
+) -> Result<Uuid>
+{
+    let (client_id, client_secret) = match basic_credentials(headers)? {
+        Some(_) if client_secret.is_some() => {
+            return Err(Error::InvalidRequest(
+                "client credentials must be sent in one way only",
+            ))
+        }
+        Some((basic_id, _)) if client_id.map_or(false, |client_id| client_id != basic_id) => {
+            return Err(Error::InvalidRequest(
+                "client_id does not match the Authorization header",
+            ))
+        }
+        Some(credentials) => credentials,
+        None => (
+            client_id.ok_or(Error::InvalidRequest("missing client_id"))?,
+            client_secret,
+        ),
+    };
+
+    let client = sqlx::query!(
+        r#"select secret_hash from "oauth_clients" where client_id = $1"#,
+        client_id
+    )
+    .fetch_optional(pg_pool)
+    .await?
+    .ok_or(Error::InvalidClient)?;
+
+    match (client.secret_hash, client_secret) {
+        (Some(secret_hash), Some(client_secret))
+            if secret_hash == tokens::hash_token(&client_secret) =>
+        {
+            Ok(client_id)
+        }
+        (None, None) => Ok(client_id),
+        _ => Err(Error::InvalidClient),
+    }
+}
+
+/// The client ID and secret of an `Authorization: Basic` header, which RFC
+/// 6749 has form-encoded before they are joined and base64-encoded. An empty
+/// secret is taken as none, as some libraries send one for public clients
+fn basic_credentials(headers: &http::HeaderMap) -> Result<Option<(Uuid, Option<String>)>>
+{
+    let header = match headers.get(http::header::AUTHORIZATION) {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+
+    let credentials = header
+        .to_str()
+        .ok()
+        .and_then(|header| header.split_once(' '))
+        .filter(|(scheme, _credentials)| scheme.eq_ignore_ascii_case("basic"))
+        .and_then(|(_scheme, credentials)| base64::decode(credentials.trim()).ok())
+        .and_then(|credentials| String::from_utf8(credentials).ok())
+        .ok_or(Error::InvalidClient)?;
+    let (client_id, client_secret) = credentials.split_once(':').ok_or(Error::InvalidClient)?;
+
+    let client_id = form_decode(client_id)?
+        .parse()
+        .map_err(|_| Error::InvalidClient)?;
+    let client_secret = Some(form_decode(client_secret)?).filter(|secret| !secret.is_empty());
+
+    Ok(Some((client_id, client_secret)))
+}
+
+fn form_decode(value: &str) -> Result<String>
+{
+    percent_decode_str(&value.replace('+', " "))
+        .decode_utf8()
+        .map(String::from)
+        .map_err(|_| Error::InvalidClient)
+}
+
+/// Parses the space-separated list of scopes of the OAuth `scope` parameter
+fn parse_scope(scope: &str) -> Result<Vec<tokens::Scope>>
+{
+    let mut scopes = scope
+        .split(' ')
+        .filter(|name| !name.is_empty())
+        .map(|name| tokens::Scope::from_name(name).ok_or(Error::InvalidScope))
+        .collect::<Result<Vec<_>>>()?;
+    scopes.sort_unstable();
+    scopes.dedup();
+
+    if scopes.is_empty() {
+        return Err(Error::InvalidScope);
+    }
+
+    Ok(scopes)
+}
+
+fn format_scope(scopes: &[tokens::Scope]) -> String
+{
+    scopes
+        .iter()
+        .map(|scope| scope.name())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn scope_names(scopes: &[tokens::Scope]) -> Vec<String>
+{
+    scopes
+        .iter()
+        .map(|scope| String::from(scope.name()))
+        .collect()
+}
+
+type Result<T> = ::core::result::Result<T, Error>;
+
+#[derive(Debug, Error)]
+enum Error
+{
+    #[error("{0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("must be authenticated")]
+    MustBeAuthenticated,
+    #[error(
+        "client name must be between 1 and {} characters long",
+        clients::MAX_NAME_LEN
+    )]
+    InvalidClientName,
+    #[error("redirect URIs must be absolute https URIs, or http ones on the loopback interface")]
+    InvalidRedirectUri,
+    #[error("no client with id {id} was found")]
+    ClientNotFound
+    {
+        id: Uuid
+    },
+
+    // The errors defined by RFC 6749, which clients are meant to handle
+    // programmatically
+    #[error("{0}")]
+    InvalidRequest(&'static str),
+    #[error("client authentication failed")]
+    InvalidClient,
+    #[error("the grant is invalid, expired or was issued to another client")]
+    InvalidGrant,
+    #[error("the grant type is not supported")]
+    UnsupportedGrantType,
+    #[error("the response type is not supported")]
+    UnsupportedResponseType,
+    #[error("the scope is invalid")]
+    InvalidScope,
+}
+
+impl Error
+{
+    fn oauth_code(&self) -> Option<&'static str>
+    {
+        match self {
+            Error::InvalidRequest(_) => Some("invalid_request"),
+            Error::InvalidClient => Some("invalid_client"),
+            Error::InvalidGrant => Some("invalid_grant"),
+            Error::UnsupportedGrantType => Some("unsupported_grant_type"),
+            Error::UnsupportedResponseType => Some("unsupported_response_type"),
+            Error::InvalidScope => Some("invalid_scope"),
+            _ => None,
+        }
+    }
+}
+
+impl response::IntoResponse for Error
+{
+    fn into_response(self) -> response::Response
+    {
+        if let Some(oauth_code) = self.oauth_code() {
+            let payload = json!({
+                "error": oauth_code,
+                "error_description": self.to_string(),
+            });
+
+            // RFC 6749 has the scheme the client may authenticate with named
+            // when authentication fails
+            if let Error::InvalidClient = self {
+                return (
+                    http::StatusCode::UNAUTHORIZED,
+                    [(http::header::WWW_AUTHENTICATE, "Basic")],
+                    axum::Json(payload),
+                )
+                    .into_response();
+            }
+
+            return (http::StatusCode::BAD_REQUEST, axum::Json(payload)).into_response();
+        }
+
+        let code = match self {
+            Error::InvalidClientName => api_error::Code::OAUTH_INVALID_CLIENT_NAME,
+            Error::InvalidRedirectUri => api_error::Code::OAUTH_INVALID_REDIRECT_URI,
+            Error::MustBeAuthenticated => return http::StatusCode::UNAUTHORIZED.into_response(),
+            Error::ClientNotFound { .. } => return http::StatusCode::NOT_FOUND.into_response(),
+            _ => return http::StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        };
+        let payload = json!({
+            "message": self.to_string(),
+            "code": code,
+        });
+
+        (http::StatusCode::UNPROCESSABLE_ENTITY, axum::Json(payload)).into_response()
+    }
+}
@@ -0,0 +1,701 @@
+use axum::{http, Extension, Form};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::http::{oauth, tokens};
+
+/// Responses carrying tokens must not be cached, as required by RFC 6749
+const NO_STORE: [(http::HeaderName, &str); 2] = [
+    (http::header::CACHE_CONTROL, "no-store"),
+    (http::header::PRAGMA, "no-cache"),
+];
+
+// The characters RFC 7636 allows in a code verifier, other than letters and
+// digits, along with its length limits
+const CODE_VERIFIER_PUNCTUATION: &[u8] = b"-._~";
+const CODE_VERIFIER_MIN_LEN: usize = 43;
+const CODE_VERIFIER_MAX_LEN: usize = 128;
+
+/// Client credentials may be left out in favor of an `Authorization: Basic`
+/// header
+#[derive(Deserialize)]
+pub(super) struct TokenRequest
+{
+    grant_type: String,
+    client_id: Option<Uuid>,
+    client_secret: Option<String>,
+    // authorization_code
+    code: Option<String>,
+    redirect_uri: Option<String>,
+    code_verifier: Option<String>,
+    // refresh_token
+    refresh_token: Option<String>,
+}
+
+#[derive(Serialize)]
+pub(super) struct TokenResponse
+{
+    access_token: String,
+    token_type: &'static str,
+    expires_in: u64,
+    refresh_token: String,
+    scope: String,
+}
+
+pub(super) async fn create_token(
+    pg_pool: Extension<PgPool>,
+    headers: http::HeaderMap,
+    Form(mut req): Form<TokenRequest>,
+) -> oauth::Result<(
+    [(http::HeaderName, &'static str); 2],
+    axum::Json<TokenResponse>,
+)>
+{
+    let client_id =
+        oauth::authenticate_client(&pg_pool, &headers, req.client_id, req.client_secret.take())
+            .await?;
+
+    let token_response = match req.grant_type.as_str() {
+        "authorization_code" => exchange_code(&pg_pool, client_id, req).await?,
+        "refresh_token" => refresh(&pg_pool, client_id, req).await?,
+        _ => return Err(oauth::Error::UnsupportedGrantType),
+    };
+
+    Ok((NO_STORE, axum::Json(token_response)))
+}
+
+async fn exchange_code(
+    pg_pool: &PgPool,
+    client_id: Uuid,
+    req: TokenRequest,
+) -> oauth::Result<TokenResponse>
+{
+    let code = req
+        .code
+        .ok_or(oauth::Error::InvalidRequest("missing code"))?;
+    let redirect_uri = req
+        .redirect_uri
+        .ok_or(oauth::Error::InvalidRequest("missing redirect_uri"))?;
+    let code_verifier = req
+        .code_verifier
+        .ok_or(oauth::Error::InvalidRequest("missing code_verifier"))?;
+    let is_valid_verifier = (CODE_VERIFIER_MIN_LEN..=CODE_VERIFIER_MAX_LEN)
+        .contains(&code_verifier.len())
+        && code_verifier
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || CODE_VERIFIER_PUNCTUATION.contains(&byte));
+    if !is_valid_verifier {
+        return Err(oauth::Error::InvalidRequest("invalid code_verifier"));
+    }
+
+    // Codes are single-use, so they are gone as soon as anyone presents them,
+    // whether or not the rest of the request checks out
+    let grant = sqlx::query!(
+        r#"
+            DELETE FROM "oauth_authorization_codes"
+            WHERE code_hash = $1
+            RETURNING
+                client_id,
+                user_id,
+                redirect_uri,
+                scopes,
+                code_challenge,
+                expires_at > now() as "is_live!"
+        "#,
+        tokens::hash_token(&code)
+    )
+    .fetch_optional(pg_pool)
+    .await?
+    .ok_or(oauth::Error::InvalidGrant)?;
+
+    let challenge = base64::encode_config(
+        Sha256::digest(code_verifier.as_bytes()),
+        base64::URL_SAFE_NO_PAD,
+    );
+    if !grant.is_live
+        || grant.client_id != client_id
+        || grant.redirect_uri != redirect_uri
+        || grant.code_challenge != challenge
+    {
+        return Err(oauth::Error::InvalidGrant);
+    }
+
+    let scopes = tokens::parse_scopes(&grant.scopes);
+    let access_token = tokens::generate_token(oauth::ACCESS_TOKEN_PREFIX);
+    let refresh_token = tokens::generate_token(oauth::REFRESH_TOKEN_PREFIX);
+
+    let _pg_query_res = sqlx::query!(
+        r#"
+            INSERT INTO "oauth_tokens"(
+                client_id,
+                user_id,
+                scopes,
+                access_token_hash,
+                access_expires_at,
+                refresh_token_hash,
+                refresh_expires_at
+            )
+            VALUES (
+                $1, $2, $3, $4, now() + make_interval(secs => $5),
+                $6, now() + make_interval(secs => $7)
+            )
+        "#,
+        grant.client_id,
+        grant.user_id,
+        &grant.scopes[..],
+        tokens::hash_token(&access_token),
+        oauth::ACCESS_TOKEN_LIFETIME.as_secs_f64(),
+        tokens::hash_token(&refresh_token),
+        oauth::REFRESH_TOKEN_LIFETIME.as_secs_f64()
+    )
+    .execute(pg_pool)
+    .await?;
+
+    Ok(token_response(access_token, refresh_token, &scopes))
+}
+
+/// Refreshing replaces both tokens of the grant, so that a leaked refresh
+/// token stops working as soon as either party uses it
+async fn refresh(
+    pg_pool: &PgPool,
+    client_id: Uuid,
+    req: TokenRequest,
+) -> oauth::Result<TokenResponse>
+{
+    let refresh_token = req
+        .refresh_token
+        .ok_or(oauth::Error::InvalidRequest("missing refresh_token"))?;
+
+    let new_access_token = tokens::generate_token(oauth::ACCESS_TOKEN_PREFIX);
+    let new_refresh_token = tokens::generate_token(oauth::REFRESH_TOKEN_PREFIX);
+
+    let grant = sqlx::query!(
+        r#"
+            UPDATE "oauth_tokens"
+            SET
+                access_token_hash = $3,
+                access_expires_at = now() + make_interval(secs => $4),
+                refresh_token_hash = $5,
+                refresh_expires_at = now() + make_interval(secs => $6)
+            WHERE refresh_token_hash = $1 AND client_id = $2 AND refresh_expires_at > now()
+            RETURNING scopes
+        "#,
+        tokens::hash_token(&refresh_token),
+        client_id,
+        tokens::hash_token(&new_access_token),
+        oauth::ACCESS_TOKEN_LIFETIME.as_secs_f64(),
+        tokens::hash_token(&new_refresh_token),
+        oauth::REFRESH_TOKEN_LIFETIME.as_secs_f64()
+    )
+    .fetch_optional(pg_pool)
+    .await?
+    .ok_or(oauth::Error::InvalidGrant)?;
+
+    Ok(token_response(
+        new_access_token,
+        new_refresh_token,
+        &tokens::parse_scopes(&grant.scopes),
+    ))
+}
+
+fn token_response(
+    access_token: String,
+    refresh_token: String,
+    scopes: &[tokens::Scope],
+) -> TokenResponse
+{
+    TokenResponse {
+        access_token,
+        token_type: "Bearer",
+        expires_in: oauth::ACCESS_TOKEN_LIFETIME.as_secs(),
+        refresh_token,
+        scope: oauth::format_scope(scopes),
+    }
+}
+
+#[derive(Deserialize)]
+pub(super) struct TokenActionRequest
+{
+    token: String,
+    client_id: Option<Uuid>,
+    client_secret: Option<String>,
+}
+
+/// Revokes the grant the access or refresh token belongs to, as defined by
+/// RFC 7009. Unknown tokens are not an error, as the outcome is the same
+pub(super) async fn revoke_token(
+    pg_pool: Extension<PgPool>,
+    headers: http::HeaderMap,
+    Form(req): Form<TokenActionRequest>,
+) -> oauth::Result<http::StatusCode>
+{
+    let client_id =
+        oauth::authenticate_client(&pg_pool, &headers, req.client_id, req.client_secret).await?;
+
+    let token_hash = tokens::hash_token(&req.token);
+    let _pg_query_res = sqlx::query!(
+        r#"
+            DELETE FROM "oauth_tokens"
+            WHERE client_id = $1 AND (access_token_hash = $2 OR refresh_token_hash = $2)
+        "#,
+        client_id,
+        token_hash
+    )
+    .execute(&*pg_pool)
+    .await?;
+
+    Ok(http::StatusCode::OK)
+}
+
+#[derive(Serialize)]
+pub(super) struct Introspection
+{
+    active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sub: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exp: Option<i64>,
+}
+
+/// Describes an access or refresh token, as defined by RFC 7662. Clients may
+/// only introspect their own tokens, any other token is reported as inactive
+pub(super) async fn introspect_token(
+    pg_pool: Extension<PgPool>,
+    headers: http::HeaderMap,
+    Form(req): Form<TokenActionRequest>,
+) -> oauth::Result<axum::Json<Introspection>>
+{
+    let client_id =
+        oauth::authenticate_client(&pg_pool, &headers, req.client_id, req.client_secret).await?;
+
+    let token_hash = tokens::hash_token(&req.token);
+    let grant = sqlx::query!(
+        r#"
+            SELECT
+                user_id,
+                scopes,
+                CASE WHEN access_token_hash = $2
+                    THEN extract(epoch from access_expires_at)::bigint
+                    ELSE extract(epoch from refresh_expires_at)::bigint
+                END as "exp!"
+            FROM "oauth_tokens"
+            WHERE client_id = $1 AND (
+                (access_token_hash = $2 AND access_expires_at > now())
+                OR (refresh_token_hash = $2 AND refresh_expires_at > now())
+            )
+        "#,
+        client_id,
+        token_hash
+    )
+    .fetch_optional(&*pg_pool)
+    .await?;
+
+    let introspection = match grant {
+        Some(grant) => Introspection {
+            active: true,
+            scope: Some(oauth::format_scope(&tokens::parse_scopes(&grant.scopes))),
+            client_id: Some(client_id),
+            sub: Some(grant.user_id),
+            exp: Some(grant.exp),
+        },
+        None => Introspection {
+            active: false,
+            scope: None,
+            client_id: None,
+            sub: None,
+            exp: None,
+        },
+    };
+
+    Ok(axum::Json(introspection))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use axum::{body::Body, Router};
+    use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+    use serde_json::Value;
+    use tower::Service;
+
+    use super::*;
+
+    const REDIRECT_URI: &str = "https://app.example/callback";
+    const VERIFIER: &str = "dBjftJeZ4CVP-mJ92K9RF_6xq8Hi.wWdc~TOz9kX-IwR3";
+
+    struct TestApp
+    {
+        pg_pool: PgPool,
+        router: Router,
+        user_id: Uuid,
+    }
+
+    /// How the client sends its credentials along
+    enum Auth<'a>
+    {
+        Basic(Uuid, &'a str),
+        Body(Uuid, Option<&'a str>),
+    }
+
+    impl TestApp
+    {
+        async fn new(pg_pool: PgPool) -> Self
+        {
+            let user = sqlx::query!(
+                r#"INSERT INTO "users"(username, password) VALUES ('alice', '') RETURNING user_id"#
+            )
+            .fetch_one(&pg_pool)
+            .await
+            .unwrap();
+
+            TestApp {
+                router: oauth::router().layer(Extension(pg_pool.clone())),
+                pg_pool,
+                user_id: user.user_id,
+            }
+        }
+
+        /// Registers a client, handing back its ID and its secret unless it
+        /// is a public one
+        async fn create_client(&self, confidential: bool) -> (Uuid, Option<String>)
+        {
+            let secret = confidential.then(|| tokens::generate_token(oauth::CLIENT_SECRET_PREFIX));
+            let client = sqlx::query!(
+                r#"
+                    INSERT INTO "oauth_clients"(owner_id, name, secret_hash, redirect_uris)
+                    VALUES ($1, 'app', $2, $3)
+                    RETURNING client_id
+                "#,
+                self.user_id,
+                secret.as_deref().map(tokens::hash_token),
+                &[String::from(REDIRECT_URI)][..]
+            )
+            .fetch_one(&self.pg_pool)
+            .await
+            .unwrap();
+
+            (client.client_id, secret)
+        }
+
+        /// Stands in for the user approving the client, with the challenge
+        /// derived from `VERIFIER`
+        async fn create_code(&self, client_id: Uuid) -> String
+        {
+            let code = tokens::generate_token(oauth::AUTHORIZATION_CODE_PREFIX);
+            let challenge =
+                base64::encode_config(Sha256::digest(VERIFIER.as_bytes()), base64::URL_SAFE_NO_PAD);
+
+            let _pg_query_res = sqlx::query!(
+                r#"
+                    INSERT INTO "oauth_authorization_codes"(
+                        code_hash, client_id, user_id, redirect_uri, scopes, code_challenge, expires_at
+                    )
+                    VALUES ($1, $2, $3, $4, '{read}', $5, now() + interval '10 minutes')
+                "#,
+                tokens::hash_token(&code),
+                client_id,
+                self.user_id,
+                REDIRECT_URI,
+                challenge
+            )
+            .execute(&self.pg_pool)
+            .await
+            .unwrap();
+
+            code
+        }
+
+        async fn send(
+            &mut self,
+            uri: &str,
+            auth: Auth<'_>,
+            params: &[(&str, &str)],
+        ) -> (http::StatusCode, http::HeaderMap, Value)
+        {
+            let client_id;
+            let mut params = params.to_vec();
+            let mut req = http::Request::builder()
+                .method(http::Method::POST)
+                .uri(uri)
+                .header(
+                    http::header::CONTENT_TYPE,
+                    "application/x-www-form-urlencoded",
+                );
+            match auth {
+                Auth::Basic(id, secret) => {
+                    let credentials = base64::encode(format!("{}:{}", id, secret));
+                    req = req.header(
+                        http::header::AUTHORIZATION,
+                        format!("Basic {}", credentials),
+                    );
+                }
+                Auth::Body(id, secret) => {
+                    client_id = id.to_string();
+                    params.push(("client_id", &client_id));
+                    if let Some(secret) = secret {
+                        params.push(("client_secret", secret));
+                    }
+                }
+            }
+            let body = params
+                .iter()
+                .map(|(key, value)| {
+                    format!("{}={}", key, utf8_percent_encode(value, NON_ALPHANUMERIC))
+                })
+                .collect::<Vec<_>>()
+                .join("&");
+
+            let res = self
+                .router
+                .call(req.body(Body::from(body)).unwrap())
+                .await
+                .unwrap();
+            let status = res.status();
+            let headers = res.headers().clone();
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let json = serde_json::from_slice(&body).unwrap_or(Value::Null);
+
+            (status, headers, json)
+        }
+
+        async fn exchange(
+            &mut self,
+            auth: Auth<'_>,
+            code: &str,
+            verifier: &str,
+        ) -> (http::StatusCode, Value)
+        {
+            let (status, _headers, json) = self
+                .send(
+                    "/oauth/token",
+                    auth,
+                    &[
+                        ("grant_type", "authorization_code"),
+                        ("code", code),
+                        ("redirect_uri", REDIRECT_URI),
+                        ("code_verifier", verifier),
+                    ],
+                )
+                .await;
+
+            (status, json)
+        }
+
+        async fn refresh(
+            &mut self,
+            auth: Auth<'_>,
+            refresh_token: &str,
+        ) -> (http::StatusCode, Value)
+        {
+            let (status, _headers, json) = self
+                .send(
+                    "/oauth/token",
+                    auth,
+                    &[
+                        ("grant_type", "refresh_token"),
+                        ("refresh_token", refresh_token),
+                    ],
+                )
+                .await;
+
+            (status, json)
+        }
+
+        async fn is_active(&self, access_token: &Value) -> bool
+        {
+            oauth::authenticate(&self.pg_pool, access_token.as_str().unwrap())
+                .await
+                .unwrap()
+                .is_some()
+        }
+    }
+
+    #[sqlx::test]
+    async fn codes_are_exchanged_once(pg_pool: PgPool)
+    {
+        let mut app = TestApp::new(pg_pool).await;
+        let (client_id, secret) = app.create_client(true).await;
+        let secret = secret.unwrap();
+        let code = app.create_code(client_id).await;
+
+        let (status, headers, tokens) = app
+            .send(
+                "/oauth/token",
+                Auth::Basic(client_id, &secret),
+                &[
+                    ("grant_type", "authorization_code"),
+                    ("code", &code),
+                    ("redirect_uri", REDIRECT_URI),
+                    ("code_verifier", VERIFIER),
+                ],
+            )
+            .await;
+        assert_eq!(status, http::StatusCode::OK);
+        assert_eq!(headers[http::header::CACHE_CONTROL], "no-store");
+        assert_eq!(tokens["token_type"], "Bearer");
+        assert_eq!(tokens["scope"], "read");
+        assert!(app.is_active(&tokens["access_token"]).await);
+
+        let (status, error) = app
+            .exchange(Auth::Body(client_id, Some(&secret)), &code, VERIFIER)
+            .await;
+        assert_eq!(status, http::StatusCode::BAD_REQUEST);
+        assert_eq!(error["error"], "invalid_grant");
+    }
+
+    #[sqlx::test]
+    async fn code_verifiers_must_be_well_formed_and_match(pg_pool: PgPool)
+    {
+        let mut app = TestApp::new(pg_pool).await;
+        let (client_id, _secret) = app.create_client(false).await;
+        let code = app.create_code(client_id).await;
+
+        // Malformed verifiers are turned away before the code is looked at
+        let too_short = &VERIFIER[..42];
+        let too_long = "a".repeat(129);
+        let unreserved = VERIFIER.replace('~', "+");
+        for verifier in [too_short, too_long.as_str(), unreserved.as_str()] {
+            let (status, error) = app
+                .exchange(Auth::Body(client_id, None), &code, verifier)
+                .await;
+            assert_eq!(status, http::StatusCode::BAD_REQUEST);
+            assert_eq!(error["error"], "invalid_request", "{}", verifier);
+        }
+
+        let (status, _tokens) = app
+            .exchange(Auth::Body(client_id, None), &code, VERIFIER)
+            .await;
+        assert_eq!(status, http::StatusCode::OK);
+
+        // A well-formed verifier which doesn't match uses the code up
+        let code = app.create_code(client_id).await;
+        let other_verifier = "a".repeat(128);
+        let (status, error) = app
+            .exchange(Auth::Body(client_id, None), &code, &other_verifier)
+            .await;
+        assert_eq!(status, http::StatusCode::BAD_REQUEST);
+        assert_eq!(error["error"], "invalid_grant");
+
+        let (status, error) = app
+            .exchange(Auth::Body(client_id, None), &code, VERIFIER)
+            .await;
+        assert_eq!(status, http::StatusCode::BAD_REQUEST);
+        assert_eq!(error["error"], "invalid_grant");
+    }
+
+    #[sqlx::test]
+    async fn refreshing_replaces_both_tokens(pg_pool: PgPool)
+    {
+        let mut app = TestApp::new(pg_pool).await;
+        let (client_id, secret) = app.create_client(true).await;
+        let secret = secret.unwrap();
+        let (other_client_id, other_secret) = app.create_client(true).await;
+        let other_secret = other_secret.unwrap();
+        let code = app.create_code(client_id).await;
+
+        let (status, first) = app
+            .exchange(Auth::Basic(client_id, &secret), &code, VERIFIER)
+            .await;
+        assert_eq!(status, http::StatusCode::OK);
+        let refresh_token = first["refresh_token"].as_str().unwrap();
+
+        // Refresh tokens are bound to the client they were issued to
+        let (status, error) = app
+            .refresh(Auth::Basic(other_client_id, &other_secret), refresh_token)
+            .await;
+        assert_eq!(status, http::StatusCode::BAD_REQUEST);
+        assert_eq!(error["error"], "invalid_grant");
+
+        let (status, second) = app
+            .refresh(Auth::Body(client_id, Some(&secret)), refresh_token)
+            .await;
+        assert_eq!(status, http::StatusCode::OK);
+        assert_ne!(second["access_token"], first["access_token"]);
+        assert_ne!(second["refresh_token"], first["refresh_token"]);
+        assert_eq!(second["scope"], "read");
+        assert!(!app.is_active(&first["access_token"]).await);
+        assert!(app.is_active(&second["access_token"]).await);
+
+        let (status, error) = app
+            .refresh(Auth::Basic(client_id, &secret), refresh_token)
+            .await;
+        assert_eq!(status, http::StatusCode::BAD_REQUEST);
+        assert_eq!(error["error"], "invalid_grant");
+    }
+
+    #[sqlx::test]
+    async fn clients_authenticate_one_way_at_a_time(pg_pool: PgPool)
+    {
+        let mut app = TestApp::new(pg_pool).await;
+        let (client_id, secret) = app.create_client(true).await;
+        let secret = secret.unwrap();
+        let (public_client_id, _secret) = app.create_client(false).await;
+        let params = [("token", "bboat_unknown")];
+
+        let (status, headers, error) = app
+            .send(
+                "/oauth/revoke",
+                Auth::Basic(client_id, "bbocs_wrong"),
+                &params,
+            )
+            .await;
+        assert_eq!(status, http::StatusCode::UNAUTHORIZED);
+        assert_eq!(headers[http::header::WWW_AUTHENTICATE], "Basic");
+        assert_eq!(error["error"], "invalid_client");
+
+        let (status, _headers, _body) = app
+            .send("/oauth/revoke", Auth::Basic(client_id, &secret), &params)
+            .await;
+        assert_eq!(status, http::StatusCode::OK);
+        // Public clients may send their ID alone in the header
+        let (status, _headers, _body) = app
+            .send("/oauth/revoke", Auth::Basic(public_client_id, ""), &params)
+            .await;
+        assert_eq!(status, http::StatusCode::OK);
+
+        // Both at once is ambiguous
+        let both = [
+            ("token", "bboat_unknown"),
+            ("client_secret", secret.as_str()),
+        ];
+        let (status, _headers, error) = app
+            .send("/oauth/revoke", Auth::Basic(client_id, &secret), &both)
+            .await;
+        assert_eq!(status, http::StatusCode::BAD_REQUEST);
+        assert_eq!(error["error"], "invalid_request");
+
+        let (status, _headers, error) = app
+            .send(
+                "/oauth/revoke",
+                Auth::Body(public_client_id, None),
+                &both[..1],
+            )
+            .await;
+        assert_eq!(status, http::StatusCode::OK, "{}", error);
+
+        let res = app
+            .router
+            .call(
+                http::Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/oauth/revoke")
+                    .header(
+                        http::header::CONTENT_TYPE,
+                        "application/x-www-form-urlencoded",
+                    )
+                    .header(http::header::AUTHORIZATION, "Basic not-base64")
+                    .body(Body::from("token=bboat_unknown"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), http::StatusCode::UNAUTHORIZED);
+    }
+}
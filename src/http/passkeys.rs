@@ -0,0 +1,803 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, TypedHeader},
+    headers::Cookie,
+    http, response,
+    routing::{delete, get, post},
+    Extension, Router,
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use thiserror::Error;
+use webauthn_rs::prelude::{
+    CreationChallengeResponse, Passkey, PasskeyRegistration, RegisterPublicKeyCredential, Webauthn,
+    WebauthnError,
+};
+
+use crate::{
+    http::{api_error, json, session},
+    password,
+};
+
+/// The session key holding the registration ceremony in progress, which is
+/// tied to whoever is logged in
+pub(in crate::http) const REGISTRATION_KEY: &str = "passkey_registration";
+const MAX_NAME_LEN: usize = 100;
+
+pub(in crate::http) fn router() -> Router
+{
+    Router::new()
+        .route(
+            "/users/me/passkeys",
+            get(fetch_passkeys).post(create_passkey),
+        )
+        .route(
+            "/users/me/passkeys/challenge",
+            post(create_passkey_challenge),
+        )
+        .route("/users/me/passkeys/:id", delete(delete_passkey))
+}
+
+/// Every passkey registered by the user, along with its ID. Credentials which
+/// no longer deserialize are skipped rather than locking the user out of the
+/// others
+pub(in crate::http) async fn user_passkeys(
+    pg_pool: &PgPool,
+    user_id: Uuid,
+) -> sqlx::Result<Vec<(Uuid, Passkey)>>
+{
+    let passkeys = sqlx::query!(
+        r#"select passkey_id, credential from "passkeys" where user_id = $1"#,
+        user_id
+    )
+    .fetch_all(pg_pool)
+    .await?
+    .into_iter()
+    .filter_map(
+        |passkey| match serde_json::from_str::<Passkey>(&passkey.credential) {
+            Ok(credential) => Some((passkey.passkey_id, credential)),
+            Err(err) => {
+                tracing::warn!("skipping passkey {}: {}", passkey.passkey_id, err);
+                None
+            }
+        },
+    )
+    .collect();
+
+    Ok(passkeys)
+}
+
+/// Records that the passkey was used to log in, saving its credential along
+/// the way if the authenticator bumped its signature counter
+pub(in crate::http) async fn record_use(
+    pg_pool: &PgPool,
+    passkey_id: Uuid,
+    updated_credential: Option<&Passkey>,
+) -> sqlx::Result<()>
+{
+    // SAFETY: Serializing a credential which was just deserialized cannot
+    // fail
+    let credential = updated_credential.map(|passkey| serde_json::to_string(passkey).unwrap());
+
+    let _pg_query_res = sqlx::query!(
+        r#"
+            UPDATE "passkeys"
+            SET last_used_at = now(), credential = coalesce($2, credential)
+            WHERE passkey_id = $1
+        "#,
+        passkey_id,
+        credential
+    )
+    .execute(pg_pool)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreatePasskeyChallenge
+{
+    current_password: String,
+}
+
+/// Starts registering a passkey, handing back the options to pass to
+/// `navigator.credentials.create()`
+///
+/// A passkey logs in on its own, so the current password is asked for at
+/// both steps, lest a hijacked session keep its own way back in
+async fn create_passkey_challenge(
+    pg_pool: Extension<PgPool>,
+    session_store: Extension<session::Store>,
+    webauthn: Extension<Arc<Webauthn>>,
+    password_hasher: Extension<password::Hasher>,
+    user_id: session::extractor::UserId,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    json::extractor::Json(req): json::extractor::Json<CreatePasskeyChallenge>,
+) -> Result<axum::Json<CreationChallengeResponse>>
+{
+    let user_id = match user_id {
+        session::extractor::UserId::Found(user_id, session::extractor::AuthMethod::Session) => {
+            user_id
+        }
+        _ => return Err(Error::MustBeAuthenticated),
+    };
+
+    verify_password(&pg_pool, &password_hasher, user_id, req.current_password).await?;
+
+    let user = sqlx::query!(r#"select username from users where user_id = $1"#, user_id)
+        .fetch_one(&*pg_pool)
+        .await?;
+
+    // Authenticators refuse to register a second passkey for the same
+    // account, rather than silently replacing the first
+    let exclude_credentials = user_passkeys(&pg_pool, user_id)
+        .await?
+        .iter()
+        .map(|(_passkey_id, passkey)| passkey.cred_id().clone())
+        .collect();
+
+    let (challenge, registration) = webauthn.start_passkey_registration(
+        user_id,
+        &user.username,
+        &user.username,
+        Some(exclude_credentials),
+    )?;
+
+    let mut session = load_session(&session_store, &cookie).await?;
+    session.insert(REGISTRATION_KEY, registration).await?;
+    let _cookie = session_store.store_session(session).await?;
+
+    Ok(axum::Json(challenge))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PasskeyDetails
+{
+    id: Uuid,
+    name: String,
+    created_at: i64,
+    last_used_at: Option<i64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreatePasskey
+{
+    current_password: String,
+    name: String,
+    /// What `navigator.credentials.create()` resolved to
+    credential: RegisterPublicKeyCredential,
+}
+
+/// Completes the registration started by [`create_passkey_challenge`]
+async fn create_passkey(
+    pg_pool: Extension<PgPool>,
+    session_store: Extension<session::Store>,
+    webauthn: Extension<Arc<Webauthn>>,
+    password_hasher: Extension<password::Hasher>,
+    user_id: session::extractor::UserId,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    json::extractor::Json(req): json::extractor::Json<CreatePasskey>,
+) -> Result<(http::StatusCode, axum::Json<PasskeyDetails>)>
+{
+    let user_id = match user_id {
+        session::extractor::UserId::Found(user_id, session::extractor::AuthMethod::Session) => {
+            user_id
+        }
+        _ => return Err(Error::MustBeAuthenticated),
+    };
+    let CreatePasskey {
+        current_password,
+        name,
+        credential,
+    } = req;
+
+    verify_password(&pg_pool, &password_hasher, user_id, current_password).await?;
+
+    let name = String::from(name.trim());
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+        return Err(Error::InvalidName);
+    }
+
+    // A challenge is only good for a single attempt
+    let mut session = load_session(&session_store, &cookie).await?;
+    let registration = session
+        .get::<PasskeyRegistration>(REGISTRATION_KEY)
+        .await
+        .ok_or(Error::NoPendingRegistration)?;
+    session.retain(|key| key != REGISTRATION_KEY);
+    let _cookie = session_store.store_session(session).await?;
+
+    let passkey = webauthn
+        .finish_passkey_registration(&credential, &registration)
+        .map_err(|_| Error::InvalidCredential)?;
+    // SAFETY: Serializing a credential which was just built cannot fail
+    let serialized_passkey = serde_json::to_string(&passkey).unwrap();
+
+    let created = sqlx::query!(
+        r#"
+            INSERT INTO "passkeys"(user_id, credential_id, name, credential)
+            VALUES ($1, $2, $3, $4)
+            RETURNING passkey_id, extract(epoch from created_at)::bigint as "created_at!"
+        "#,
+        user_id,
+        &passkey.cred_id().0[..],
+        name,
+        serialized_passkey
+    )
+    .fetch_one(&*pg_pool)
+    .await
+    .map_err(|err| match err {
+        sqlx::Error::Database(db_err)
+            if db_err.constraint() == Some("passkeys_credential_id_key") =>
+        {
+            Error::AlreadyRegistered
+        }
+        err => err.into(),
+    })?;
+
+    Ok((
+        http::StatusCode::CREATED,
+        axum::Json(PasskeyDetails {
+            id: created.passkey_id,
+            name,
+            created_at: created.created_at,
+            last_used_at: None,
+        }),
+    ))
+}
+
+async fn fetch_passkeys(
+    pg_pool: Extension<PgPool>,
+    user_id: session::extractor::UserId,
+) -> Result<axum::Json<Vec<PasskeyDetails>>>
+{
+    let user_id = match user_id {
+        session::extractor::UserId::Found(user_id, session::extractor::AuthMethod::Session) => {
+            user_id
+        }
+        _ => return Err(Error::MustBeAuthenticated),
+    };
+
+    let passkeys = sqlx::query!(
+        r#"
+            SELECT
+                passkey_id,
+                name,
+                extract(epoch from created_at)::bigint as "created_at!",
+                extract(epoch from last_used_at)::bigint as last_used_at
+            FROM "passkeys"
+            WHERE user_id = $1
+            ORDER BY created_at DESC
+        "#,
+        user_id
+    )
+    .fetch_all(&*pg_pool)
+    .await?
+    .into_iter()
+    .map(|passkey| PasskeyDetails {
+        id: passkey.passkey_id,
+        name: passkey.name,
+        created_at: passkey.created_at,
+        last_used_at: passkey.last_used_at,
+    })
+    .collect();
+
+    Ok(axum::Json(passkeys))
+}
+
+async fn delete_passkey(
+    pg_pool: Extension<PgPool>,
+    user_id: session::extractor::UserId,
+    Path(passkey_id): Path<Uuid>,
+) -> Result<http::StatusCode>
+{
+    let user_id = match user_id {
+        session::extractor::UserId::Found(user_id, session::extractor::AuthMethod::Session) => {
+            user_id
+        }
+        _ => return Err(Error::MustBeAuthenticated),
+    };
+
+    let pg_query_res = sqlx::query!(
+        r#"DELETE FROM "passkeys" WHERE passkey_id = $1 AND user_id = $2"#,
+        passkey_id,
+        user_id
+    )
+    .execute(&*pg_pool)
+    .await?;
+    if pg_query_res.rows_affected() == 0 {
+        return Err(Error::PasskeyNotFound { id: passkey_id });
+    }
+
+    Ok(http::StatusCode::NO_CONTENT)
+}
+
+/// Loads the session the request was authenticated with, which registration
+/// keeps its ceremony in
+async fn load_session(session_store: &session::Store, cookie: &Cookie) -> Result<session::Session>
+{
+    let session_cookie = cookie
+        .get(session::SESSION_COOKIE_NAME)
+        .ok_or(Error::MustBeAuthenticated)?;
+
+    Ok(session_store.load_session(session_cookie).await?)
+}
+
+/// Checks the password the user entered to confirm it is them making the
+/// change
+async fn verify_password(
+    pg_pool: &PgPool,
+    password_hasher: &password::Hasher,
+    user_id: Uuid,
+    current_password: String,
+) -> Result<()>
+{
+    let user = sqlx::query!(r#"select password from users where user_id = $1"#, user_id)
+        .fetch_one(pg_pool)
+        .await?;
+
+    let password_is_correct = password_hasher
+        .verify(current_password, user.password)
+        .await?;
+    if !password_is_correct {
+        return Err(Error::WrongPassword);
+    }
+
+    Ok(())
+}
+
+type Result<T> = ::core::result::Result<T, Error>;
+
+#[derive(Debug, Error)]
+enum Error
+{
+    #[error("{0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("{0}")]
+    Session(#[from] session::Error),
+    #[error("{0}")]
+    Webauthn(#[from] WebauthnError),
+    #[error("{0}")]
+    Password(#[from] password::Error),
+    #[error("must be authenticated")]
+    MustBeAuthenticated,
+    #[error("the provided password is wrong")]
+    WrongPassword,
+    #[error("passkey name must be between 1 and {} characters long", MAX_NAME_LEN)]
+    InvalidName,
+    #[error("no passkey registration is in progress")]
+    NoPendingRegistration,
+    #[error("the credential could not be verified")]
+    InvalidCredential,
+    #[error("the passkey is already registered")]
+    AlreadyRegistered,
+    #[error("no passkey with id {id} was found")]
+    PasskeyNotFound
+    {
+        id: Uuid
+    },
+}
+
+impl response::IntoResponse for Error
+{
+    fn into_response(self) -> response::Response
+    {
+        let code = match self {
+            Error::InvalidName => api_error::Code::PASSKEY_INVALID_NAME,
+            Error::InvalidCredential => api_error::Code::PASSKEY_INVALID_CREDENTIAL,
+            Error::MustBeAuthenticated => return http::StatusCode::UNAUTHORIZED.into_response(),
+            Error::WrongPassword => return http::StatusCode::UNPROCESSABLE_ENTITY.into_response(),
+            Error::NoPendingRegistration | Error::AlreadyRegistered => {
+                return http::StatusCode::CONFLICT.into_response()
+            }
+            Error::PasskeyNotFound { .. } => return http::StatusCode::NOT_FOUND.into_response(),
+            _ => return http::StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        };
+
+        let payload = json!({
+            "message": self.to_string(),
+            "code": code,
+        });
+
+        (http::StatusCode::UNPROCESSABLE_ENTITY, axum::Json(payload)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::{net::SocketAddr, time::Duration};
+
+    use axum::{body::Body, extract::ConnectInfo};
+    use serde_json::Value;
+    use tower::Service;
+    use webauthn_authenticator_rs::{softpasskey::SoftPasskey, WebauthnAuthenticator};
+    use webauthn_rs::{
+        prelude::{RequestChallengeResponse, Url},
+        WebauthnBuilder,
+    };
+
+    use super::*;
+    use crate::http::{auth, rate_limit};
+
+    const PASSWORD: &str = "correct horse battery staple";
+
+    fn origin() -> Url
+    {
+        Url::parse("https://localhost").unwrap()
+    }
+
+    struct TestApp
+    {
+        pg_pool: PgPool,
+        session_store: session::Store,
+        password_hasher: password::Hasher,
+        router: Router,
+    }
+
+    impl TestApp
+    {
+        fn new(pg_pool: PgPool) -> Self
+        {
+            let session_store: session::Store = Arc::new(session::MemoryStore::new());
+            let rate_limit_store: rate_limit::Store = Arc::new(rate_limit::MemoryStore::new());
+            let login_limiter = rate_limit::LoginLimiter::new(
+                rate_limit_store,
+                rate_limit::Quota::new(100, Duration::from_secs(60 * 60)),
+                rate_limit::Quota::new(100, Duration::from_secs(60 * 60)),
+                Duration::from_secs(60),
+                Duration::from_secs(60 * 60),
+            );
+            let webauthn = WebauthnBuilder::new("localhost", &origin())
+                .unwrap()
+                .build()
+                .unwrap();
+            let password_hasher = password::Hasher::new(
+                argon2::Params::new(8, 1, 1, None).unwrap(),
+                Default::default(),
+            );
+            let router = router()
+                .merge(auth::router())
+                .layer(Extension(pg_pool.clone()))
+                .layer(Extension(session_store.clone()))
+                .layer(Extension(session::Expiry::new(
+                    Duration::from_secs(60 * 60),
+                    Duration::from_secs(60 * 60),
+                )))
+                .layer(Extension(session::CookieBuilder::new(
+                    false,
+                    session::SameSite::Lax,
+                    String::from("/"),
+                    None,
+                )))
+                .layer(Extension(password_hasher.clone()))
+                .layer(Extension(login_limiter))
+                .layer(Extension(rate_limit::TrustedProxies::default()))
+                .layer(Extension(Arc::new(webauthn)));
+
+            TestApp {
+                pg_pool,
+                session_store,
+                password_hasher,
+                router,
+            }
+        }
+
+        /// Creates the user and logs them in with their password, handing
+        /// back their session cookie
+        async fn sign_up(&self, username: &str) -> String
+        {
+            let password = self
+                .password_hasher
+                .hash(String::from(PASSWORD))
+                .await
+                .unwrap();
+            let user = sqlx::query!(
+                r#"INSERT INTO "users"(username, password) VALUES ($1, $2) RETURNING user_id"#,
+                username,
+                password
+            )
+            .fetch_one(&self.pg_pool)
+            .await
+            .unwrap();
+
+            let mut session = session::Session::new(session::Expiry::new(
+                Duration::from_secs(60 * 60),
+                Duration::from_secs(60 * 60),
+            ));
+            session.insert("user_id", user.user_id).await.unwrap();
+            let cookie = self.session_store.store_session(session).await.unwrap();
+
+            format!("{}={}", session::SESSION_COOKIE_NAME, cookie.unwrap())
+        }
+
+        /// Sends the request with the cookie, if any, handing back the status,
+        /// the cookie the response set, if any, and the JSON body, or `null`
+        /// when there is none
+        async fn send(
+            &mut self,
+            method: http::Method,
+            uri: &str,
+            cookie: Option<&str>,
+            body: Value,
+        ) -> (http::StatusCode, Option<String>, Value)
+        {
+            let mut req = http::Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(http::header::CONTENT_TYPE, "application/json");
+            if let Some(cookie) = cookie {
+                req = req.header(http::header::COOKIE, cookie);
+            }
+            let mut req = req.body(Body::from(body.to_string())).unwrap();
+            let _prev_value = req
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4000))));
+
+            let res = self.router.call(req).await.unwrap();
+            let status = res.status();
+            let set_cookie = res.headers().get(http::header::SET_COOKIE).map(|value| {
+                let value = value.to_str().unwrap();
+                String::from(value.split(';').next().unwrap())
+            });
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+
+            (status, set_cookie, body)
+        }
+
+        /// Registers a passkey held by the authenticator through both steps
+        async fn register(
+            &mut self,
+            cookie: &str,
+            authenticator: &mut WebauthnAuthenticator<SoftPasskey>,
+        ) -> Value
+        {
+            let (status, _set_cookie, challenge) = self
+                .send(
+                    http::Method::POST,
+                    "/users/me/passkeys/challenge",
+                    Some(cookie),
+                    json!({ "currentPassword": PASSWORD }),
+                )
+                .await;
+            assert_eq!(status, http::StatusCode::OK);
+
+            let challenge = serde_json::from_value::<CreationChallengeResponse>(challenge).unwrap();
+            let credential = authenticator.do_registration(origin(), challenge).unwrap();
+
+            let (status, _set_cookie, passkey) = self
+                .send(
+                    http::Method::POST,
+                    "/users/me/passkeys",
+                    Some(cookie),
+                    json!({
+                        "currentPassword": PASSWORD,
+                        "name": "laptop",
+                        "credential": credential,
+                    }),
+                )
+                .await;
+            assert_eq!(status, http::StatusCode::CREATED);
+
+            passkey
+        }
+    }
+
+    #[sqlx::test]
+    async fn registration_needs_the_password_and_the_challenge_from_the_session(pg_pool: PgPool)
+    {
+        let mut app = TestApp::new(pg_pool.clone());
+        let alice = app.sign_up("alice").await;
+        let mut authenticator = WebauthnAuthenticator::new(SoftPasskey::new());
+
+        let (status, _set_cookie, _body) = app
+            .send(
+                http::Method::POST,
+                "/users/me/passkeys/challenge",
+                Some(&alice),
+                json!({ "currentPassword": "hunter2" }),
+            )
+            .await;
+        assert_eq!(status, http::StatusCode::UNPROCESSABLE_ENTITY);
+
+        let (status, _set_cookie, challenge) = app
+            .send(
+                http::Method::POST,
+                "/users/me/passkeys/challenge",
+                Some(&alice),
+                json!({ "currentPassword": PASSWORD }),
+            )
+            .await;
+        assert_eq!(status, http::StatusCode::OK);
+
+        // The ceremony is kept in the session, not handed to the client
+        let (_name, session_cookie) = alice.split_once('=').unwrap();
+        let session = app
+            .session_store
+            .load_session(session_cookie)
+            .await
+            .unwrap();
+        assert!(session
+            .get::<PasskeyRegistration>(REGISTRATION_KEY)
+            .await
+            .is_some());
+
+        let challenge = serde_json::from_value::<CreationChallengeResponse>(challenge).unwrap();
+        let credential = authenticator.do_registration(origin(), challenge).unwrap();
+        let body = |current_password: &str| {
+            json!({
+                "currentPassword": current_password,
+                "name": "laptop",
+                "credential": credential,
+            })
+        };
+
+        // A wrong password leaves the challenge in place
+        let (status, _set_cookie, _body) = app
+            .send(
+                http::Method::POST,
+                "/users/me/passkeys",
+                Some(&alice),
+                body("hunter2"),
+            )
+            .await;
+        assert_eq!(status, http::StatusCode::UNPROCESSABLE_ENTITY);
+
+        let (status, _set_cookie, passkey) = app
+            .send(
+                http::Method::POST,
+                "/users/me/passkeys",
+                Some(&alice),
+                body(PASSWORD),
+            )
+            .await;
+        assert_eq!(status, http::StatusCode::CREATED);
+        assert_eq!(passkey["name"], "laptop");
+
+        // The challenge is only good once
+        let (status, _set_cookie, _body) = app
+            .send(
+                http::Method::POST,
+                "/users/me/passkeys",
+                Some(&alice),
+                body(PASSWORD),
+            )
+            .await;
+        assert_eq!(status, http::StatusCode::CONFLICT);
+
+        let passkeys = sqlx::query!(r#"SELECT passkey_id, credential FROM "passkeys""#)
+            .fetch_all(&pg_pool)
+            .await
+            .unwrap();
+        assert_eq!(passkeys.len(), 1);
+        assert_eq!(passkeys[0].passkey_id.to_string(), passkey["id"]);
+        assert!(serde_json::from_str::<Passkey>(&passkeys[0].credential).is_ok());
+    }
+
+    #[sqlx::test]
+    async fn registered_passkeys_log_in_on_a_fresh_session(pg_pool: PgPool)
+    {
+        let mut app = TestApp::new(pg_pool.clone());
+        let alice = app.sign_up("alice").await;
+        let mut authenticator = WebauthnAuthenticator::new(SoftPasskey::new());
+        let passkey = app.register(&alice, &mut authenticator).await;
+
+        let (status, pending_cookie, challenge) = app
+            .send(
+                http::Method::POST,
+                "/auth/webauthn/challenge",
+                None,
+                json!({ "username": "alice" }),
+            )
+            .await;
+        assert_eq!(status, http::StatusCode::OK);
+        let pending_cookie = pending_cookie.unwrap();
+
+        let challenge = serde_json::from_value::<RequestChallengeResponse>(challenge).unwrap();
+        let credential = authenticator
+            .do_authentication(origin(), challenge)
+            .unwrap();
+        let credential = serde_json::to_value(credential).unwrap();
+
+        let (status, cookie, _body) = app
+            .send(
+                http::Method::POST,
+                "/auth/webauthn",
+                Some(&pending_cookie),
+                credential.clone(),
+            )
+            .await;
+        assert_eq!(status, http::StatusCode::NO_CONTENT);
+        let cookie = cookie.unwrap();
+        assert_ne!(cookie, pending_cookie);
+
+        let (status, _set_cookie, _body) = app
+            .send(
+                http::Method::GET,
+                "/users/me/passkeys",
+                Some(&cookie),
+                json!({}),
+            )
+            .await;
+        assert_eq!(status, http::StatusCode::OK);
+
+        // The session which held the challenge is gone, and the signature
+        // with it
+        let (status, _set_cookie, _body) = app
+            .send(
+                http::Method::POST,
+                "/auth/webauthn",
+                Some(&pending_cookie),
+                credential,
+            )
+            .await;
+        assert_eq!(status, http::StatusCode::UNAUTHORIZED);
+
+        let used = sqlx::query!(
+            r#"
+                SELECT last_used_at IS NOT NULL as "is_used!"
+                FROM "passkeys"
+                WHERE passkey_id = $1
+            "#,
+            passkey["id"].as_str().unwrap().parse::<Uuid>().unwrap()
+        )
+        .fetch_one(&pg_pool)
+        .await
+        .unwrap();
+        assert!(used.is_used);
+    }
+
+    #[sqlx::test]
+    async fn logins_with_another_users_passkey_are_refused(pg_pool: PgPool)
+    {
+        let mut app = TestApp::new(pg_pool);
+        let alice = app.sign_up("alice").await;
+        let mallory = app.sign_up("mallory").await;
+        let mut authenticator = WebauthnAuthenticator::new(SoftPasskey::new());
+        let mut foreign_authenticator = WebauthnAuthenticator::new(SoftPasskey::new());
+        let _passkey = app.register(&alice, &mut authenticator).await;
+        let _foreign_passkey = app.register(&mallory, &mut foreign_authenticator).await;
+
+        let (_status, _pending_cookie, foreign_challenge) = app
+            .send(
+                http::Method::POST,
+                "/auth/webauthn/challenge",
+                None,
+                json!({ "username": "mallory" }),
+            )
+            .await;
+        let (_status, pending_cookie, _challenge) = app
+            .send(
+                http::Method::POST,
+                "/auth/webauthn/challenge",
+                None,
+                json!({ "username": "alice" }),
+            )
+            .await;
+
+        let foreign_challenge =
+            serde_json::from_value::<RequestChallengeResponse>(foreign_challenge).unwrap();
+        let credential = foreign_authenticator
+            .do_authentication(origin(), foreign_challenge)
+            .unwrap();
+
+        let (status, cookie, _body) = app
+            .send(
+                http::Method::POST,
+                "/auth/webauthn",
+                pending_cookie.as_deref(),
+                serde_json::to_value(credential).unwrap(),
+            )
+            .await;
+        assert_eq!(status, http::StatusCode::UNAUTHORIZED);
+        assert_eq!(cookie, None);
+    }
+}
@@ -0,0 +1,225 @@
+use axum::{
+    extract::{Path, Query},
+    Extension,
+};
+use sqlx::PgPool;
+
+use serde::Serialize;
+
+use crate::http::{posts, session};
+
+/// A user's own posts, leaving out their replies, along with what they
+/// reposted, newest first
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct Feed
+{
+    items: Vec<FeedItem>,
+    /// Fetches the next page when passed as `cursor`
+    next_cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FeedItem
+{
+    post: posts::Post,
+    /// Set when the post shows up for having been reposted by the user
+    reposted_by: Option<posts::Author>,
+    reposted_at: Option<i64>,
+}
+
+/// Reposts of posts which were deleted since are left out, the same as the
+/// posts themselves
+pub(super) async fn fetch_profile_feed(
+    pg_pool: Extension<PgPool>,
+    session::extractor::Viewer(viewer_id): session::extractor::Viewer,
+    Path(username): Path<String>,
+    Query(pagination): Query<posts::Pagination>,
+) -> posts::Result<axum::Json<Feed>>
+{
+    let cursor = pagination.cursor()?;
+    let limit = pagination.limit();
+
+    let user = sqlx::query!(
+        r#"select user_id, username from users where lower(username) = lower($1)"#,
+        username
+    )
+    .fetch_optional(&*pg_pool)
+    .await?
+    .ok_or(posts::Error::UserNotFound { username })?;
+
+    // One entry more than asked for tells whether there is a next page. Each
+    // side of the union is cut to a page on its own first, so that both are
+    // read off their index from the cursor on. Without a cursor, the bound
+    // lies past every entry
+    let mut page = sqlx::query!(
+        r#"
+            WITH "entries" AS (
+                (
+                    SELECT
+                        post_id,
+                        NULL::uuid AS repost_id,
+                        post_id AS entry_id,
+                        created_at AS activity_at
+                    FROM "posts"
+                    WHERE author_id = $1
+                        AND depth = 0
+                        AND deleted_at IS NULL
+                        AND (created_at, post_id) < (
+                            coalesce('epoch'::timestamptz + $2::bigint * interval '1 microsecond', 'infinity'),
+                            $3::uuid
+                        )
+                    ORDER BY created_at DESC, post_id DESC
+                    LIMIT $4
+                )
+                UNION ALL
+                (
+                    SELECT
+                        "reposts".post_id,
+                        "reposts".repost_id,
+                        "reposts".repost_id,
+                        "reposts".created_at
+                    FROM "reposts"
+                    JOIN "posts" ON "posts".post_id = "reposts".post_id
+                    WHERE "reposts".user_id = $1
+                        AND "reposts".quote_post_id IS NULL
+                        AND "posts".deleted_at IS NULL
+                        AND ("reposts".created_at, "reposts".repost_id) < (
+                            coalesce('epoch'::timestamptz + $2::bigint * interval '1 microsecond', 'infinity'),
+                            $3::uuid
+                        )
+                    ORDER BY "reposts".created_at DESC, "reposts".repost_id DESC
+                    LIMIT $4
+                )
+            )
+            SELECT
+                "post_view" as "post!: posts::PostView",
+                liked_by("post_view".post_id, $5) as "liked_by_me!",
+                "entries".repost_id,
+                extract(epoch from "entries".activity_at)::bigint as "activity_at!",
+                (extract(epoch from "entries".activity_at) * 1000000)::bigint as "activity_at_micros!",
+                "entries".entry_id as "entry_id!"
+            FROM "entries"
+            JOIN "post_view" ON "post_view".post_id = "entries".post_id
+            ORDER BY "entries".activity_at DESC, "entries".entry_id DESC
+            LIMIT $4
+        "#,
+        user.user_id,
+        cursor.as_ref().map(|cursor| cursor.at_micros),
+        cursor.as_ref().map(|cursor| cursor.id),
+        limit + 1,
+        viewer_id
+    )
+    .fetch_all(&*pg_pool)
+    .await?;
+
+    let has_next_page = page.len() as i64 > limit;
+    page.truncate(limit as usize);
+    let next_cursor = page.last().filter(|_| has_next_page).map(|last| {
+        posts::Cursor {
+            at_micros: last.activity_at_micros,
+            id: last.entry_id,
+        }
+        .encode()
+    });
+
+    let items = page
+        .into_iter()
+        .filter_map(|row| {
+            let is_repost = row.repost_id.is_some();
+            let post = posts::PostRow {
+                post: row.post,
+                liked_by_me: row.liked_by_me,
+            }
+            .into_post()?;
+
+            Some(FeedItem {
+                post,
+                reposted_by: is_repost.then(|| posts::Author {
+                    id: user.user_id,
+                    username: user.username.clone(),
+                }),
+                reposted_at: is_repost.then_some(row.activity_at),
+            })
+        })
+        .collect();
+
+    Ok(axum::Json(Feed { items, next_cursor }))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use axum::http;
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::http::posts::tests::TestApp;
+
+    /// Follows the cursors to the end, handing back every page
+    async fn fetch_pages(app: &mut TestApp, uri: &str) -> Vec<Value>
+    {
+        let mut pages = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let uri = match &cursor {
+                Some(cursor) => format!("{}&cursor={}", uri, cursor),
+                None => String::from(uri),
+            };
+            let (status, page) = app.send(http::Method::GET, &uri, None, None).await;
+            assert_eq!(status, http::StatusCode::OK);
+
+            cursor = page["nextCursor"].as_str().map(String::from);
+            pages.push(page);
+            if cursor.is_none() {
+                return pages;
+            }
+        }
+    }
+
+    #[sqlx::test]
+    async fn feed_pages_meet_at_the_cursor(pg_pool: PgPool)
+    {
+        let mut app = TestApp::new(pg_pool);
+        let alice = app.sign_up("alice").await;
+        let bob = app.sign_up("bob").await;
+
+        let first_id = app.post(&alice, "first", None).await;
+        let bobs_id = app.post(&bob, "bob's", None).await;
+        let second_id = app.post(&alice, "second", None).await;
+        // Replies are left out of the feed
+        let _reply_id = app.post(&alice, "reply", Some(&bobs_id)).await;
+        let uri = format!("/posts/{}/reposts", bobs_id);
+        let (status, _repost) = app
+            .send(http::Method::POST, &uri, Some(&alice), Some(json!({})))
+            .await;
+        assert_eq!(status, http::StatusCode::CREATED);
+        let third_id = app.post(&alice, "third", None).await;
+
+        let pages = fetch_pages(&mut app, "/users/alice/posts?limit=2").await;
+        assert_eq!(pages.len(), 2);
+        let items = pages
+            .iter()
+            .flat_map(|page| page["items"].as_array().unwrap())
+            .collect::<Vec<_>>();
+        let ids = items
+            .iter()
+            .map(|item| item["post"]["id"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            [
+                third_id.as_str(),
+                bobs_id.as_str(),
+                second_id.as_str(),
+                first_id.as_str()
+            ]
+        );
+
+        // The repost says who made it, while the post keeps its own author
+        assert_eq!(items[1]["repostedBy"]["username"], "alice");
+        assert_eq!(items[1]["post"]["author"]["username"], "bob");
+        assert_eq!(items[0]["repostedBy"], Value::Null);
+    }
+}
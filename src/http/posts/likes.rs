@@ -0,0 +1,419 @@
+use axum::{
+    extract::{Path, Query},
+    http, Extension,
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use serde::Serialize;
+
+use crate::http::{posts, session, tokens};
+
+/// The users who liked a post, most recent like first
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct PostLikes
+{
+    items: Vec<PostLike>,
+    /// Fetches the next page when passed as `cursor`
+    next_cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PostLike
+{
+    user: posts::Author,
+    liked_at: i64,
+}
+
+/// The posts a user liked, most recent like first
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct UserLikes
+{
+    items: Vec<UserLike>,
+    /// Fetches the next page when passed as `cursor`
+    next_cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UserLike
+{
+    post: posts::Post,
+    liked_at: i64,
+}
+
+/// Liking a post which is already liked changes nothing. The like and the
+/// count it adds to are written in a single statement, so that the count
+/// only goes up for likes which were not there yet, however many requests
+/// race
+pub(super) async fn like_post(
+    pg_pool: Extension<PgPool>,
+    user_id: session::extractor::UserId,
+    Path(post_id): Path<Uuid>,
+) -> posts::Result<http::StatusCode>
+{
+    let user_id = match user_id {
+        session::extractor::UserId::Found(user_id, auth_method)
+            if auth_method.allows(tokens::Scope::Write) =>
+        {
+            user_id
+        }
+        _ => return Err(posts::Error::MustBeAuthenticated),
+    };
+
+    let liked = sqlx::query!(
+        r#"
+            WITH "like" AS (
+                INSERT INTO "likes"(user_id, post_id)
+                SELECT $1, post_id
+                FROM "posts"
+                WHERE post_id = $2 AND deleted_at IS NULL
+                ON CONFLICT DO NOTHING
+                RETURNING post_id
+            )
+            UPDATE "posts"
+            SET like_count = like_count + 1
+            FROM "like"
+            WHERE "posts".post_id = "like".post_id
+        "#,
+        user_id,
+        post_id
+    )
+    .execute(&*pg_pool)
+    .await?;
+
+    // Nothing being liked is either down to the like being there already or
+    // to there being no post to like
+    if liked.rows_affected() == 0 {
+        let _post = sqlx::query!(
+            r#"select post_id from "posts" where post_id = $1 and deleted_at is null"#,
+            post_id
+        )
+        .fetch_optional(&*pg_pool)
+        .await?
+        .ok_or(posts::Error::PostNotFound { id: post_id })?;
+    }
+
+    Ok(http::StatusCode::NO_CONTENT)
+}
+
+/// Unliking a post which is not liked changes nothing, the same as liking
+/// one twice
+pub(super) async fn unlike_post(
+    pg_pool: Extension<PgPool>,
+    user_id: session::extractor::UserId,
+    Path(post_id): Path<Uuid>,
+) -> posts::Result<http::StatusCode>
+{
+    let user_id = match user_id {
+        session::extractor::UserId::Found(user_id, auth_method)
+            if auth_method.allows(tokens::Scope::Write) =>
+        {
+            user_id
+        }
+        _ => return Err(posts::Error::MustBeAuthenticated),
+    };
+
+    let _pg_query_res = sqlx::query!(
+        r#"
+            WITH "like" AS (
+                DELETE FROM "likes"
+                WHERE user_id = $1 AND post_id = $2
+                RETURNING post_id
+            )
+            UPDATE "posts"
+            SET like_count = like_count - 1
+            FROM "like"
+            WHERE "posts".post_id = "like".post_id
+        "#,
+        user_id,
+        post_id
+    )
+    .execute(&*pg_pool)
+    .await?;
+
+    Ok(http::StatusCode::NO_CONTENT)
+}
+
+/// Likes are public, the same as the posts themselves
+pub(super) async fn fetch_post_likes(
+    pg_pool: Extension<PgPool>,
+    Path(post_id): Path<Uuid>,
+    Query(pagination): Query<posts::Pagination>,
+) -> posts::Result<axum::Json<PostLikes>>
+{
+    let cursor = pagination.cursor()?;
+    let limit = pagination.limit();
+
+    let _post = sqlx::query!(
+        r#"select post_id from "posts" where post_id = $1 and deleted_at is null"#,
+        post_id
+    )
+    .fetch_optional(&*pg_pool)
+    .await?
+    .ok_or(posts::Error::PostNotFound { id: post_id })?;
+
+    // One like more than asked for tells whether there is a next page
+    let mut page = sqlx::query!(
+        r#"
+            SELECT
+                "users".user_id,
+                "users".username,
+                extract(epoch from "likes".created_at)::bigint as "liked_at!",
+                (extract(epoch from "likes".created_at) * 1000000)::bigint as "liked_at_micros!"
+            FROM "likes"
+            JOIN "users" ON "users".user_id = "likes".user_id
+            WHERE "likes".post_id = $1
+                -- Without a cursor, the bound lies past every like
+                AND ("likes".created_at, "likes".user_id) < (
+                    coalesce('epoch'::timestamptz + $2::bigint * interval '1 microsecond', 'infinity'),
+                    $3::uuid
+                )
+            ORDER BY "likes".created_at DESC, "likes".user_id DESC
+            LIMIT $4
+        "#,
+        post_id,
+        cursor.as_ref().map(|cursor| cursor.at_micros),
+        cursor.as_ref().map(|cursor| cursor.id),
+        limit + 1
+    )
+    .fetch_all(&*pg_pool)
+    .await?;
+
+    let has_next_page = page.len() as i64 > limit;
+    page.truncate(limit as usize);
+    let next_cursor = page.last().filter(|_| has_next_page).map(|last| {
+        posts::Cursor {
+            at_micros: last.liked_at_micros,
+            id: last.user_id,
+        }
+        .encode()
+    });
+
+    let items = page
+        .into_iter()
+        .map(|row| PostLike {
+            user: posts::Author {
+                id: row.user_id,
+                username: row.username,
+            },
+            liked_at: row.liked_at,
+        })
+        .collect();
+
+    Ok(axum::Json(PostLikes { items, next_cursor }))
+}
+
+/// Likes of posts which were deleted since are left out
+pub(super) async fn fetch_user_likes(
+    pg_pool: Extension<PgPool>,
+    session::extractor::Viewer(viewer_id): session::extractor::Viewer,
+    Path(username): Path<String>,
+    Query(pagination): Query<posts::Pagination>,
+) -> posts::Result<axum::Json<UserLikes>>
+{
+    let cursor = pagination.cursor()?;
+    let limit = pagination.limit();
+
+    let user = sqlx::query!(
+        r#"select user_id from users where lower(username) = lower($1)"#,
+        username
+    )
+    .fetch_optional(&*pg_pool)
+    .await?
+    .ok_or(posts::Error::UserNotFound { username })?;
+
+    // One like more than asked for tells whether there is a next page
+    let mut page = sqlx::query!(
+        r#"
+            SELECT
+                "post_view" as "post!: posts::PostView",
+                liked_by("likes".post_id, $5) as "liked_by_me!",
+                "likes".post_id,
+                extract(epoch from "likes".created_at)::bigint as "liked_at!",
+                (extract(epoch from "likes".created_at) * 1000000)::bigint as "liked_at_micros!"
+            FROM "likes"
+            JOIN "posts" ON "posts".post_id = "likes".post_id
+            JOIN "post_view" ON "post_view".post_id = "likes".post_id
+            WHERE "likes".user_id = $1
+                AND "posts".deleted_at IS NULL
+                -- Without a cursor, the bound lies past every like
+                AND ("likes".created_at, "likes".post_id) < (
+                    coalesce('epoch'::timestamptz + $2::bigint * interval '1 microsecond', 'infinity'),
+                    $3::uuid
+                )
+            ORDER BY "likes".created_at DESC, "likes".post_id DESC
+            LIMIT $4
+        "#,
+        user.user_id,
+        cursor.as_ref().map(|cursor| cursor.at_micros),
+        cursor.as_ref().map(|cursor| cursor.id),
+        limit + 1,
+        viewer_id
+    )
+    .fetch_all(&*pg_pool)
+    .await?;
+
+    let has_next_page = page.len() as i64 > limit;
+    page.truncate(limit as usize);
+    let next_cursor = page.last().filter(|_| has_next_page).map(|last| {
+        posts::Cursor {
+            at_micros: last.liked_at_micros,
+            id: last.post_id,
+        }
+        .encode()
+    });
+
+    let items = page
+        .into_iter()
+        .filter_map(|row| {
+            let post = posts::PostRow {
+                post: row.post,
+                liked_by_me: row.liked_by_me,
+            }
+            .into_post()?;
+
+            Some(UserLike {
+                post,
+                liked_at: row.liked_at,
+            })
+        })
+        .collect();
+
+    Ok(axum::Json(UserLikes { items, next_cursor }))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use serde_json::Value;
+
+    use super::*;
+    use crate::http::posts::tests::TestApp;
+
+    async fn like_count(app: &mut TestApp, post_id: &str, cookie: Option<&str>) -> (i64, bool)
+    {
+        let uri = format!("/posts/{}", post_id);
+        let (status, post) = app.send(http::Method::GET, &uri, cookie, None).await;
+        assert_eq!(status, http::StatusCode::OK);
+
+        (
+            post["likeCount"].as_i64().unwrap(),
+            post["likedByMe"].as_bool().unwrap(),
+        )
+    }
+
+    async fn send_like(app: &mut TestApp, method: http::Method, post_id: &str, cookie: &str)
+    {
+        let uri = format!("/posts/{}/like", post_id);
+        let (status, _body) = app.send(method, &uri, Some(cookie), None).await;
+        assert_eq!(status, http::StatusCode::NO_CONTENT);
+    }
+
+    /// Follows the cursors to the end, handing back the items of every page
+    /// along with how many pages there were
+    async fn fetch_all(app: &mut TestApp, uri: &str) -> (Vec<Value>, usize)
+    {
+        let mut items = Vec::new();
+        let mut pages = 0;
+        let mut cursor: Option<String> = None;
+        loop {
+            let uri = match &cursor {
+                Some(cursor) => format!("{}&cursor={}", uri, cursor),
+                None => String::from(uri),
+            };
+            let (status, page) = app.send(http::Method::GET, &uri, None, None).await;
+            assert_eq!(status, http::StatusCode::OK);
+
+            pages += 1;
+            items.extend(page["items"].as_array().unwrap().iter().cloned());
+            cursor = page["nextCursor"].as_str().map(String::from);
+            if cursor.is_none() {
+                return (items, pages);
+            }
+        }
+    }
+
+    #[sqlx::test]
+    async fn liking_twice_counts_once(pg_pool: PgPool)
+    {
+        let mut app = TestApp::new(pg_pool);
+        let alice = app.sign_up("alice").await;
+        let bob = app.sign_up("bob").await;
+
+        let post_id = app.post(&alice, "hello", None).await;
+        send_like(&mut app, http::Method::POST, &post_id, &bob).await;
+        send_like(&mut app, http::Method::POST, &post_id, &bob).await;
+
+        assert_eq!(like_count(&mut app, &post_id, Some(&bob)).await, (1, true));
+        assert_eq!(
+            like_count(&mut app, &post_id, Some(&alice)).await,
+            (1, false)
+        );
+        assert_eq!(like_count(&mut app, &post_id, None).await, (1, false));
+    }
+
+    #[sqlx::test]
+    async fn posts_can_be_liked_again_once_unliked(pg_pool: PgPool)
+    {
+        let mut app = TestApp::new(pg_pool);
+        let alice = app.sign_up("alice").await;
+        let bob = app.sign_up("bob").await;
+
+        let post_id = app.post(&alice, "hello", None).await;
+        send_like(&mut app, http::Method::POST, &post_id, &bob).await;
+        send_like(&mut app, http::Method::DELETE, &post_id, &bob).await;
+        assert_eq!(like_count(&mut app, &post_id, Some(&bob)).await, (0, false));
+
+        // Unliking what is not liked leaves the count alone
+        send_like(&mut app, http::Method::DELETE, &post_id, &bob).await;
+        assert_eq!(like_count(&mut app, &post_id, Some(&bob)).await, (0, false));
+
+        send_like(&mut app, http::Method::POST, &post_id, &bob).await;
+        assert_eq!(like_count(&mut app, &post_id, Some(&bob)).await, (1, true));
+    }
+
+    #[sqlx::test]
+    async fn like_pages_meet_at_the_cursor(pg_pool: PgPool)
+    {
+        let mut app = TestApp::new(pg_pool);
+        let alice = app.sign_up("alice").await;
+
+        let post_id = app.post(&alice, "hello", None).await;
+        let mut likers = Vec::new();
+        for username in ["bob", "carol", "dave"] {
+            let cookie = app.sign_up(username).await;
+            send_like(&mut app, http::Method::POST, &post_id, &cookie).await;
+            likers.push(username);
+        }
+
+        let uri = format!("/posts/{}/likes?limit=2", post_id);
+        let (items, pages) = fetch_all(&mut app, &uri).await;
+        assert_eq!(pages, 2);
+        let usernames = items
+            .iter()
+            .map(|item| item["user"]["username"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        likers.reverse();
+        assert_eq!(usernames, likers);
+
+        let mut post_ids = Vec::new();
+        for body in ["first", "second", "third"] {
+            let post_id = app.post(&alice, body, None).await;
+            send_like(&mut app, http::Method::POST, &post_id, &alice).await;
+            post_ids.push(post_id);
+        }
+
+        let (items, pages) = fetch_all(&mut app, "/users/alice/likes?limit=2").await;
+        assert_eq!(pages, 2);
+        let liked_ids = items
+            .iter()
+            .map(|item| item["post"]["id"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        post_ids.reverse();
+        assert_eq!(liked_ids, post_ids);
+    }
+}
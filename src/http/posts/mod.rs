@@ -0,0 +1,698 @@
+use axum::{
+    extract::Path,
+    http, response,
+    routing::{get, post},
+    Extension, Router,
+};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use thiserror::Error;
+
+use crate::http::{api_error, json, session, tokens};
+
+mod feed;
+mod likes;
+mod reposts;
+mod thread;
+
+/// Counted in characters rather than bytes, so that every script gets as much
+/// room
+const MAX_BODY_LEN: usize = 280;
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 50;
+
+pub(in crate::http) fn router() -> Router
+{
+    Router::new()
+        .route("/posts", post(create_post))
+        .route("/posts/:id", get(fetch_post).delete(delete_post))
+        .route(
+            "/posts/:id/like",
+            post(likes::like_post).delete(likes::unlike_post),
+        )
+        .route("/posts/:id/likes", get(likes::fetch_post_likes))
+        .route(
+            "/posts/:id/reposts",
+            post(reposts::create_repost).delete(reposts::delete_repost),
+        )
+        .route("/posts/:id/thread", get(thread::fetch_thread))
+        .route("/users/:username/posts", get(feed::fetch_profile_feed))
+        .route("/users/:username/likes", get(likes::fetch_user_likes))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Author
+{
+    id: Uuid,
+    username: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Post
+{
+    id: Uuid,
+    author: Author,
+    body: String,
+    in_reply_to: Option<Uuid>,
+    conversation_id: Uuid,
+    /// The post this one quotes, if it is a quote
+    quoted_post_id: Option<Uuid>,
+    reply_count: i32,
+    repost_count: i32,
+    quote_count: i32,
+    like_count: i32,
+    /// Always false unless the post is fetched by a logged in user
+    liked_by_me: bool,
+    created_at: i64,
+    edited_at: Option<i64>,
+}
+
+/// A post as read whole from `post_view`, which may have been deleted
+#[derive(sqlx::Type)]
+#[sqlx(type_name = "post_view")]
+struct PostView
+{
+    post_id: Uuid,
+    author_id: Uuid,
+    username: String,
+    body: Option<String>,
+    in_reply_to: Option<Uuid>,
+    conversation_id: Uuid,
+    quoted_post_id: Option<Uuid>,
+    reply_count: i32,
+    repost_count: i32,
+    quote_count: i32,
+    like_count: i32,
+    created_at: i64,
+    edited_at: Option<i64>,
+    /// The direct replies which show in threads, deleted ones included as
+    /// long as anything below them is not
+    thread_reply_count: i32,
+}
+
+/// A post along with whether whoever fetched it liked it, as selected with
+/// `"post_view" as "post!: PostView"` and `liked_by("post_view".post_id,
+/// viewer_id) as "liked_by_me!"`
+struct PostRow
+{
+    post: PostView,
+    liked_by_me: bool,
+}
+
+impl PostRow
+{
+    /// Deleted posts have nothing left to show
+    fn into_post(self) -> Option<Post>
+    {
+        let PostRow { post, liked_by_me } = self;
+
+        Some(Post {
+            id: post.post_id,
+            author: Author {
+                id: post.author_id,
+                username: post.username,
+            },
+            body: post.body?,
+            in_reply_to: post.in_reply_to,
+            conversation_id: post.conversation_id,
+            quoted_post_id: post.quoted_post_id,
+            reply_count: post.reply_count,
+            repost_count: post.repost_count,
+            quote_count: post.quote_count,
+            like_count: post.like_count,
+            liked_by_me,
+            created_at: post.created_at,
+            edited_at: post.edited_at,
+        })
+    }
+}
+
+/// Where a page of a list ordered newest first stops, with an id to break
+/// ties between entries made at the same time
+///
+/// Timestamps are kept to the microsecond, as Postgres stores them, and are
+/// turned back into one with `'epoch'::timestamptz + $n * interval '1
+/// microsecond'`, which is exact, so that the keyset compares the indexed
+/// column itself
+struct Cursor
+{
+    at_micros: i64,
+    id: Uuid,
+}
+
+impl Cursor
+{
+    fn encode(&self) -> String
+    {
+        base64::encode_config(
+            format!("{}:{}", self.at_micros, self.id),
+            base64::URL_SAFE_NO_PAD,
+        )
+    }
+
+    fn decode(cursor: &str) -> Option<Self>
+    {
+        let cursor = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD).ok()?;
+        let cursor = String::from_utf8(cursor).ok()?;
+        let (at_micros, id) = cursor.split_once(':')?;
+
+        Some(Cursor {
+            at_micros: at_micros.parse().ok()?,
+            id: id.parse().ok()?,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct Pagination
+{
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+impl Pagination
+{
+    fn cursor(&self) -> Result<Option<Cursor>>
+    {
+        match &self.cursor {
+            Some(cursor) => Ok(Some(Cursor::decode(cursor).ok_or(Error::InvalidCursor)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn limit(&self) -> i64
+    {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreatePost
+{
+    body: String,
+    in_reply_to: Option<Uuid>,
+}
+
+async fn create_post(
+    pg_pool: Extension<PgPool>,
+    user_id: session::extractor::UserId,
+    json::extractor::Json(req): json::extractor::Json<CreatePost>,
+) -> Result<(http::StatusCode, axum::Json<Post>)>
+{
+    let user_id = match user_id {
+        session::extractor::UserId::Found(user_id, auth_method)
+            if auth_method.allows(tokens::Scope::Write) =>
+        {
+            user_id
+        }
+        _ => return Err(Error::MustBeAuthenticated),
+    };
+
+    let body = validate_body(&req.body)?;
+
+    let mut tx = pg_pool.begin().await?;
+    let post = insert_post(&mut tx, user_id, body, req.in_reply_to).await?;
+    tx.commit().await?;
+
+    Ok((http::StatusCode::CREATED, axum::Json(post)))
+}
+
+/// Trims the body, which must then fit the length limit
+fn validate_body(body: &str) -> Result<String>
+{
+    let body = String::from(body.trim());
+    if body.is_empty() {
+        return Err(Error::EmptyBody);
+    }
+    if body.chars().count() > MAX_BODY_LEN {
+        return Err(Error::BodyTooLong);
+    }
+
+    Ok(body)
+}
+
+/// Inserts a post by the user, replying to another one or starting a
+/// conversation of its own
+async fn insert_post(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    body: String,
+    in_reply_to: Option<Uuid>,
+) -> Result<Post>
+{
+    // Deleted posts cannot be replied to, though their existing replies stay
+    let parent = match in_reply_to {
+        Some(parent_id) => Some(
+            sqlx::query!(
+                r#"
+                    UPDATE "posts"
+                    SET reply_count = reply_count + 1, thread_reply_count = thread_reply_count + 1
+                    WHERE post_id = $1 AND deleted_at IS NULL
+                    RETURNING conversation_id, depth
+                "#,
+                parent_id
+            )
+            .fetch_optional(&mut *conn)
+            .await?
+            .ok_or(Error::InvalidReplyTarget { id: parent_id })?,
+        ),
+        None => None,
+    };
+
+    // Root posts start their own conversation, named after themselves
+    let post = sqlx::query!(
+        r#"
+            WITH "post" AS (
+                INSERT INTO "posts"(post_id, author_id, body, in_reply_to, conversation_id, depth)
+                SELECT id, $1, $2, $3, coalesce($4, id), $5
+                FROM gen_random_uuid() AS id
+                RETURNING post_id, author_id, conversation_id, created_at
+            )
+            SELECT
+                "post".post_id as "post_id!",
+                "post".conversation_id as "conversation_id!",
+                "users".username as "username!",
+                extract(epoch from "post".created_at)::bigint as "created_at!"
+            FROM "post"
+            JOIN "users" ON "users".user_id = "post".author_id
+        "#,
+        user_id,
+        body,
+        in_reply_to,
+        parent.as_ref().map(|parent| parent.conversation_id),
+        parent.as_ref().map_or(0, |parent| parent.depth + 1)
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(Post {
+        id: post.post_id,
+        author: Author {
+            id: user_id,
+            username: post.username,
+        },
+        body,
+        in_reply_to,
+        conversation_id: post.conversation_id,
+        quoted_post_id: None,
+        reply_count: 0,
+        repost_count: 0,
+        quote_count: 0,
+        like_count: 0,
+        liked_by_me: false,
+        created_at: post.created_at,
+        edited_at: None,
+    })
+}
+
+/// Posts are public, so anyone may fetch them without logging in. Deleted
+/// ones only show up as tombstones in threads
+async fn fetch_post(
+    pg_pool: Extension<PgPool>,
+    session::extractor::Viewer(viewer_id): session::extractor::Viewer,
+    Path(post_id): Path<Uuid>,
+) -> Result<axum::Json<Post>>
+{
+    let post = sqlx::query_as!(
+        PostRow,
+        r#"
+            SELECT
+                "post_view" as "post!: PostView",
+                liked_by("post_view".post_id, $2) as "liked_by_me!"
+            FROM "post_view"
+            WHERE "post_view".post_id = $1
+        "#,
+        post_id,
+        viewer_id
+    )
+    .fetch_optional(&*pg_pool)
+    .await?
+    .and_then(PostRow::into_post)
+    .ok_or(Error::PostNotFound { id: post_id })?;
+
+    Ok(axum::Json(post))
+}
+
+/// Only the author may delete a post. Anyone else is told it does not exist,
+/// the same as for posts which really do not
+///
+/// The post is kept as a tombstone, so that the replies it got stay in their
+/// thread. A quote stops counting as one for the post it quoted
+async fn delete_post(
+    pg_pool: Extension<PgPool>,
+    user_id: session::extractor::UserId,
+    Path(post_id): Path<Uuid>,
+) -> Result<http::StatusCode>
+{
+    let user_id = match user_id {
+        session::extractor::UserId::Found(user_id, auth_method)
+            if auth_method.allows(tokens::Scope::Write) =>
+        {
+            user_id
+        }
+        _ => return Err(Error::MustBeAuthenticated),
+    };
+
+    let mut tx = pg_pool.begin().await?;
+
+    let post = sqlx::query!(
+        r#"
+            UPDATE "posts"
+            SET body = NULL, deleted_at = now()
+            WHERE post_id = $1 AND author_id = $2 AND deleted_at IS NULL
+            RETURNING in_reply_to, thread_reply_count
+        "#,
+        post_id,
+        user_id
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or(Error::PostNotFound { id: post_id })?;
+
+    if let Some(parent_id) = post.in_reply_to {
+        let _pg_query_res = sqlx::query!(
+            r#"UPDATE "posts" SET reply_count = reply_count - 1 WHERE post_id = $1"#,
+            parent_id
+        )
+        .execute(&mut tx)
+        .await?;
+
+        // A tombstone only stays in its thread to hold up the replies below it
+        if post.thread_reply_count == 0 {
+            drop_from_thread(&mut tx, parent_id).await?;
+        }
+    }
+
+    let _pg_query_res = sqlx::query!(
+        r#"
+            WITH "quote" AS (
+                DELETE FROM "reposts"
+                WHERE quote_post_id = $1
+                RETURNING post_id
+            )
+            UPDATE "posts"
+            SET quote_count = quote_count - 1
+            FROM "quote"
+            WHERE "posts".post_id = "quote".post_id
+        "#,
+        post_id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(http::StatusCode::NO_CONTENT)
+}
+
+/// Takes a reply which no longer shows in its thread out of its parent's
+/// count, and so on up for as long as that leaves a tombstone with nothing to
+/// hold up
+async fn drop_from_thread(conn: &mut PgConnection, parent_id: Uuid) -> Result<()>
+{
+    let mut parent_id = parent_id;
+    loop {
+        let parent = sqlx::query!(
+            r#"
+                UPDATE "posts"
+                SET thread_reply_count = thread_reply_count - 1
+                WHERE post_id = $1
+                RETURNING
+                    in_reply_to,
+                    deleted_at IS NOT NULL AND thread_reply_count = 0 as "is_dropped!"
+            "#,
+            parent_id
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        match parent.in_reply_to {
+            Some(grandparent_id) if parent.is_dropped => parent_id = grandparent_id,
+            _ => return Ok(()),
+        }
+    }
+}
+
+type Result<T> = ::core::result::Result<T, Error>;
+
+#[derive(Debug, Error)]
+enum Error
+{
+    #[error("{0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("must be authenticated")]
+    MustBeAuthenticated,
+    #[error("post body must not be empty")]
+    EmptyBody,
+    #[error("post body must be at most {} characters long", MAX_BODY_LEN)]
+    BodyTooLong,
+    #[error("no post with id {id} was found")]
+    PostNotFound
+    {
+        id: Uuid
+    },
+    #[error("no post with id {id} can be replied to")]
+    InvalidReplyTarget
+    {
+        id: Uuid
+    },
+    #[error("cannot repost one's own post")]
+    CannotRepostOwnPost,
+    #[error("the post is already reposted")]
+    AlreadyReposted,
+    #[error("the post is not reposted")]
+    NotReposted,
+    #[error("no user named {username} was found")]
+    UserNotFound
+    {
+        username: String
+    },
+    #[error("invalid cursor")]
+    InvalidCursor,
+}
+
+impl response::IntoResponse for Error
+{
+    fn into_response(self) -> response::Response
+    {
+        let code = match self {
+            Error::EmptyBody => api_error::Code::POST_EMPTY_BODY,
+            Error::BodyTooLong => api_error::Code::POST_BODY_TOO_LONG,
+            Error::InvalidReplyTarget { .. } => api_error::Code::POST_INVALID_REPLY_TARGET,
+            Error::CannotRepostOwnPost => api_error::Code::POST_CANNOT_REPOST_OWN,
+            Error::InvalidCursor => api_error::Code::POST_INVALID_CURSOR,
+            Error::MustBeAuthenticated => return http::StatusCode::UNAUTHORIZED.into_response(),
+            Error::AlreadyReposted => return http::StatusCode::CONFLICT.into_response(),
+            Error::PostNotFound { .. } | Error::NotReposted | Error::UserNotFound { .. } => {
+                return http::StatusCode::NOT_FOUND.into_response()
+            }
+            Error::Sqlx(_) => return http::StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        };
+
+        let payload = json!({
+            "message": self.to_string(),
+            "code": code,
+        });
+
+        (http::StatusCode::UNPROCESSABLE_ENTITY, axum::Json(payload)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::{sync::Arc, time::Duration};
+
+    use axum::body::Body;
+    use serde_json::Value;
+    use tower::Service;
+
+    use super::*;
+
+    /// The posts router along with what it needs to tell who is logged in
+    pub(super) struct TestApp
+    {
+        pg_pool: PgPool,
+        session_store: session::Store,
+        router: Router,
+    }
+
+    impl TestApp
+    {
+        pub(super) fn new(pg_pool: PgPool) -> Self
+        {
+            let session_store: session::Store = Arc::new(session::MemoryStore::new());
+            let router = router()
+                .layer(Extension(pg_pool.clone()))
+                .layer(Extension(session_store.clone()));
+
+            TestApp {
+                pg_pool,
+                session_store,
+                router,
+            }
+        }
+
+        /// Creates the user and logs them in, handing back their session
+        /// cookie
+        pub(super) async fn sign_up(&self, username: &str) -> String
+        {
+            let user = sqlx::query!(
+                r#"INSERT INTO "users"(username, password) VALUES ($1, '') RETURNING user_id"#,
+                username
+            )
+            .fetch_one(&self.pg_pool)
+            .await
+            .unwrap();
+
+            let mut session = session::Session::new(session::Expiry::new(
+                Duration::from_secs(60 * 60),
+                Duration::from_secs(60 * 60),
+            ));
+            session.insert("user_id", user.user_id).await.unwrap();
+            let cookie = self.session_store.store_session(session).await.unwrap();
+
+            format!("{}={}", session::SESSION_COOKIE_NAME, cookie.unwrap())
+        }
+
+        /// Sends the request as whoever the cookie belongs to, if anyone,
+        /// handing back the status along with the JSON body, or `null` when
+        /// there is none
+        pub(super) async fn send(
+            &mut self,
+            method: http::Method,
+            uri: &str,
+            cookie: Option<&str>,
+            body: Option<Value>,
+        ) -> (http::StatusCode, Value)
+        {
+            let mut req = http::Request::builder().method(method).uri(uri);
+            if let Some(cookie) = cookie {
+                req = req.header(http::header::COOKIE, cookie);
+            }
+            let req = match body {
+                Some(body) => req
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.to_string())),
+                None => req.body(Body::empty()),
+            }
+            .unwrap();
+
+            let res = self.router.call(req).await.unwrap();
+            let status = res.status();
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+
+            (status, body)
+        }
+
+        /// Posts the body as whoever the cookie belongs to, handing back the
+        /// ID of the new post
+        pub(super) async fn post(
+            &mut self,
+            cookie: &str,
+            body: &str,
+            in_reply_to: Option<&str>,
+        ) -> String
+        {
+            let (status, post) = self
+                .send(
+                    http::Method::POST,
+                    "/posts",
+                    Some(cookie),
+                    Some(json!({ "body": body, "inReplyTo": in_reply_to })),
+                )
+                .await;
+            assert_eq!(status, http::StatusCode::CREATED);
+
+            String::from(post["id"].as_str().unwrap())
+        }
+    }
+
+    #[sqlx::test]
+    async fn posts_are_created_fetched_and_deleted(pg_pool: PgPool)
+    {
+        let mut app = TestApp::new(pg_pool);
+        let alice = app.sign_up("alice").await;
+        let bob = app.sign_up("bob").await;
+
+        let post_id = app.post(&alice, "  hello world  ", None).await;
+        let uri = format!("/posts/{}", post_id);
+
+        let (status, post) = app.send(http::Method::GET, &uri, None, None).await;
+        assert_eq!(status, http::StatusCode::OK);
+        assert_eq!(post["body"], "hello world");
+        assert_eq!(post["author"]["username"], "alice");
+        assert_eq!(post["conversationId"], post["id"]);
+
+        // Anyone but the author is told the post is not there
+        let (status, _body) = app.send(http::Method::DELETE, &uri, Some(&bob), None).await;
+        assert_eq!(status, http::StatusCode::NOT_FOUND);
+
+        let (status, _body) = app
+            .send(http::Method::DELETE, &uri, Some(&alice), None)
+            .await;
+        assert_eq!(status, http::StatusCode::NO_CONTENT);
+
+        let (status, _body) = app.send(http::Method::GET, &uri, None, None).await;
+        assert_eq!(status, http::StatusCode::NOT_FOUND);
+        let (status, _body) = app
+            .send(http::Method::DELETE, &uri, Some(&alice), None)
+            .await;
+        assert_eq!(status, http::StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    async fn posting_needs_a_login_and_a_valid_body(pg_pool: PgPool)
+    {
+        let mut app = TestApp::new(pg_pool);
+        let alice = app.sign_up("alice").await;
+
+        let (status, _body) = app
+            .send(
+                http::Method::POST,
+                "/posts",
+                None,
+                Some(json!({ "body": "hello" })),
+            )
+            .await;
+        assert_eq!(status, http::StatusCode::UNAUTHORIZED);
+
+        let (status, body) = app
+            .send(
+                http::Method::POST,
+                "/posts",
+                Some(&alice),
+                Some(json!({ "body": "   " })),
+            )
+            .await;
+        assert_eq!(status, http::StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], 900);
+
+        // The limit is in characters, so this fits despite taking up more
+        // bytes than that
+        let body = "é".repeat(MAX_BODY_LEN);
+        let _post_id = app.post(&alice, &body, None).await;
+
+        let body = "a".repeat(MAX_BODY_LEN + 1);
+        let (status, body) = app
+            .send(
+                http::Method::POST,
+                "/posts",
+                Some(&alice),
+                Some(json!({ "body": body })),
+            )
+            .await;
+        assert_eq!(status, http::StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], 910);
+    }
+}
@@ -0,0 +1,266 @@
+use axum::{extract::Path, http, Extension};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use serde::{Deserialize, Serialize};
+
+use crate::http::{json, posts, session, tokens};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct Repost
+{
+    id: Uuid,
+    post_id: Uuid,
+    /// The post the user wrote about it, unless the repost is a plain one
+    quote: Option<posts::Post>,
+    created_at: i64,
+}
+
+#[derive(Deserialize)]
+pub(super) struct CreateRepost
+{
+    /// Makes the repost a quote when given
+    body: Option<String>,
+}
+
+/// Reposts someone else's post, plainly or with a quote. A post can only be
+/// reposted plainly once by the same user, but quoted any number of times
+pub(super) async fn create_repost(
+    pg_pool: Extension<PgPool>,
+    user_id: session::extractor::UserId,
+    Path(post_id): Path<Uuid>,
+    json::extractor::Json(req): json::extractor::Json<CreateRepost>,
+) -> posts::Result<(http::StatusCode, axum::Json<Repost>)>
+{
+    let user_id = match user_id {
+        session::extractor::UserId::Found(user_id, auth_method)
+            if auth_method.allows(tokens::Scope::Write) =>
+        {
+            user_id
+        }
+        _ => return Err(posts::Error::MustBeAuthenticated),
+    };
+
+    let body = match req.body {
+        Some(body) => Some(posts::validate_body(&body)?),
+        None => None,
+    };
+
+    let mut tx = pg_pool.begin().await?;
+
+    // Nothing is written should the post turn out to be the user's own, as
+    // the transaction is rolled back when dropped
+    let reposted = match body {
+        Some(_) => sqlx::query!(
+            r#"
+                UPDATE "posts"
+                SET quote_count = quote_count + 1
+                WHERE post_id = $1 AND deleted_at IS NULL
+                RETURNING author_id
+            "#,
+            post_id
+        )
+        .fetch_optional(&mut tx)
+        .await?
+        .map(|post| post.author_id),
+        None => sqlx::query!(
+            r#"
+                UPDATE "posts"
+                SET repost_count = repost_count + 1
+                WHERE post_id = $1 AND deleted_at IS NULL
+                RETURNING author_id
+            "#,
+            post_id
+        )
+        .fetch_optional(&mut tx)
+        .await?
+        .map(|post| post.author_id),
+    };
+    match reposted {
+        Some(author_id) if author_id == user_id => return Err(posts::Error::CannotRepostOwnPost),
+        Some(_) => {}
+        None => return Err(posts::Error::PostNotFound { id: post_id }),
+    }
+
+    let quote = match body {
+        Some(body) => {
+            let mut quote = posts::insert_post(&mut tx, user_id, body, None).await?;
+            quote.quoted_post_id = Some(post_id);
+            Some(quote)
+        }
+        None => None,
+    };
+
+    let repost = sqlx::query!(
+        r#"
+            INSERT INTO "reposts"(user_id, post_id, quote_post_id)
+            VALUES ($1, $2, $3)
+            RETURNING repost_id, extract(epoch from created_at)::bigint as "created_at!"
+        "#,
+        user_id,
+        post_id,
+        quote.as_ref().map(|quote| quote.id)
+    )
+    .fetch_one(&mut tx)
+    .await
+    .map_err(|err| match err {
+        sqlx::Error::Database(db_err)
+            if db_err.constraint() == Some("reposts_user_id_post_id_key") =>
+        {
+            posts::Error::AlreadyReposted
+        }
+        err => err.into(),
+    })?;
+
+    tx.commit().await?;
+
+    Ok((
+        http::StatusCode::CREATED,
+        axum::Json(Repost {
+            id: repost.repost_id,
+            post_id,
+            quote,
+            created_at: repost.created_at,
+        }),
+    ))
+}
+
+/// Undoes a plain repost. Quotes are undone by deleting them, like any other
+/// post
+pub(super) async fn delete_repost(
+    pg_pool: Extension<PgPool>,
+    user_id: session::extractor::UserId,
+    Path(post_id): Path<Uuid>,
+) -> posts::Result<http::StatusCode>
+{
+    let user_id = match user_id {
+        session::extractor::UserId::Found(user_id, auth_method)
+            if auth_method.allows(tokens::Scope::Write) =>
+        {
+            user_id
+        }
+        _ => return Err(posts::Error::MustBeAuthenticated),
+    };
+
+    let mut tx = pg_pool.begin().await?;
+
+    let _repost = sqlx::query!(
+        r#"
+            DELETE FROM "reposts"
+            WHERE user_id = $1 AND post_id = $2 AND quote_post_id IS NULL
+            RETURNING repost_id
+        "#,
+        user_id,
+        post_id
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or(posts::Error::NotReposted)?;
+
+    let _pg_query_res = sqlx::query!(
+        r#"UPDATE "posts" SET repost_count = repost_count - 1 WHERE post_id = $1"#,
+        post_id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(http::StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::http::posts::tests::TestApp;
+
+    async fn fetch_post(app: &mut TestApp, post_id: &str) -> Value
+    {
+        let uri = format!("/posts/{}", post_id);
+        let (status, post) = app.send(http::Method::GET, &uri, None, None).await;
+        assert_eq!(status, http::StatusCode::OK);
+
+        post
+    }
+
+    #[sqlx::test]
+    async fn plain_reposts_are_once_per_user(pg_pool: PgPool)
+    {
+        let mut app = TestApp::new(pg_pool);
+        let alice = app.sign_up("alice").await;
+        let bob = app.sign_up("bob").await;
+
+        let post_id = app.post(&alice, "hello", None).await;
+        let uri = format!("/posts/{}/reposts", post_id);
+
+        let (status, repost) = app
+            .send(http::Method::POST, &uri, Some(&bob), Some(json!({})))
+            .await;
+        assert_eq!(status, http::StatusCode::CREATED);
+        assert_eq!(repost["quote"], Value::Null);
+        let (status, _body) = app
+            .send(http::Method::POST, &uri, Some(&bob), Some(json!({})))
+            .await;
+        assert_eq!(status, http::StatusCode::CONFLICT);
+
+        // Quotes are not held to the same rule
+        for _ in 0..2 {
+            let (status, repost) = app
+                .send(
+                    http::Method::POST,
+                    &uri,
+                    Some(&bob),
+                    Some(json!({ "body": "look at this" })),
+                )
+                .await;
+            assert_eq!(status, http::StatusCode::CREATED);
+            assert_eq!(repost["quote"]["quotedPostId"], post_id.as_str());
+        }
+
+        let post = fetch_post(&mut app, &post_id).await;
+        assert_eq!(post["repostCount"], 1);
+        assert_eq!(post["quoteCount"], 2);
+
+        let (status, _body) = app.send(http::Method::DELETE, &uri, Some(&bob), None).await;
+        assert_eq!(status, http::StatusCode::NO_CONTENT);
+        let (status, _body) = app.send(http::Method::DELETE, &uri, Some(&bob), None).await;
+        assert_eq!(status, http::StatusCode::NOT_FOUND);
+
+        let post = fetch_post(&mut app, &post_id).await;
+        assert_eq!(post["repostCount"], 0);
+        assert_eq!(post["quoteCount"], 2);
+    }
+
+    #[sqlx::test]
+    async fn reposting_ones_own_post_changes_nothing(pg_pool: PgPool)
+    {
+        let mut app = TestApp::new(pg_pool.clone());
+        let alice = app.sign_up("alice").await;
+
+        let post_id = app.post(&alice, "hello", None).await;
+        let uri = format!("/posts/{}/reposts", post_id);
+
+        for body in [json!({}), json!({ "body": "look at this" })] {
+            let (status, body) = app
+                .send(http::Method::POST, &uri, Some(&alice), Some(body))
+                .await;
+            assert_eq!(status, http::StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(body["code"], 940);
+        }
+
+        let post = fetch_post(&mut app, &post_id).await;
+        assert_eq!(post["repostCount"], 0);
+        assert_eq!(post["quoteCount"], 0);
+
+        // Nor was the quote itself kept
+        let posts = sqlx::query!(r#"SELECT count(*) as "count!" FROM "posts""#)
+            .fetch_one(&pg_pool)
+            .await
+            .unwrap();
+        assert_eq!(posts.count, 1);
+    }
+}
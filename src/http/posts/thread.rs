@@ -0,0 +1,522 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, Query},
+    Extension,
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use serde::{Deserialize, Serialize};
+
+use crate::http::{posts, session};
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 50;
+/// How many levels of replies are shown below the post the thread is fetched
+/// for. Deeper ones are left for fetching the thread of the last shown reply
+const MAX_DEPTH: usize = 3;
+/// How many replies are shown to each reply within the tree, as opposed to
+/// the direct replies to the post, which are paginated instead
+const MAX_NESTED_REPLIES: i64 = 3;
+
+/// The conversation around a post: every post it replies to, up to the root,
+/// and the replies it got
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct Thread
+{
+    /// Oldest first, starting at the root of the conversation
+    ancestors: Vec<ThreadPost>,
+    post: ThreadPost,
+    replies: Vec<Reply>,
+    /// Fetches the next page of direct replies when passed as `cursor`
+    next_cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Reply
+{
+    post: ThreadPost,
+    replies: Vec<Reply>,
+    /// Whether the post got replies beyond those shown, which its own thread
+    /// has
+    has_more_replies: bool,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum ThreadPost
+{
+    // Boxed as tombstones are much smaller
+    Post(Box<posts::Post>),
+    Deleted(Tombstone),
+}
+
+/// Stands in for a deleted post, which nothing is told about besides where it
+/// was in the thread
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Tombstone
+{
+    id: Uuid,
+    deleted: bool,
+    in_reply_to: Option<Uuid>,
+    reply_count: i32,
+}
+
+impl From<posts::PostRow> for ThreadPost
+{
+    fn from(row: posts::PostRow) -> Self
+    {
+        let tombstone = Tombstone {
+            id: row.post.post_id,
+            deleted: true,
+            in_reply_to: row.post.in_reply_to,
+            reply_count: row.post.reply_count,
+        };
+
+        match row.into_post() {
+            Some(post) => ThreadPost::Post(Box::new(post)),
+            None => ThreadPost::Deleted(tombstone),
+        }
+    }
+}
+
+/// Where a page of direct replies stops, in the order they are ranked in.
+/// Nothing it holds changes once a reply is made, so no reply moves across
+/// it between pages
+struct Cursor
+{
+    author_rank: i32,
+    created_at_micros: i64,
+    post_id: Uuid,
+}
+
+impl Cursor
+{
+    fn encode(&self) -> String
+    {
+        base64::encode_config(
+            format!(
+                "{}:{}:{}",
+                self.author_rank, self.created_at_micros, self.post_id
+            ),
+            base64::URL_SAFE_NO_PAD,
+        )
+    }
+
+    fn decode(cursor: &str) -> Option<Self>
+    {
+        let cursor = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD).ok()?;
+        let cursor = String::from_utf8(cursor).ok()?;
+        let mut parts = cursor.split(':');
+
+        let cursor = Cursor {
+            author_rank: parts.next()?.parse().ok()?,
+            created_at_micros: parts.next()?.parse().ok()?,
+            post_id: parts.next()?.parse().ok()?,
+        };
+        if parts.next().is_some() {
+            return None;
+        }
+
+        Some(cursor)
+    }
+}
+
+#[derive(Deserialize)]
+pub(super) struct Pagination
+{
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+/// Replies are ranked with those by the author of the conversation first, as
+/// they tend to carry it on, then oldest first. Ranking by how many replies
+/// they got would let them move between pages as those come in
+pub(super) async fn fetch_thread(
+    pg_pool: Extension<PgPool>,
+    session::extractor::Viewer(viewer_id): session::extractor::Viewer,
+    Path(post_id): Path<Uuid>,
+    Query(pagination): Query<Pagination>,
+) -> posts::Result<axum::Json<Thread>>
+{
+    let cursor = match pagination.cursor {
+        Some(cursor) => Some(Cursor::decode(&cursor).ok_or(posts::Error::InvalidCursor)?),
+        None => None,
+    };
+    let limit = pagination
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    let post = sqlx::query_as!(
+        posts::PostRow,
+        r#"
+            SELECT
+                "post_view" as "post!: posts::PostView",
+                liked_by("post_view".post_id, $2) as "liked_by_me!"
+            FROM "post_view"
+            WHERE "post_view".post_id = $1
+        "#,
+        post_id,
+        viewer_id
+    )
+    .fetch_optional(&*pg_pool)
+    .await?
+    .ok_or(posts::Error::PostNotFound { id: post_id })?;
+
+    let ancestors = sqlx::query_as!(
+        posts::PostRow,
+        r#"
+            WITH RECURSIVE "ancestors" AS (
+                SELECT in_reply_to, 1 AS distance
+                FROM "posts"
+                WHERE post_id = $1
+                UNION ALL
+                SELECT "posts".in_reply_to, "ancestors".distance + 1
+                FROM "posts"
+                JOIN "ancestors" ON "posts".post_id = "ancestors".in_reply_to
+            )
+            SELECT
+                "post_view" as "post!: posts::PostView",
+                liked_by("post_view".post_id, $2) as "liked_by_me!"
+            FROM "ancestors"
+            JOIN "post_view" ON "post_view".post_id = "ancestors".in_reply_to
+            ORDER BY "ancestors".distance DESC
+        "#,
+        post_id,
+        viewer_id
+    )
+    .fetch_all(&*pg_pool)
+    .await?;
+
+    // The root may be gone along with its author's account, in which case no
+    // reply gets ranked first for being by them
+    let conversation_author_id = sqlx::query!(
+        r#"select author_id from "posts" where post_id = $1"#,
+        post.post.conversation_id
+    )
+    .fetch_optional(&*pg_pool)
+    .await?
+    .map(|root| root.author_id);
+
+    // Deleted replies are left out unless something below them is not, which
+    // would be cut off from the thread otherwise. One reply more than asked
+    // for tells whether there is a next page
+    let mut page = sqlx::query!(
+        r#"
+            SELECT
+                "post_view" as "post!: posts::PostView",
+                liked_by("posts".post_id, $7) as "liked_by_me!",
+                CASE WHEN "posts".author_id = $2 THEN 0 ELSE 1 END as "author_rank!",
+                (extract(epoch from "posts".created_at) * 1000000)::bigint as "created_at_micros!"
+            FROM "posts"
+            JOIN "post_view" ON "post_view".post_id = "posts".post_id
+            WHERE "posts".in_reply_to = $1
+                AND ("posts".deleted_at IS NULL OR "posts".thread_reply_count > 0)
+                AND (
+                    $3::int IS NULL
+                    OR (
+                        CASE WHEN "posts".author_id = $2 THEN 0 ELSE 1 END,
+                        (extract(epoch from "posts".created_at) * 1000000)::bigint,
+                        "posts".post_id
+                    ) > ($3::int, $4::bigint, $5::uuid)
+                )
+            ORDER BY
+                CASE WHEN "posts".author_id = $2 THEN 0 ELSE 1 END,
+                "posts".created_at,
+                "posts".post_id
+            LIMIT $6
+        "#,
+        post_id,
+        conversation_author_id,
+        cursor.as_ref().map(|cursor| cursor.author_rank),
+        cursor.as_ref().map(|cursor| cursor.created_at_micros),
+        cursor.as_ref().map(|cursor| cursor.post_id),
+        limit + 1,
+        viewer_id
+    )
+    .fetch_all(&*pg_pool)
+    .await?;
+
+    let has_next_page = page.len() as i64 > limit;
+    page.truncate(limit as usize);
+    let next_cursor = page.last().filter(|_| has_next_page).map(|last| {
+        Cursor {
+            author_rank: last.author_rank,
+            created_at_micros: last.created_at_micros,
+            post_id: last.post.post_id,
+        }
+        .encode()
+    });
+
+    let top_level_replies = page
+        .into_iter()
+        .map(|row| posts::PostRow {
+            post: row.post,
+            liked_by_me: row.liked_by_me,
+        })
+        .collect::<Vec<_>>();
+
+    // Every further level is fetched at once, for all the replies shown on
+    // the level above
+    let mut nested_replies: HashMap<Uuid, Vec<posts::PostRow>> = HashMap::new();
+    let mut parent_ids = top_level_replies
+        .iter()
+        .filter(|reply| reply.post.thread_reply_count > 0)
+        .map(|reply| reply.post.post_id)
+        .collect::<Vec<_>>();
+    for _depth in 1..MAX_DEPTH {
+        if parent_ids.is_empty() {
+            break;
+        }
+
+        let replies = sqlx::query_as!(
+            posts::PostRow,
+            r#"
+                SELECT
+                    post as "post!: posts::PostView",
+                    liked_by_me as "liked_by_me!"
+                FROM (
+                    SELECT
+                        "post_view" as post,
+                        liked_by("posts".post_id, $4) as liked_by_me,
+                        "posts".in_reply_to,
+                        row_number() OVER (
+                            PARTITION BY "posts".in_reply_to
+                            ORDER BY
+                                CASE WHEN "posts".author_id = $2 THEN 0 ELSE 1 END,
+                                "posts".created_at,
+                                "posts".post_id
+                        ) as rank
+                    FROM "posts"
+                    JOIN "post_view" ON "post_view".post_id = "posts".post_id
+                    WHERE "posts".in_reply_to = ANY($1)
+                        AND ("posts".deleted_at IS NULL OR "posts".thread_reply_count > 0)
+                ) AS "ranked"
+                WHERE rank <= $3
+                ORDER BY in_reply_to, rank
+            "#,
+            &parent_ids[..],
+            conversation_author_id,
+            MAX_NESTED_REPLIES,
+            viewer_id
+        )
+        .fetch_all(&*pg_pool)
+        .await?;
+
+        parent_ids = replies
+            .iter()
+            .filter(|reply| reply.post.thread_reply_count > 0)
+            .map(|reply| reply.post.post_id)
+            .collect();
+        for reply in replies {
+            // SAFETY: Only replies were fetched, which all have a parent
+            let parent_id = reply.post.in_reply_to.unwrap();
+            nested_replies.entry(parent_id).or_default().push(reply);
+        }
+    }
+
+    let replies = top_level_replies
+        .into_iter()
+        .map(|reply| build_reply(reply, &mut nested_replies))
+        .collect();
+
+    Ok(axum::Json(Thread {
+        ancestors: ancestors.into_iter().map(ThreadPost::from).collect(),
+        post: post.into(),
+        replies,
+        next_cursor,
+    }))
+}
+
+fn build_reply(
+    row: posts::PostRow,
+    nested_replies: &mut HashMap<Uuid, Vec<posts::PostRow>>,
+) -> Reply
+{
+    let replies = nested_replies
+        .remove(&row.post.post_id)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|reply| build_reply(reply, nested_replies))
+        .collect::<Vec<_>>();
+
+    Reply {
+        // Counted the same way as the replies were picked, tombstones and all
+        has_more_replies: row.post.thread_reply_count as usize > replies.len(),
+        replies,
+        post: row.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use axum::http;
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::http::posts::tests::TestApp;
+
+    async fn fetch(app: &mut TestApp, post_id: &str, query: &str) -> Value
+    {
+        let uri = format!("/posts/{}/thread{}", post_id, query);
+        let (status, thread) = app.send(http::Method::GET, &uri, None, None).await;
+        assert_eq!(status, http::StatusCode::OK);
+
+        thread
+    }
+
+    async fn delete(app: &mut TestApp, cookie: &str, post_id: &str)
+    {
+        let uri = format!("/posts/{}", post_id);
+        let (status, _body) = app
+            .send(http::Method::DELETE, &uri, Some(cookie), None)
+            .await;
+        assert_eq!(status, http::StatusCode::NO_CONTENT);
+    }
+
+    #[sqlx::test]
+    async fn deleted_parents_show_as_tombstones(pg_pool: PgPool)
+    {
+        let mut app = TestApp::new(pg_pool);
+        let alice = app.sign_up("alice").await;
+        let bob = app.sign_up("bob").await;
+
+        let root_id = app.post(&alice, "root", None).await;
+        let parent_id = app.post(&bob, "parent", Some(&root_id)).await;
+        let reply_id = app.post(&alice, "reply", Some(&parent_id)).await;
+        delete(&mut app, &bob, &parent_id).await;
+
+        let thread = fetch(&mut app, &reply_id, "").await;
+        assert_eq!(thread["ancestors"][0]["body"], "root");
+        assert_eq!(thread["ancestors"][1]["id"], parent_id.as_str());
+        assert_eq!(thread["ancestors"][1]["deleted"], true);
+        assert_eq!(thread["ancestors"][1]["body"], Value::Null);
+        assert_eq!(thread["post"]["body"], "reply");
+
+        let thread = fetch(&mut app, &root_id, "").await;
+        let tombstone = &thread["replies"][0];
+        assert_eq!(tombstone["post"]["id"], parent_id.as_str());
+        assert_eq!(tombstone["post"]["deleted"], true);
+        assert_eq!(tombstone["replies"][0]["post"]["body"], "reply");
+        assert_eq!(tombstone["hasMoreReplies"], false);
+    }
+
+    #[sqlx::test]
+    async fn deleted_chains_stay_while_anything_below_is_live(pg_pool: PgPool)
+    {
+        let mut app = TestApp::new(pg_pool);
+        let alice = app.sign_up("alice").await;
+
+        let a = app.post(&alice, "a", None).await;
+        let b = app.post(&alice, "b", Some(&a)).await;
+        let c = app.post(&alice, "c", Some(&b)).await;
+        let d = app.post(&alice, "d", Some(&c)).await;
+        delete(&mut app, &alice, &b).await;
+        delete(&mut app, &alice, &c).await;
+
+        let thread = fetch(&mut app, &a, "").await;
+        let b_reply = &thread["replies"][0];
+        assert_eq!(b_reply["post"]["id"], b.as_str());
+        assert_eq!(b_reply["post"]["deleted"], true);
+        let c_reply = &b_reply["replies"][0];
+        assert_eq!(c_reply["post"]["id"], c.as_str());
+        assert_eq!(c_reply["post"]["deleted"], true);
+        assert_eq!(c_reply["replies"][0]["post"]["body"], "d");
+
+        // Once nothing below them is left, the tombstones go as well
+        delete(&mut app, &alice, &d).await;
+
+        let thread = fetch(&mut app, &a, "").await;
+        assert_eq!(thread["replies"], json!([]));
+    }
+
+    #[sqlx::test]
+    async fn nested_replies_past_the_limit_are_told_of(pg_pool: PgPool)
+    {
+        let mut app = TestApp::new(pg_pool);
+        let alice = app.sign_up("alice").await;
+
+        let root_id = app.post(&alice, "root", None).await;
+        let reply_id = app.post(&alice, "reply", Some(&root_id)).await;
+        for _ in 0..MAX_NESTED_REPLIES {
+            let _nested_id = app.post(&alice, "nested", Some(&reply_id)).await;
+        }
+
+        let thread = fetch(&mut app, &root_id, "").await;
+        assert_eq!(thread["replies"][0]["hasMoreReplies"], false);
+
+        // Deleted replies with nothing below them are not counted, as they are
+        // not shown either
+        let deleted_id = app.post(&alice, "deleted", Some(&reply_id)).await;
+        delete(&mut app, &alice, &deleted_id).await;
+
+        let thread = fetch(&mut app, &root_id, "").await;
+        assert_eq!(thread["replies"][0]["hasMoreReplies"], false);
+
+        let _nested_id = app.post(&alice, "nested", Some(&reply_id)).await;
+
+        let thread = fetch(&mut app, &root_id, "").await;
+        let reply = &thread["replies"][0];
+        assert_eq!(
+            reply["replies"].as_array().unwrap().len(),
+            MAX_NESTED_REPLIES as usize
+        );
+        assert_eq!(reply["hasMoreReplies"], true);
+    }
+
+    #[sqlx::test]
+    async fn replies_are_ranked_and_paged_without_drifting(pg_pool: PgPool)
+    {
+        let mut app = TestApp::new(pg_pool);
+        let alice = app.sign_up("alice").await;
+        let bob = app.sign_up("bob").await;
+        let carol = app.sign_up("carol").await;
+
+        let root_id = app.post(&alice, "root", None).await;
+        let bob_reply = app.post(&bob, "first", Some(&root_id)).await;
+        let carol_reply = app.post(&carol, "second", Some(&root_id)).await;
+        let alice_reply = app.post(&alice, "third", Some(&root_id)).await;
+
+        // The author of the conversation comes first, then the oldest
+        let thread = fetch(&mut app, &root_id, "?limit=2").await;
+        let ids = thread["replies"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|reply| reply["post"]["id"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [alice_reply.as_str(), bob_reply.as_str()]);
+        let cursor = String::from(thread["nextCursor"].as_str().unwrap());
+
+        // Replies coming in between pages move nothing across the cursor
+        for _ in 0..2 {
+            let _nested_id = app.post(&bob, "nested", Some(&carol_reply)).await;
+        }
+
+        let thread = fetch(&mut app, &root_id, &format!("?limit=2&cursor={}", cursor)).await;
+        let ids = thread["replies"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|reply| reply["post"]["id"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [carol_reply.as_str()]);
+        assert_eq!(thread["nextCursor"], Value::Null);
+
+        let (status, _body) = app
+            .send(
+                http::Method::GET,
+                &format!("/posts/{}/thread?cursor=nonsense", root_id),
+                None,
+                None,
+            )
+            .await;
+        assert_eq!(status, http::StatusCode::UNPROCESSABLE_ENTITY);
+    }
+}
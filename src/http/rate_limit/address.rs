@@ -0,0 +1,38 @@
+use crate::http::rate_limit;
+
+/// Limits how many emails may be sent to each address, whoever asks for them,
+/// so that no one can flood an inbox by spreading their requests over many
+/// IPs or accounts
+///
+/// Addresses are counted however they are spelled, and whether or not they
+/// belong to anyone, so running out tells nothing about who has an account
+#[derive(Debug, Clone)]
+pub struct AddressLimiter
+{
+    store: rate_limit::Store,
+    quota: rate_limit::Quota,
+}
+
+impl AddressLimiter
+{
+    pub fn new(store: rate_limit::Store, quota: rate_limit::Quota) -> Self
+    {
+        AddressLimiter { store, quota }
+    }
+
+    /// Counts an email to the address, failing with
+    /// [`rate_limit::Error::TooManyRequests`] once it is past the quota
+    pub(in crate::http) async fn hit(&self, address: &str) -> rate_limit::Result<()>
+    {
+        let key = format!("mail:address:{}", address.to_lowercase());
+
+        let hits = self.store.hit(&key, self.quota.window).await?;
+        if hits.count > self.quota.max_hits {
+            return Err(rate_limit::Error::TooManyRequests {
+                retry_after: hits.reset_in,
+            });
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,272 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http, Extension, RequestPartsExt,
+};
+
+use async_trait::async_trait;
+use thiserror::Error;
+
+use crate::http::rate_limit;
+
+/// The reverse proxies whose word is taken for the address of the client they
+/// forward a request for, written as comma-separated addresses or CIDR
+/// ranges, such as `127.0.0.1,10.0.0.0/8`
+///
+/// Without any, every request is taken to come from its peer address, which
+/// behind a proxy is the proxy's own, so that every client shares a single
+/// rate limit. Trusting an address which is not a proxy lets whoever is behind
+/// it claim to be anyone
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(Arc<[(IpAddr, u8)]>);
+
+impl TrustedProxies
+{
+    fn contains(&self, ip: IpAddr) -> bool
+    {
+        self.0
+            .iter()
+            .any(|(network, prefix_len)| in_network(ip, *network, *prefix_len))
+    }
+
+    /// Follows the chain of addresses the proxies recorded, from the one
+    /// closest to the server, up to the first which is not a trusted proxy.
+    /// `Forwarded` is preferred over `X-Forwarded-For` when a request has both
+    pub fn client_ip(&self, peer: IpAddr, headers: &http::HeaderMap) -> IpAddr
+    {
+        if !self.contains(peer) {
+            return peer;
+        }
+
+        let forwarded = headers
+            .get_all(http::header::FORWARDED)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(name, _value)| name.trim().eq_ignore_ascii_case("for"))
+                    .map_or("", |(_name, value)| value)
+            })
+            .collect::<Vec<_>>();
+        let hops = if forwarded.is_empty() {
+            headers
+                .get_all("x-forwarded-for")
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .collect()
+        } else {
+            forwarded
+        };
+
+        let mut client_ip = peer;
+        for hop in hops.into_iter().rev() {
+            // Whatever a trusted proxy cannot vouch for, such as an obfuscated
+            // identifier, leaves the proxy itself as the client
+            match parse_hop(hop) {
+                Some(ip) => client_ip = ip,
+                None => break,
+            }
+            if !self.contains(client_ip) {
+                break;
+            }
+        }
+
+        client_ip
+    }
+}
+
+impl FromStr for TrustedProxies
+{
+    type Err = InvalidTrustedProxies;
+
+    fn from_str(s: &str) -> ::core::result::Result<Self, Self::Err>
+    {
+        let mut proxies = Vec::new();
+
+        for proxy in s
+            .split(',')
+            .map(str::trim)
+            .filter(|proxy| !proxy.is_empty())
+        {
+            let invalid = || InvalidTrustedProxies(String::from(proxy));
+
+            let (network, prefix_len) = match proxy.split_once('/') {
+                Some((network, prefix_len)) => {
+                    let network = network.parse::<IpAddr>().map_err(|_| invalid())?;
+                    let prefix_len = prefix_len.parse::<u8>().map_err(|_| invalid())?;
+                    (network, prefix_len)
+                }
+                None => {
+                    let ip = proxy.parse::<IpAddr>().map_err(|_| invalid())?;
+                    (ip, max_prefix_len(ip))
+                }
+            };
+            if prefix_len > max_prefix_len(network) {
+                return Err(invalid());
+            }
+
+            proxies.push((network, prefix_len));
+        }
+
+        Ok(TrustedProxies(proxies.into()))
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("`{0}` is neither an IP address nor a CIDR range")]
+pub struct InvalidTrustedProxies(String);
+
+fn max_prefix_len(ip: IpAddr) -> u8
+{
+    match ip {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+fn in_network(ip: IpAddr, network: IpAddr, prefix_len: u8) -> bool
+{
+    match (ip.to_canonical(), network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            let mask = u32::MAX
+                .checked_shl(32 - u32::from(prefix_len))
+                .unwrap_or(0);
+            u32::from(ip) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            let mask = u128::MAX
+                .checked_shl(128 - u32::from(prefix_len))
+                .unwrap_or(0);
+            u128::from(ip) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Reads a node as either proxy header spells it: a bare address, or one
+/// with a port, where IPv6 addresses are bracketed, possibly quoted
+fn parse_hop(hop: &str) -> Option<IpAddr>
+{
+    let hop = hop.trim().trim_matches('"');
+
+    hop.parse::<IpAddr>()
+        .or_else(|_| hop.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .or_else(|_| {
+            hop.strip_prefix('[')
+                .and_then(|hop| hop.strip_suffix(']'))
+                .unwrap_or(hop)
+                .parse::<IpAddr>()
+        })
+        .ok()
+}
+
+/// The address of the client the request came from, as told by the trusted
+/// proxies it went through, if any
+#[derive(Debug, Clone, Copy)]
+pub(in crate::http) struct ClientIp(pub(in crate::http) IpAddr);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+{
+    type Rejection = rate_limit::Error;
+
+    async fn from_request_parts(
+        parts: &mut http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection>
+    {
+        let ConnectInfo(addr) = parts
+            .extract::<ConnectInfo<SocketAddr>>()
+            .await
+            .map_err(|_| rate_limit::Error::MissingConnectInfo)?;
+        let Extension(trusted_proxies) = parts
+            .extract::<Extension<TrustedProxies>>()
+            .await
+            .map_err(|_| rate_limit::Error::MissingTrustedProxiesExtension)?;
+
+        Ok(ClientIp(
+            trusted_proxies.client_ip(addr.ip(), &parts.headers),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> http::HeaderMap
+    {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    http::HeaderName::from_static(name),
+                    http::HeaderValue::from_static(value),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn untrusted_peers_cannot_claim_another_address()
+    {
+        let trusted_proxies = "10.0.0.0/8".parse::<TrustedProxies>().unwrap();
+        let headers = headers(&[("x-forwarded-for", "203.0.113.7")]);
+
+        let peer = "198.51.100.1".parse().unwrap();
+        assert_eq!(trusted_proxies.client_ip(peer, &headers), peer);
+    }
+
+    #[test]
+    fn the_first_untrusted_hop_from_the_right_is_the_client()
+    {
+        let trusted_proxies = "10.0.0.0/8,127.0.0.1".parse::<TrustedProxies>().unwrap();
+        // The leftmost address is whatever the client chose to send
+        let headers = headers(&[("x-forwarded-for", "192.0.2.66, 203.0.113.7, 10.1.2.3")]);
+
+        let client_ip = trusted_proxies.client_ip("127.0.0.1".parse().unwrap(), &headers);
+        assert_eq!(client_ip, "203.0.113.7".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn forwarded_is_preferred_and_may_carry_ports()
+    {
+        let trusted_proxies = "::1".parse::<TrustedProxies>().unwrap();
+        let headers = headers(&[
+            ("forwarded", r#"for="[2001:db8::17]:4711";proto=https"#),
+            ("x-forwarded-for", "203.0.113.7"),
+        ]);
+
+        let client_ip = trusted_proxies.client_ip("::1".parse().unwrap(), &headers);
+        assert_eq!(client_ip, "2001:db8::17".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn obfuscated_hops_leave_the_proxy_as_the_client()
+    {
+        let trusted_proxies = "127.0.0.1".parse::<TrustedProxies>().unwrap();
+        let headers = headers(&[("forwarded", "for=_hidden")]);
+
+        let peer = "127.0.0.1".parse().unwrap();
+        assert_eq!(trusted_proxies.client_ip(peer, &headers), peer);
+    }
+
+    #[test]
+    fn invalid_ranges_are_refused()
+    {
+        assert!("10.0.0.0/33".parse::<TrustedProxies>().is_err());
+        assert!("proxy.internal".parse::<TrustedProxies>().is_err());
+    }
+}
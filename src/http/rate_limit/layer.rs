@@ -0,0 +1,124 @@
+use std::{
+    future::Future,
+    mem,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use axum::{
+    extract::ConnectInfo,
+    http,
+    response::{self, IntoResponse},
+};
+use tower::{Layer, Service};
+
+use crate::http::rate_limit;
+
+/// Limits how many requests each client IP may make to the services it wraps,
+/// answering any beyond the quota with `429 Too Many Requests`. Requests from
+/// trusted proxies count against the client they were forwarded for
+///
+/// Every layer counts under its own `name`, so wrapping several routes with
+/// layers of the same name makes them share a single quota
+#[derive(Debug, Clone)]
+pub struct RateLimitLayer
+{
+    store: rate_limit::Store,
+    trusted_proxies: rate_limit::TrustedProxies,
+    name: &'static str,
+    quota: rate_limit::Quota,
+}
+
+impl RateLimitLayer
+{
+    pub fn new(
+        store: rate_limit::Store,
+        trusted_proxies: rate_limit::TrustedProxies,
+        name: &'static str,
+        quota: rate_limit::Quota,
+    ) -> Self
+    {
+        RateLimitLayer {
+            store,
+            trusted_proxies,
+            name,
+            quota,
+        }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer
+{
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service
+    {
+        RateLimit {
+            inner,
+            store: self.store.clone(),
+            trusted_proxies: self.trusted_proxies.clone(),
+            name: self.name,
+            quota: self.quota,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimit<S>
+{
+    inner: S,
+    store: rate_limit::Store,
+    trusted_proxies: rate_limit::TrustedProxies,
+    name: &'static str,
+    quota: rate_limit::Quota,
+}
+
+impl<S, B> Service<http::Request<B>> for RateLimit<S>
+where
+    S: Service<http::Request<B>, Response = response::Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = response::Response;
+    type Error = S::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>>
+    {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future
+    {
+        // The service that was driven to readiness is the one that has to
+        // handle the request, so it is the clone that stays behind
+        let clone = self.inner.clone();
+        let mut inner = mem::replace(&mut self.inner, clone);
+        let store = self.store.clone();
+        let trusted_proxies = self.trusted_proxies.clone();
+        let name = self.name;
+        let quota = self.quota;
+
+        Box::pin(async move {
+            let ip = match req.extensions().get::<ConnectInfo<SocketAddr>>() {
+                Some(ConnectInfo(addr)) => trusted_proxies.client_ip(addr.ip(), req.headers()),
+                None => return Ok(rate_limit::Error::MissingConnectInfo.into_response()),
+            };
+
+            let hits = match store.hit(&format!("{}:{}", name, ip), quota.window).await {
+                Ok(hits) => hits,
+                Err(err) => return Ok(err.into_response()),
+            };
+            if hits.count > quota.max_hits {
+                let err = rate_limit::Error::TooManyRequests {
+                    retry_after: hits.reset_in,
+                };
+                return Ok(err.into_response());
+            }
+
+            inner.call(req).await
+        })
+    }
+}
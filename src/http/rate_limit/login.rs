@@ -0,0 +1,154 @@
+use std::{net::IpAddr, time::Duration};
+
+use crate::http::rate_limit;
+
+/// How long a subject's past lockouts count towards the length of its next
+/// one
+const LOCKOUT_MEMORY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Throttles password guessing by counting login attempts both per client IP
+/// and per username, so neither spreading guesses over many accounts nor over
+/// many addresses gets around it
+///
+/// Attempts are counted before the credentials are checked, rather than once
+/// they turn out to be wrong, so that a burst of requests racing each other
+/// gets no more guesses than the quota. A subject that runs out of attempts is
+/// locked out, for twice as long as the time before, up to `max_lockout`.
+/// Locks lift on their own once they run out
+#[derive(Debug, Clone)]
+pub struct LoginLimiter
+{
+    store: rate_limit::Store,
+    per_username: rate_limit::Quota,
+    per_ip: rate_limit::Quota,
+    lockout: Duration,
+    max_lockout: Duration,
+}
+
+impl LoginLimiter
+{
+    pub fn new(
+        store: rate_limit::Store,
+        per_username: rate_limit::Quota,
+        per_ip: rate_limit::Quota,
+        lockout: Duration,
+        max_lockout: Duration,
+    ) -> Self
+    {
+        LoginLimiter {
+            store,
+            per_username,
+            per_ip,
+            lockout,
+            max_lockout,
+        }
+    }
+
+    /// Fails with [`rate_limit::Error::TooManyRequests`] while either the IP or
+    /// the username is locked out, without counting anything. Suits steps
+    /// which come before any credential is checked
+    pub(in crate::http) async fn check(&self, ip: IpAddr, username: &str)
+        -> rate_limit::Result<()>
+    {
+        let mut retry_after = None;
+        for subject in subjects(ip, username) {
+            let blocked_for = self.store.blocked_for(&blocked_key(&subject)).await?;
+            retry_after = retry_after.max(blocked_for);
+        }
+
+        match retry_after {
+            Some(retry_after) => Err(rate_limit::Error::TooManyRequests { retry_after }),
+            None => Ok(()),
+        }
+    }
+
+    /// Counts an attempt against both the IP and the username, to be called
+    /// before the credentials are checked. Fails with
+    /// [`rate_limit::Error::TooManyRequests`] when either is locked out, which
+    /// the attempt running out the quota gets them
+    pub(in crate::http) async fn attempt(
+        &self,
+        ip: IpAddr,
+        username: &str,
+    ) -> rate_limit::Result<()>
+    {
+        self.check(ip, username).await?;
+
+        let [ip_subject, username_subject] = subjects(ip, username);
+        for (subject, quota) in [
+            (ip_subject, self.per_ip),
+            (username_subject, self.per_username),
+        ] {
+            let attempts_key = attempts_key(&subject);
+
+            // Only the first attempt past the quota locks the subject out, so
+            // that those racing it do not pile up lockouts. The others are
+            // refused by the check below
+            let attempts = self.store.hit(&attempts_key, quota.window).await?;
+            if attempts.count == quota.max_hits + 1 {
+                let lockouts = self
+                    .store
+                    .hit(&lockouts_key(&subject), LOCKOUT_MEMORY)
+                    .await?;
+
+                self.store
+                    .block(&blocked_key(&subject), self.lockout_for(lockouts.count))
+                    .await?;
+                self.store.clear(&[&attempts_key]).await?;
+            }
+        }
+
+        // Checked once more, as a lockout may have started while the attempt
+        // was being counted
+        self.check(ip, username).await
+    }
+
+    /// Forgets the attempts of the username. The IP only gets the attempt
+    /// which succeeded taken back, as logging into an account of one's own
+    /// would otherwise reset the count of all the other ones
+    pub(in crate::http) async fn record_success(
+        &self,
+        ip: IpAddr,
+        username: &str,
+    ) -> rate_limit::Result<()>
+    {
+        let [ip_subject, username_subject] = subjects(ip, username);
+
+        self.store.release(&attempts_key(&ip_subject)).await?;
+        self.store
+            .clear(&[
+                &attempts_key(&username_subject),
+                &lockouts_key(&username_subject),
+            ])
+            .await
+    }
+
+    fn lockout_for(&self, lockouts: u64) -> Duration
+    {
+        let doublings = u32::try_from(lockouts.saturating_sub(1)).unwrap_or(u32::MAX);
+
+        self.lockout
+            .saturating_mul(2u32.saturating_pow(doublings))
+            .min(self.max_lockout)
+    }
+}
+
+fn subjects(ip: IpAddr, username: &str) -> [String; 2]
+{
+    [format!("ip:{}", ip), format!("username:{}", username)]
+}
+
+fn attempts_key(subject: &str) -> String
+{
+    format!("login:attempts:{}", subject)
+}
+
+fn lockouts_key(subject: &str) -> String
+{
+    format!("login:lockouts:{}", subject)
+}
+
+fn blocked_key(subject: &str) -> String
+{
+    format!("login:blocked:{}", subject)
+}
@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use axum::{http, response};
+
+use thiserror::Error;
+
+mod address;
+mod client_ip;
+mod layer;
+mod login;
+mod store;
+
+pub use address::AddressLimiter;
+pub(in crate::http) use client_ip::ClientIp;
+pub use client_ip::{InvalidTrustedProxies, TrustedProxies};
+pub use layer::{RateLimit, RateLimitLayer};
+pub use login::LoginLimiter;
+pub use store::{Hits, MemoryStore, RateLimitStore, RedisStore, Store};
+
+/// How many hits are allowed within a window of time
+#[derive(Debug, Clone, Copy)]
+pub struct Quota
+{
+    max_hits: u64,
+    window: Duration,
+}
+
+impl Quota
+{
+    pub const fn new(max_hits: u64, window: Duration) -> Self
+    {
+        Quota { max_hits, window }
+    }
+}
+
+pub type Result<T> = ::core::result::Result<T, Error>;
+
+#[derive(Debug, Error)]
+pub enum Error
+{
+    #[error("{0}")]
+    Redis(#[from] redis::RedisError),
+    #[error("timed out waiting for redis")]
+    RedisTimeout,
+    #[error("missing request connection info")]
+    MissingConnectInfo,
+    #[error("missing request trusted proxies extension")]
+    MissingTrustedProxiesExtension,
+    #[error("too many requests, retry after {retry_after:?}")]
+    TooManyRequests
+    {
+        retry_after: Duration
+    },
+}
+
+impl response::IntoResponse for Error
+{
+    fn into_response(self) -> response::Response
+    {
+        match self {
+            Error::Redis(_) | Error::MissingConnectInfo | Error::MissingTrustedProxiesExtension => {
+                http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+            Error::RedisTimeout => http::StatusCode::SERVICE_UNAVAILABLE.into_response(),
+            Error::TooManyRequests { retry_after } => {
+                // `Retry-After` only takes whole seconds, so partial ones are
+                // rounded up rather than inviting a retry that is still early
+                let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+
+                (
+                    http::StatusCode::TOO_MANY_REQUESTS,
+                    [(http::header::RETRY_AFTER, seconds.max(1).to_string())],
+                )
+                    .into_response()
+            }
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_lock::Mutex;
+use async_trait::async_trait;
+
+use crate::http::rate_limit;
+
+/// Keeps counters in the memory of the current process, so limits only hold
+/// per node and are forgotten on restart
+#[derive(Debug, Clone)]
+pub struct MemoryStore
+{
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry
+{
+    count: u64,
+    expires_at: Instant,
+}
+
+impl MemoryStore
+{
+    pub fn new() -> Self
+    {
+        MemoryStore {
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+#[async_trait]
+impl rate_limit::RateLimitStore for MemoryStore
+{
+    async fn hit(&self, key: &str, window: Duration) -> rate_limit::Result<rate_limit::Hits>
+    {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().await;
+
+        // Nothing else evicts entries, so this is where stale ones are dropped
+        entries.retain(|_key, entry| entry.expires_at > now);
+
+        let entry = entries.entry(String::from(key)).or_insert(Entry {
+            count: 0,
+            expires_at: now + window,
+        });
+        entry.count += 1;
+
+        Ok(rate_limit::Hits {
+            count: entry.count,
+            reset_in: entry.expires_at - now,
+        })
+    }
+
+    async fn release(&self, key: &str) -> rate_limit::Result<()>
+    {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().await;
+
+        if let Some(entry) = entries.get_mut(key).filter(|entry| entry.expires_at > now) {
+            entry.count = entry.count.saturating_sub(1);
+        }
+
+        Ok(())
+    }
+
+    async fn block(&self, key: &str, duration: Duration) -> rate_limit::Result<()>
+    {
+        let entry = Entry {
+            count: 1,
+            expires_at: Instant::now() + duration,
+        };
+        let _prev_entry = self.entries.lock().await.insert(String::from(key), entry);
+
+        Ok(())
+    }
+
+    async fn blocked_for(&self, key: &str) -> rate_limit::Result<Option<Duration>>
+    {
+        let now = Instant::now();
+        let entries = self.entries.lock().await;
+
+        Ok(entries
+            .get(key)
+            .filter(|entry| entry.expires_at > now)
+            .map(|entry| entry.expires_at - now))
+    }
+
+    async fn clear(&self, keys: &[&str]) -> rate_limit::Result<()>
+    {
+        let mut entries = self.entries.lock().await;
+        for key in keys {
+            let _entry = entries.remove(*key);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+
+use crate::http::rate_limit;
+
+mod memory;
+mod redis;
+
+pub use self::{memory::MemoryStore, redis::RedisStore};
+
+/// The rate limit store shared by every limiter
+pub type Store = Arc<dyn RateLimitStore>;
+
+/// The state of a counter right after a hit was counted against it
+#[derive(Debug, Clone, Copy)]
+pub struct Hits
+{
+    pub count: u64,
+    /// How long until the counter starts over
+    pub reset_in: Duration,
+}
+
+#[async_trait]
+pub trait RateLimitStore: fmt::Debug + Send + Sync
+{
+    /// Counts a hit against the key, whose count starts over once `window` has
+    /// passed since its first hit
+    async fn hit(&self, key: &str, window: Duration) -> rate_limit::Result<Hits>;
+
+    /// Takes back one hit counted against the key, if it has any left, without
+    /// moving the end of its window
+    async fn release(&self, key: &str) -> rate_limit::Result<()>;
+
+    /// Blocks the key for `duration`, replacing any block already in place
+    async fn block(&self, key: &str, duration: Duration) -> rate_limit::Result<()>;
+
+    /// How much longer the key stays blocked, if it is blocked at all
+    async fn blocked_for(&self, key: &str) -> rate_limit::Result<Option<Duration>>;
+
+    /// Forgets the counters and blocks stored under the keys
+    async fn clear(&self, keys: &[&str]) -> rate_limit::Result<()>;
+}
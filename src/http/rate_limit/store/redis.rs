@@ -0,0 +1,156 @@
+use std::{fmt, future::Future, time::Duration};
+
+use tokio::time;
+
+use async_trait::async_trait;
+use redis::{aio::ConnectionManager, AsyncCommands};
+
+use crate::http::rate_limit;
+
+/// Keeps counters in Redis, so limits hold across every node sharing it. The
+/// commands involved are small enough for a single multiplexed connection,
+/// which transparently reconnects when the connection to Redis drops
+#[derive(Clone)]
+pub struct RedisStore
+{
+    connection: ConnectionManager,
+    timeout: Duration,
+}
+
+impl fmt::Debug for RedisStore
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        f.debug_struct("RedisStore")
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RedisStore
+{
+    pub async fn connect(client: redis::Client, timeout: Duration) -> rate_limit::Result<Self>
+    {
+        let connection = time::timeout(timeout, client.get_tokio_connection_manager())
+            .await
+            .map_err(|_| rate_limit::Error::RedisTimeout)??;
+
+        Ok(RedisStore {
+            connection,
+            timeout,
+        })
+    }
+
+    async fn with_timeout<T>(
+        &self,
+        command: impl Future<Output = redis::RedisResult<T>>,
+    ) -> rate_limit::Result<T>
+    {
+        time::timeout(self.timeout, command)
+            .await
+            .map_err(|_| rate_limit::Error::RedisTimeout)?
+            .map_err(rate_limit::Error::from)
+    }
+}
+
+#[async_trait]
+impl rate_limit::RateLimitStore for RedisStore
+{
+    async fn hit(&self, key: &str, window: Duration) -> rate_limit::Result<rate_limit::Hits>
+    {
+        let key = store_key(key);
+        let mut connection = self.connection.clone();
+
+        // The counter only gets its expiry when it is created, so that the
+        // window is fixed from the first hit rather than sliding with each one
+        let (count, reset_in_ms) = self
+            .with_timeout(
+                redis::pipe()
+                    .atomic()
+                    .cmd("SET")
+                    .arg(&key)
+                    .arg(0)
+                    .arg("PX")
+                    .arg(milliseconds(window))
+                    .arg("NX")
+                    .ignore()
+                    .incr(&key, 1)
+                    .pttl(&key)
+                    .query_async::<_, (u64, i64)>(&mut connection),
+            )
+            .await?;
+
+        Ok(rate_limit::Hits {
+            count,
+            reset_in: u64::try_from(reset_in_ms).map_or(window, Duration::from_millis),
+        })
+    }
+
+    async fn release(&self, key: &str) -> rate_limit::Result<()>
+    {
+        let mut connection = self.connection.clone();
+
+        // DECR alone would bring a counter which expired in the meantime back
+        // as a negative one without any expiry
+        self.with_timeout(
+            redis::Script::new(
+                r"
+                    if redis.call('GET', KEYS[1]) then
+                        redis.call('DECR', KEYS[1])
+                    end
+                ",
+            )
+            .key(store_key(key))
+            .invoke_async::<_, ()>(&mut connection),
+        )
+        .await
+    }
+
+    async fn block(&self, key: &str, duration: Duration) -> rate_limit::Result<()>
+    {
+        let mut connection = self.connection.clone();
+
+        self.with_timeout(connection.pset_ex::<_, _, ()>(store_key(key), 1, milliseconds(duration)))
+            .await
+    }
+
+    async fn blocked_for(&self, key: &str) -> rate_limit::Result<Option<Duration>>
+    {
+        let mut connection = self.connection.clone();
+
+        // PTTL is negative for missing keys
+        let remaining_ms = self
+            .with_timeout(connection.pttl::<_, i64>(store_key(key)))
+            .await?;
+
+        Ok(u64::try_from(remaining_ms)
+            .ok()
+            .filter(|remaining_ms| *remaining_ms > 0)
+            .map(Duration::from_millis))
+    }
+
+    async fn clear(&self, keys: &[&str]) -> rate_limit::Result<()>
+    {
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        let keys = keys.iter().map(|key| store_key(key)).collect::<Vec<_>>();
+        let mut connection = self.connection.clone();
+
+        self.with_timeout(connection.del::<_, ()>(keys)).await
+    }
+}
+
+fn store_key(key: &str) -> String
+{
+    format!("rate_limit:{}", key)
+}
+
+fn milliseconds(duration: Duration) -> usize
+{
+    // Redis rejects an expiry of zero
+    usize::try_from(duration.as_millis())
+        .unwrap_or(usize::MAX)
+        .max(1)
+}
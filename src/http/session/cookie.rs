@@ -0,0 +1,102 @@
+use std::{fmt, str::FromStr, time::Duration};
+
+use axum::http;
+
+use thiserror::Error;
+
+use crate::http::session;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite
+{
+    Strict,
+    Lax,
+    None,
+}
+
+impl fmt::Display for SameSite
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self {
+            SameSite::Strict => f.write_str("Strict"),
+            SameSite::Lax => f.write_str("Lax"),
+            SameSite::None => f.write_str("None"),
+        }
+    }
+}
+
+impl FromStr for SameSite
+{
+    type Err = InvalidSameSite;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s.to_ascii_lowercase().as_str() {
+            "strict" => Ok(SameSite::Strict),
+            "lax" => Ok(SameSite::Lax),
+            "none" => Ok(SameSite::None),
+            _ => Err(InvalidSameSite),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Error)]
+#[error("expected one of Strict, Lax or None")]
+pub struct InvalidSameSite;
+
+/// Builds the `Set-Cookie` header values for the session cookie, so that
+/// every handler issuing or clearing it does so with the same attributes
+#[derive(Debug, Clone)]
+pub struct CookieBuilder
+{
+    secure: bool,
+    same_site: SameSite,
+    path: String,
+    domain: Option<String>,
+}
+
+impl CookieBuilder
+{
+    pub fn new(secure: bool, same_site: SameSite, path: String, domain: Option<String>) -> Self
+    {
+        CookieBuilder {
+            secure,
+            same_site,
+            path,
+            domain,
+        }
+    }
+
+    /// A cookie without a `max_age` lasts until the browser is closed
+    pub(in crate::http) fn build(
+        &self,
+        value: &str,
+        max_age: Option<Duration>,
+    ) -> session::Result<http::HeaderValue>
+    {
+        let mut cookie = format!("{}={}", session::SESSION_COOKIE_NAME, value);
+
+        if let Some(max_age) = max_age {
+            cookie.push_str(&format!("; Max-Age={}", max_age.as_secs()));
+        }
+        cookie.push_str(&format!("; Path={}", self.path));
+        if let Some(domain) = &self.domain {
+            cookie.push_str(&format!("; Domain={}", domain));
+        }
+        cookie.push_str(&format!("; SameSite={}", self.same_site));
+        // Browsers refuse `SameSite=None` cookies that are not also `Secure`
+        if self.secure || self.same_site == SameSite::None {
+            cookie.push_str("; Secure");
+        }
+        cookie.push_str("; HttpOnly");
+
+        http::HeaderValue::from_str(&cookie).map_err(session::Error::from)
+    }
+
+    /// Instructs the browser to discard the session cookie
+    pub(in crate::http) fn expired(&self) -> session::Result<http::HeaderValue>
+    {
+        self.build("", Some(Duration::ZERO))
+    }
+}
@@ -1,21 +1,90 @@
+use std::convert::Infallible;
+
 use axum::{
     extract::{FromRequestParts, TypedHeader},
-    headers::Cookie,
+    headers::{authorization::Bearer, Authorization, Cookie},
     http, Extension, RequestPartsExt,
 };
+use sqlx::PgPool;
 
 use async_trait::async_trait;
 use uuid::Uuid;
 
-use crate::http::session;
+use crate::http::{oauth, session, tokens};
+
+/// The ID of the session the request was made with, without checking that
+/// the session actually exists
+#[derive(Debug, Clone)]
+pub(in crate::http) struct SessionId(pub(in crate::http) Option<String>);
 
-#[derive(Debug, Clone, Copy)]
+#[async_trait]
+impl<S> FromRequestParts<S> for SessionId
+where
+    S: Send + Sync,
+{
+    type Rejection = session::Error;
+
+    async fn from_request_parts(
+        parts: &mut http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection>
+    {
+        let cookie = parts
+            .extract::<Option<TypedHeader<Cookie>>>()
+            .await
+            // SAFETY: Unwrapping `Result<T, Infallible>` is guaranteed to
+            // never panic
+            .unwrap();
+        let session_cookie = cookie
+            .as_ref()
+            .and_then(|cookie| cookie.get(session::SESSION_COOKIE_NAME));
+
+        match session_cookie {
+            Some(session_cookie) => Ok(SessionId(Some(session::Session::id_from_cookie(
+                session_cookie,
+            )?))),
+            None => Ok(SessionId(None)),
+        }
+    }
+}
+
+/// The user the request was made on behalf of, resolved from either an
+/// `Authorization: Bearer` token or the session cookie, in that order
+#[derive(Debug, Clone)]
 pub(in crate::http) enum UserId
 {
-    Found(Uuid),
+    Found(Uuid, AuthMethod),
     NotFound,
 }
 
+#[derive(Debug, Clone)]
+pub(in crate::http) enum AuthMethod
+{
+    Session,
+    PersonalAccessToken
+    {
+        scopes: Vec<tokens::Scope>,
+    },
+    /// An access token granted to a third-party app
+    OAuth
+    {
+        scopes: Vec<tokens::Scope>,
+    },
+}
+
+impl AuthMethod
+{
+    pub(in crate::http) fn allows(&self, scope: tokens::Scope) -> bool
+    {
+        match self {
+            AuthMethod::Session => true,
+            AuthMethod::PersonalAccessToken { scopes } | AuthMethod::OAuth { scopes } => {
+                scopes.contains(&scope)
+            }
+        }
+    }
+}
+
 #[async_trait]
 impl<S> FromRequestParts<S> for UserId
 where
@@ -28,6 +97,40 @@ where
         _state: &S,
     ) -> Result<Self, Self::Rejection>
     {
+        let bearer = parts
+            .extract::<Option<TypedHeader<Authorization<Bearer>>>>()
+            .await
+            // SAFETY: Unwrapping `Result<T, Infallible>` is guaranteed to
+            // never panic
+            .unwrap();
+        // A request carrying a token is never resolved through its cookie,
+        // even when the token turns out to be invalid
+        if let Some(TypedHeader(Authorization(bearer))) = bearer {
+            let pg_pool = parts
+                .extract::<Extension<PgPool>>()
+                .await
+                .map_err(|_| session::Error::MissingPgPoolExtension)?;
+
+            if bearer.token().starts_with(tokens::TOKEN_PREFIX) {
+                if let Some((user_id, scopes)) =
+                    tokens::authenticate(&pg_pool, bearer.token()).await?
+                {
+                    return Ok(UserId::Found(
+                        user_id,
+                        AuthMethod::PersonalAccessToken { scopes },
+                    ));
+                }
+            } else if bearer.token().starts_with(oauth::ACCESS_TOKEN_PREFIX) {
+                if let Some((user_id, scopes)) =
+                    oauth::authenticate(&pg_pool, bearer.token()).await?
+                {
+                    return Ok(UserId::Found(user_id, AuthMethod::OAuth { scopes }));
+                }
+            }
+
+            return Ok(UserId::NotFound);
+        }
+
         let store = parts
             .extract::<Extension<session::Store>>()
             .await
@@ -44,10 +147,11 @@ where
 
         match session_cookie {
             Some(session_cookie) => {
-                let session = store.load_session(session_cookie).await?;
+                let mut session = store.load_session(session_cookie).await?;
+                store.touch_session(&mut session).await?;
 
                 if let Some(user_id) = session.get::<Uuid>("user_id").await {
-                    Ok(UserId::Found(user_id))
+                    Ok(UserId::Found(user_id, AuthMethod::Session))
                 } else {
                     Ok(UserId::NotFound)
                 }
@@ -56,3 +160,31 @@ where
         }
     }
 }
+
+/// The user a public resource is fetched by, which only tailors what is shown
+/// to them. Unlike `UserId`, a stale cookie, an expired session or an invalid
+/// token never fails the request, but leaves it anonymous, the same as a
+/// token without read access does
+#[derive(Debug, Clone)]
+pub(in crate::http) struct Viewer(pub(in crate::http) Option<Uuid>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Viewer
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection>
+    {
+        match UserId::from_request_parts(parts, state).await {
+            Ok(UserId::Found(user_id, auth_method)) if auth_method.allows(tokens::Scope::Read) => {
+                Ok(Viewer(Some(user_id)))
+            }
+            _ => Ok(Viewer(None)),
+        }
+    }
+}
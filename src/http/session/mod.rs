@@ -4,7 +4,7 @@ use std::{
         atomic::{AtomicBool, Ordering},
         Arc, RwLock,
     },
-    time::Duration,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use axum::{http, response};
@@ -13,13 +13,20 @@ use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+mod cookie;
 pub(in crate::http) mod extractor;
 mod store;
 
-pub use store::Store;
+pub use cookie::{CookieBuilder, InvalidSameSite, SameSite};
+pub use store::{MemoryStore, RedisStore, SessionStore, Store};
 
 pub const SESSION_COOKIE_NAME: &str = "bluebird_session";
 
+// Renewing the session on every single request would mean a write to the
+// store per request, so activity is only recorded once this much time has
+// passed since it was last recorded
+const TOUCH_INTERVAL: Duration = Duration::from_secs(60);
+
 pub fn generate_cookie(len: usize) -> String
 {
     let mut key = vec![0u8; len];
@@ -27,11 +34,44 @@ pub fn generate_cookie(len: usize) -> String
     base64::encode(key)
 }
 
+pub(in crate::http) fn unix_timestamp(time: SystemTime) -> u64
+{
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Expiry
+{
+    lifetime: Duration,
+    idle_timeout: Duration,
+}
+
+impl Expiry
+{
+    pub fn new(lifetime: Duration, idle_timeout: Duration) -> Self
+    {
+        Expiry {
+            lifetime,
+            idle_timeout,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Session
 {
     id: String,
+    /// How long the session may go unused before it expires
     expires_in: Option<Duration>,
+    /// The point in time past which the session expires regardless of activity
+    #[serde(default)]
+    expires_at: Option<SystemTime>,
+    #[serde(default = "SystemTime::now")]
+    created_at: SystemTime,
+    #[serde(default = "SystemTime::now")]
+    last_active: SystemTime,
     data: Arc<RwLock<HashMap<String, String>>>,
 
     #[serde(skip)]
@@ -42,17 +82,21 @@ pub struct Session
 
 impl Session
 {
-    pub fn new() -> Self
+    pub fn new(expiry: Expiry) -> Self
     {
         let cookie = generate_cookie(64);
         // SAFETY: This cannot fail as the cookie is not mutated between the
         // base64 encoding and the base64 decoding, which is the only step at
         // which the below call could fail
         let id = Session::id_from_cookie(&cookie).unwrap();
+        let now = SystemTime::now();
 
         Session {
             id,
-            expires_in: None,
+            expires_in: Some(expiry.idle_timeout),
+            expires_at: Some(now + expiry.lifetime),
+            created_at: now,
+            last_active: now,
             data: Arc::new(RwLock::new(HashMap::default())),
 
             cookie_value: Some(cookie),
@@ -60,14 +104,111 @@ impl Session
         }
     }
 
+    /// Moves the session's data under a fresh ID and cookie, restarting its
+    /// lifetime, so that whoever knew the previous cookie loses access to it.
+    /// The record under the previous ID is left for the caller to destroy
+    pub fn regenerate(&mut self, expiry: Expiry)
+    {
+        let cookie = generate_cookie(64);
+        // SAFETY: Same as in `Session::new`
+        let id = Session::id_from_cookie(&cookie).unwrap();
+        let now = SystemTime::now();
+
+        self.id = id;
+        self.expires_in = Some(expiry.idle_timeout);
+        self.expires_at = Some(now + expiry.lifetime);
+        self.created_at = now;
+        self.last_active = now;
+
+        self.cookie_value = Some(cookie);
+        self.data_changed.store(true, Ordering::Relaxed);
+    }
+
+    pub fn id(&self) -> &str
+    {
+        &self.id
+    }
+
+    /// The ID is URL-safe, as it doubles as the handle through which a user
+    /// revokes one of their sessions
     pub fn id_from_cookie(cookie: &str) -> Result<String>
     {
         let decoded = base64::decode(cookie)?;
         let hash = blake3::hash(&decoded);
 
+        Ok(base64::encode_config(
+            hash.as_bytes(),
+            base64::URL_SAFE_NO_PAD,
+        ))
+    }
+
+    /// The ID sessions were stored under before it had to be URL-safe, which
+    /// is only looked up to carry them over. Once every session from before
+    /// has reached the end of its lifetime, this can go
+    pub(in crate::http) fn legacy_id_from_cookie(cookie: &str) -> Result<String>
+    {
+        let decoded = base64::decode(cookie)?;
+        let hash = blake3::hash(&decoded);
+
         Ok(base64::encode(hash.as_bytes()))
     }
 
+    pub fn created_at(&self) -> SystemTime
+    {
+        self.created_at
+    }
+
+    pub fn last_active(&self) -> SystemTime
+    {
+        self.last_active
+    }
+
+    /// Time left until the end of the session's lifetime, ignoring activity
+    pub fn remaining_lifetime(&self) -> Option<Duration>
+    {
+        self.expires_at.map(|expires_at| {
+            expires_at
+                .duration_since(SystemTime::now())
+                .unwrap_or_default()
+        })
+    }
+
+    /// Time left until the session expires, either through inactivity or by
+    /// reaching the end of its lifetime
+    pub fn ttl(&self) -> Option<Duration>
+    {
+        let idle = self.expires_in.map(|expires_in| {
+            (self.last_active + expires_in)
+                .duration_since(SystemTime::now())
+                .unwrap_or_default()
+        });
+
+        match (idle, self.remaining_lifetime()) {
+            (Some(idle), Some(remaining)) => Some(idle.min(remaining)),
+            (idle, remaining) => idle.or(remaining),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool
+    {
+        self.ttl() == Some(Duration::ZERO)
+    }
+
+    /// Records activity on the session, returning whether it has to be
+    /// persisted again for the renewal to take effect
+    pub fn touch(&mut self) -> bool
+    {
+        let now = SystemTime::now();
+        let since_last_active = now.duration_since(self.last_active).unwrap_or_default();
+
+        if since_last_active >= TOUCH_INTERVAL {
+            self.last_active = now;
+            true
+        } else {
+            false
+        }
+    }
+
     pub async fn get<T>(&self, key: &str) -> Option<T>
     where
         T: serde::de::DeserializeOwned,
@@ -88,7 +229,6 @@ impl Session
 
     async fn insert_raw(&mut self, key: &str, value: String)
     {
-        // TODO: Same as line 75
         let mut data = self.data.write().unwrap();
         if data.get(key) != Some(&value) {
             let _prev_val = data.insert(String::from(key), value);
@@ -96,6 +236,18 @@ impl Session
         }
     }
 
+    pub fn retain<F>(&mut self, mut keep: F)
+    where
+        F: FnMut(&str) -> bool,
+    {
+        let mut data = self.data.write().unwrap();
+        let len = data.len();
+        data.retain(|key, _value| keep(key));
+        if data.len() != len {
+            self.data_changed.store(true, Ordering::Relaxed);
+        }
+    }
+
     pub fn reset_data_changed(&self)
     {
         self.data_changed.store(false, Ordering::Relaxed);
@@ -114,6 +266,9 @@ impl Clone for Session
         Session {
             id: self.id.clone(),
             expires_in: self.expires_in,
+            expires_at: self.expires_at,
+            created_at: self.created_at,
+            last_active: self.last_active,
             data: self.data.clone(),
 
             cookie_value: None,
@@ -133,13 +288,26 @@ pub enum Error
     SerdeJson(#[from] serde_json::Error),
     #[error("{0}")]
     Redis(#[from] redis::RedisError),
+    #[error("timed out waiting for redis")]
+    RedisTimeout,
+    #[error("{0}")]
+    InvalidHeaderValue(#[from] http::header::InvalidHeaderValue),
     #[error("missing request session store extension")]
     MissingStoreExtension,
+    #[error("missing request postgres pool extension")]
+    MissingPgPoolExtension,
+    #[error("{0}")]
+    Sqlx(#[from] sqlx::Error),
     #[error("no session found for cookie {cookie}")]
     NoSessionFound
     {
         cookie: String
     },
+    #[error("the session for cookie {cookie} has expired")]
+    SessionExpired
+    {
+        cookie: String
+    },
 }
 
 impl response::IntoResponse for Error
@@ -150,10 +318,13 @@ impl response::IntoResponse for Error
             Error::Base64Decode(_)
             | Error::SerdeJson(_)
             | Error::Redis(_)
-            | Error::MissingStoreExtension => {
-                http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
-            }
+            | Error::InvalidHeaderValue(_)
+            | Error::MissingStoreExtension
+            | Error::MissingPgPoolExtension
+            | Error::Sqlx(_) => http::StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            Error::RedisTimeout => http::StatusCode::SERVICE_UNAVAILABLE.into_response(),
             Error::NoSessionFound { .. } => http::StatusCode::BAD_REQUEST.into_response(),
+            Error::SessionExpired { .. } => http::StatusCode::UNAUTHORIZED.into_response(),
         }
     }
 }
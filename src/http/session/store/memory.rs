@@ -0,0 +1,301 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_lock::RwLock;
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::http::session;
+
+/// Keeps sessions in the memory of the current process, which makes it
+/// suitable for tests and single-node deployments only
+#[derive(Debug, Clone)]
+pub struct MemoryStore
+{
+    records: Arc<RwLock<Records>>,
+}
+
+#[derive(Debug, Default)]
+struct Records
+{
+    sessions: HashMap<String, Record>,
+    user_index: HashMap<Uuid, HashSet<String>>,
+}
+
+#[derive(Debug)]
+struct Record
+{
+    value: String,
+    evict_at: Option<Instant>,
+}
+
+impl Record
+{
+    fn is_evicted(&self, now: Instant) -> bool
+    {
+        self.evict_at.map_or(false, |evict_at| evict_at <= now)
+    }
+}
+
+impl MemoryStore
+{
+    pub fn new() -> Self
+    {
+        MemoryStore {
+            records: Arc::new(RwLock::new(Records::default())),
+        }
+    }
+}
+
+#[async_trait]
+impl session::SessionStore for MemoryStore
+{
+    async fn load_session(&self, cookie: &str) -> session::Result<session::Session>
+    {
+        let id = session::Session::id_from_cookie(cookie)?;
+        let mut records = self.records.write().await;
+
+        let record = records
+            .sessions
+            .get(&id)
+            .filter(|record| !record.is_evicted(Instant::now()))
+            .ok_or_else(|| {
+                let cookie = String::from(cookie);
+                session::Error::NoSessionFound { cookie }
+            })?;
+
+        let session: session::Session = serde_json::from_str(&record.value)?;
+
+        if session.is_expired() {
+            let _record = records.sessions.remove(&id);
+
+            let cookie = String::from(cookie);
+            return Err(session::Error::SessionExpired { cookie });
+        }
+
+        Ok(session)
+    }
+
+    async fn store_session(&self, session: session::Session) -> session::Result<Option<String>>
+    {
+        let value = serde_json::to_string(&session)?;
+        let user_id = session.get::<Uuid>("user_id").await;
+        let now = Instant::now();
+
+        // NOTE: Same as the Redis store, the record is kept around for the
+        // rest of the session's lifetime even when it is idle
+        let evict_at = session
+            .remaining_lifetime()
+            .map(|remaining| now + remaining.max(Duration::from_secs(1)));
+
+        let mut records = self.records.write().await;
+        let Records {
+            sessions,
+            user_index,
+        } = &mut *records;
+
+        // Nothing else evicts records, so this is where stale ones are dropped
+        sessions.retain(|_id, record| !record.is_evicted(now));
+        user_index.retain(|_user_id, ids| {
+            ids.retain(|id| sessions.contains_key(id));
+            !ids.is_empty()
+        });
+
+        let _prev_record = sessions.insert(session.id.clone(), Record { value, evict_at });
+        if let Some(user_id) = user_id {
+            let _is_new = user_index
+                .entry(user_id)
+                .or_default()
+                .insert(session.id.clone());
+        }
+
+        Ok(session.into_cookie_value())
+    }
+
+    async fn touch_session(&self, session: &mut session::Session) -> session::Result<()>
+    {
+        if !session.touch() {
+            return Ok(());
+        }
+
+        let value = serde_json::to_string(&session)?;
+        let mut records = self.records.write().await;
+
+        // The record's eviction only depends on the session's lifetime, which
+        // activity does not extend
+        if let Some(record) = records
+            .sessions
+            .get_mut(&session.id)
+            .filter(|record| !record.is_evicted(Instant::now()))
+        {
+            record.value = value;
+        }
+
+        Ok(())
+    }
+
+    async fn destroy_session(&self, id: &str) -> session::Result<()>
+    {
+        let _record = self.records.write().await.sessions.remove(id);
+
+        Ok(())
+    }
+
+    async fn user_sessions(&self, user_id: Uuid) -> session::Result<Vec<session::Session>>
+    {
+        let records = self.records.read().await;
+        let now = Instant::now();
+
+        let ids = match records.user_index.get(&user_id) {
+            Some(ids) => ids,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut sessions = Vec::with_capacity(ids.len());
+        for id in ids {
+            let record = match records.sessions.get(id) {
+                Some(record) if !record.is_evicted(now) => record,
+                _ => continue,
+            };
+
+            let session: session::Session = serde_json::from_str(&record.value)?;
+            if !session.is_expired() {
+                sessions.push(session);
+            }
+        }
+
+        Ok(sessions)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::time::SystemTime;
+
+    use super::*;
+    use crate::http::session::SessionStore;
+
+    fn expiry() -> session::Expiry
+    {
+        session::Expiry::new(Duration::from_secs(60 * 60), Duration::from_secs(10 * 60))
+    }
+
+    #[tokio::test]
+    async fn sessions_round_trip_until_destroyed()
+    {
+        let store = MemoryStore::new();
+        let user_id = Uuid::new_v4();
+
+        let mut session = session::Session::new(expiry());
+        session.insert("user_id", user_id).await.unwrap();
+        let id = String::from(session.id());
+        let cookie = store.store_session(session).await.unwrap().unwrap();
+
+        let session = store.load_session(&cookie).await.unwrap();
+        assert_eq!(session.id(), id);
+        assert_eq!(session.get::<Uuid>("user_id").await, Some(user_id));
+
+        let user_sessions = store.user_sessions(user_id).await.unwrap();
+        assert_eq!(user_sessions.len(), 1);
+        assert_eq!(user_sessions[0].id(), id);
+
+        store.destroy_session(&id).await.unwrap();
+        assert!(matches!(
+            store.load_session(&cookie).await,
+            Err(session::Error::NoSessionFound { .. })
+        ));
+        assert!(store.user_sessions(user_id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn sessions_expire_at_the_end_of_their_lifetime()
+    {
+        let store = MemoryStore::new();
+
+        let mut session = session::Session::new(expiry());
+        session.expires_at = Some(SystemTime::now() - Duration::from_secs(1));
+        let cookie = store.store_session(session).await.unwrap().unwrap();
+
+        assert!(matches!(
+            store.load_session(&cookie).await,
+            Err(session::Error::SessionExpired { .. })
+        ));
+        // Loading an expired session drops it
+        assert!(matches!(
+            store.load_session(&cookie).await,
+            Err(session::Error::NoSessionFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn records_are_evicted_once_their_lifetime_is_over()
+    {
+        let store = MemoryStore::new();
+
+        let mut session = session::Session::new(expiry());
+        session.expires_at = Some(SystemTime::now() + Duration::from_secs(60));
+        let id = String::from(session.id());
+        let _cookie = store.store_session(session).await.unwrap();
+
+        let records = store.records.read().await;
+        let evict_at = records.sessions[&id].evict_at.unwrap();
+        assert!(evict_at <= Instant::now() + Duration::from_secs(60));
+        assert!(evict_at > Instant::now() + Duration::from_secs(50));
+    }
+
+    #[tokio::test]
+    async fn sessions_expire_when_left_idle()
+    {
+        let store = MemoryStore::new();
+
+        let mut session = session::Session::new(expiry());
+        session.last_active = SystemTime::now() - Duration::from_secs(11 * 60);
+        let cookie = store.store_session(session).await.unwrap().unwrap();
+
+        assert!(matches!(
+            store.load_session(&cookie).await,
+            Err(session::Error::SessionExpired { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn touching_sessions_extends_their_idle_timeout()
+    {
+        let store = MemoryStore::new();
+
+        let mut session = session::Session::new(expiry());
+        let last_active = SystemTime::now() - Duration::from_secs(9 * 60);
+        session.last_active = last_active;
+        let cookie = store.store_session(session).await.unwrap().unwrap();
+
+        let mut session = store.load_session(&cookie).await.unwrap();
+        store.touch_session(&mut session).await.unwrap();
+
+        let session = store.load_session(&cookie).await.unwrap();
+        assert!(session.last_active() > last_active);
+        assert!(session.ttl().unwrap() > Duration::from_secs(9 * 60));
+    }
+
+    #[tokio::test]
+    async fn touching_destroyed_sessions_does_not_bring_them_back()
+    {
+        let store = MemoryStore::new();
+
+        let mut session = session::Session::new(expiry());
+        session.last_active = SystemTime::now() - Duration::from_secs(9 * 60);
+        let cookie = store.store_session(session).await.unwrap().unwrap();
+
+        let mut session = store.load_session(&cookie).await.unwrap();
+        store.destroy_session(session.id()).await.unwrap();
+        store.touch_session(&mut session).await.unwrap();
+
+        assert!(matches!(
+            store.load_session(&cookie).await,
+            Err(session::Error::NoSessionFound { .. })
+        ));
+    }
+}
@@ -0,0 +1,51 @@
+use std::{fmt, sync::Arc};
+
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::http::session;
+
+mod memory;
+mod redis;
+
+pub use self::{memory::MemoryStore, redis::RedisStore};
+
+/// The session store shared by every request handler
+pub type Store = Arc<dyn SessionStore>;
+
+#[async_trait]
+pub trait SessionStore: fmt::Debug + Send + Sync
+{
+    async fn load_session(&self, cookie: &str) -> session::Result<session::Session>;
+
+    /// Persists the session, handing back its cookie value if it was newly
+    /// created
+    async fn store_session(&self, session: session::Session) -> session::Result<Option<String>>;
+
+    async fn destroy_session(&self, id: &str) -> session::Result<()>;
+
+    /// Every live session belonging to the user, in no particular order
+    async fn user_sessions(&self, user_id: Uuid) -> session::Result<Vec<session::Session>>;
+
+    /// Destroys every session belonging to the user, save for the one whose ID
+    /// is `except`
+    async fn destroy_user_sessions(
+        &self,
+        user_id: Uuid,
+        except: Option<&str>,
+    ) -> session::Result<()>
+    {
+        for session in self.user_sessions(user_id).await? {
+            if Some(session.id()) != except {
+                self.destroy_session(session.id()).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records activity on the session, extending its idle timeout. Only a
+    /// session which is still stored is renewed, so that one destroyed in the
+    /// meantime, such as by logging out elsewhere, is not brought back
+    async fn touch_session(&self, session: &mut session::Session) -> session::Result<()>;
+}
@@ -0,0 +1,278 @@
+use std::{
+    fmt,
+    future::Future,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use tokio::time;
+
+use async_trait::async_trait;
+use redis::{aio::ConnectionManager, AsyncCommands};
+use uuid::Uuid;
+
+use crate::http::session;
+
+/// Spreads commands over a fixed set of multiplexed connections, each of
+/// which transparently reconnects when the connection to Redis drops
+#[derive(Clone)]
+pub struct RedisStore
+{
+    connections: Arc<[ConnectionManager]>,
+    next_connection: Arc<AtomicUsize>,
+    timeout: Duration,
+}
+
+impl fmt::Debug for RedisStore
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        f.debug_struct("RedisStore")
+            .field("pool_size", &self.connections.len())
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RedisStore
+{
+    pub async fn connect(
+        client: redis::Client,
+        pool_size: NonZeroUsize,
+        timeout: Duration,
+    ) -> session::Result<Self>
+    {
+        let mut connections = Vec::with_capacity(pool_size.get());
+        for _ in 0..pool_size.get() {
+            let connection = time::timeout(timeout, client.get_tokio_connection_manager())
+                .await
+                .map_err(|_| session::Error::RedisTimeout)??;
+            connections.push(connection);
+        }
+
+        Ok(RedisStore {
+            connections: connections.into(),
+            next_connection: Arc::new(AtomicUsize::new(0)),
+            timeout,
+        })
+    }
+
+    fn connection(&self) -> ConnectionManager
+    {
+        let index = self.next_connection.fetch_add(1, Ordering::Relaxed) % self.connections.len();
+
+        self.connections[index].clone()
+    }
+
+    async fn with_timeout<T>(
+        &self,
+        command: impl Future<Output = redis::RedisResult<T>>,
+    ) -> session::Result<T>
+    {
+        time::timeout(self.timeout, command)
+            .await
+            .map_err(|_| session::Error::RedisTimeout)?
+            .map_err(session::Error::from)
+    }
+}
+
+#[async_trait]
+impl session::SessionStore for RedisStore
+{
+    async fn load_session(&self, cookie: &str) -> session::Result<session::Session>
+    {
+        let id = session::Session::id_from_cookie(cookie)?;
+        let mut connection = self.connection();
+
+        let record = self
+            .with_timeout(connection.get::<_, Option<String>>(&id))
+            .await?;
+        let session = match record {
+            Some(record) => serde_json::from_str::<session::Session>(&record)?,
+            None => self
+                .load_legacy_session(cookie, &id)
+                .await?
+                .ok_or_else(|| {
+                    let cookie = String::from(cookie);
+                    session::Error::NoSessionFound { cookie }
+                })?,
+        };
+
+        if session.is_expired() {
+            self.with_timeout(connection.del::<_, ()>(&session.id))
+                .await?;
+
+            let cookie = String::from(cookie);
+            return Err(session::Error::SessionExpired { cookie });
+        }
+
+        Ok(session)
+    }
+
+    async fn store_session(&self, session: session::Session) -> session::Result<Option<String>>
+    {
+        let record = serde_json::to_string(&session)?;
+        let user_id = session.get::<Uuid>("user_id").await;
+        let mut connection = self.connection();
+
+        let mut pipeline = redis::pipe();
+        let _pipeline = pipeline.atomic();
+
+        // NOTE: The record is kept around for the rest of the session's
+        // lifetime even when it is idle, so that loading it after the idle
+        // timeout is reported as an expired session rather than a missing one
+        match session.remaining_lifetime() {
+            Some(remaining) => {
+                // Redis rejects an expiry of zero seconds
+                let seconds = remaining.as_secs().max(1) as usize;
+                let _pipeline = pipeline.set_ex(&session.id, record, seconds).ignore();
+            }
+            None => {
+                let _pipeline = pipeline.set(&session.id, record).ignore();
+            }
+        };
+
+        // The index is scored by the point in time at which each session
+        // reaches the end of its lifetime, which lets stale entries be pruned
+        // without looking up the records they point to
+        if let Some(user_id) = user_id {
+            let index = user_index_key(user_id);
+            let score = session.expires_at.map_or(f64::INFINITY, |expires_at| {
+                session::unix_timestamp(expires_at) as f64
+            });
+            let now = session::unix_timestamp(SystemTime::now());
+
+            let _pipeline = pipeline
+                .zrembyscore(&index, "-inf", now)
+                .ignore()
+                .zadd(&index, &session.id, score)
+                .ignore();
+        }
+
+        self.with_timeout(pipeline.query_async::<_, ()>(&mut connection))
+            .await?;
+
+        Ok(session.into_cookie_value())
+    }
+
+    async fn touch_session(&self, session: &mut session::Session) -> session::Result<()>
+    {
+        if !session.touch() {
+            return Ok(());
+        }
+
+        let record = serde_json::to_string(&session)?;
+        let mut connection = self.connection();
+
+        // `XX` leaves a destroyed session destroyed, while `KEEPTTL` keeps the
+        // expiry, which only depends on the session's lifetime
+        self.with_timeout(
+            redis::cmd("SET")
+                .arg(&session.id)
+                .arg(record)
+                .arg("XX")
+                .arg("KEEPTTL")
+                .query_async::<_, ()>(&mut connection),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn destroy_session(&self, id: &str) -> session::Result<()>
+    {
+        let mut connection = self.connection();
+
+        self.with_timeout(connection.del::<_, ()>(id)).await?;
+
+        Ok(())
+    }
+
+    async fn user_sessions(&self, user_id: Uuid) -> session::Result<Vec<session::Session>>
+    {
+        let index = user_index_key(user_id);
+        let now = session::unix_timestamp(SystemTime::now());
+        let mut connection = self.connection();
+
+        let ids = self
+            .with_timeout(connection.zrangebyscore::<_, _, _, Vec<String>>(&index, now, "+inf"))
+            .await?;
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let records = self
+            .with_timeout(
+                redis::cmd("MGET")
+                    .arg(&ids)
+                    .query_async::<_, Vec<Option<String>>>(&mut connection),
+            )
+            .await?;
+
+        let mut sessions = Vec::with_capacity(ids.len());
+        let mut destroyed = Vec::new();
+        for (id, record) in ids.into_iter().zip(records) {
+            match record {
+                Some(record) => {
+                    let session: session::Session = serde_json::from_str(&record)?;
+                    if !session.is_expired() {
+                        sessions.push(session);
+                    }
+                }
+                None => destroyed.push(id),
+            }
+        }
+
+        if !destroyed.is_empty() {
+            self.with_timeout(connection.zrem::<_, _, ()>(&index, destroyed))
+                .await?;
+        }
+
+        Ok(sessions)
+    }
+}
+
+impl RedisStore
+{
+    /// Moves a session stored under its legacy ID to its current one, so that
+    /// the change of encoding does not log everyone out. Its record keeps the
+    /// rest of its lifetime, and its entry in the user's index is replaced
+    /// once the one under the legacy ID is found to be gone
+    async fn load_legacy_session(
+        &self,
+        cookie: &str,
+        id: &str,
+    ) -> session::Result<Option<session::Session>>
+    {
+        let legacy_id = session::Session::legacy_id_from_cookie(cookie)?;
+        let mut connection = self.connection();
+
+        let record = match self
+            .with_timeout(connection.get::<_, Option<String>>(&legacy_id))
+            .await?
+        {
+            Some(record) => record,
+            None => return Ok(None),
+        };
+
+        let mut session: session::Session = serde_json::from_str(&record)?;
+        session.id = String::from(id);
+        if !session.is_expired() {
+            let _cookie = self.store_session(session.clone()).await?;
+        }
+
+        self.with_timeout(connection.del::<_, ()>(&legacy_id))
+            .await?;
+
+        Ok(Some(session))
+    }
+}
+
+fn user_index_key(user_id: Uuid) -> String
+{
+    format!("user_sessions:{}", user_id)
+}
@@ -0,0 +1,371 @@
+use std::time::Duration;
+
+use axum::{
+    extract::Path,
+    http, response,
+    routing::{delete, get},
+    Extension, Router,
+};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use thiserror::Error;
+
+use crate::http::{api_error, json, session};
+
+/// Tells personal access tokens apart from other bearer tokens, and makes
+/// them easy to spot when leaked
+pub(in crate::http) const TOKEN_PREFIX: &str = "bbpat_";
+const TOKEN_LEN: usize = 32;
+const MAX_NAME_LEN: usize = 100;
+const SECS_PER_DAY: u32 = 24 * 60 * 60;
+/// Tokens which must expire cannot be made to last any longer than this
+const MAX_TOKEN_DAYS: u32 = 365;
+
+// Recording every single use would mean a write per request, so a use is only
+// recorded once this much time has passed since the last recorded one, the
+// same as session activity
+const LAST_USED_INTERVAL: Duration = Duration::from_secs(60);
+
+pub(in crate::http) fn router() -> Router
+{
+    Router::new()
+        .route("/users/me/tokens", get(fetch_tokens).post(create_token))
+        .route("/users/me/tokens/:id", delete(revoke_token))
+}
+
+/// What a bearer token may be used for. Cookie sessions may do anything
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(in crate::http) enum Scope
+{
+    Read,
+    Write,
+}
+
+impl Scope
+{
+    pub(in crate::http) fn name(self) -> &'static str
+    {
+        match self {
+            Scope::Read => "read",
+            Scope::Write => "write",
+        }
+    }
+
+    pub(in crate::http) fn from_name(name: &str) -> Option<Self>
+    {
+        match name {
+            "read" => Some(Scope::Read),
+            "write" => Some(Scope::Write),
+            _ => None,
+        }
+    }
+}
+
+/// Resolves a personal access token to the user it belongs to and its
+/// scopes, recording that it was used unless that was done lately. Expired
+/// and unknown tokens resolve to nothing
+pub(in crate::http) async fn authenticate(
+    pg_pool: &PgPool,
+    token: &str,
+) -> sqlx::Result<Option<(Uuid, Vec<Scope>)>>
+{
+    // The update runs whether or not anything reads from it
+    let token = sqlx::query!(
+        r#"
+            WITH "token" AS (
+                SELECT token_id, user_id, scopes, last_used_at
+                FROM "personal_access_tokens"
+                WHERE token_hash = $1 AND (expires_at IS NULL OR expires_at > now())
+            ), "touched" AS (
+                UPDATE "personal_access_tokens"
+                SET last_used_at = now()
+                FROM "token"
+                WHERE "personal_access_tokens".token_id = "token".token_id
+                    AND (
+                        "token".last_used_at IS NULL
+                        OR "token".last_used_at < now() - make_interval(secs => $2)
+                    )
+            )
+            SELECT user_id as "user_id!", scopes as "scopes!"
+            FROM "token"
+        "#,
+        hash_token(token),
+        LAST_USED_INTERVAL.as_secs_f64()
+    )
+    .fetch_optional(pg_pool)
+    .await?;
+
+    Ok(token.map(|token| (token.user_id, parse_scopes(&token.scopes))))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Token
+{
+    id: Uuid,
+    name: String,
+    scopes: Vec<Scope>,
+    created_at: i64,
+    expires_at: Option<i64>,
+    last_used_at: Option<i64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CreatedToken
+{
+    #[serde(flatten)]
+    details: Token,
+    /// Only ever shown here, as nothing but its hash is kept
+    token: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateToken
+{
+    name: String,
+    scopes: Vec<Scope>,
+    /// Tokens never expire when left out
+    expires_in_days: Option<u32>,
+}
+
+async fn create_token(
+    pg_pool: Extension<PgPool>,
+    user_id: session::extractor::UserId,
+    json::extractor::Json(req): json::extractor::Json<CreateToken>,
+) -> Result<(http::StatusCode, axum::Json<CreatedToken>)>
+{
+    // Tokens are not allowed to mint further tokens
+    let user_id = match user_id {
+        session::extractor::UserId::Found(user_id, session::extractor::AuthMethod::Session) => {
+            user_id
+        }
+        _ => return Err(Error::MustBeAuthenticated),
+    };
+    let CreateToken {
+        name,
+        mut scopes,
+        expires_in_days,
+    } = req;
+
+    let name = String::from(name.trim());
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+        return Err(Error::InvalidName);
+    }
+    scopes.sort_unstable();
+    scopes.dedup();
+    if scopes.is_empty() {
+        return Err(Error::NoScopes);
+    }
+    if let Some(days) = expires_in_days {
+        if !(1..=MAX_TOKEN_DAYS).contains(&days) {
+            return Err(Error::InvalidExpiry);
+        }
+    }
+
+    let token = generate_token(TOKEN_PREFIX);
+    let scope_names = scopes
+        .iter()
+        .map(|scope| String::from(scope.name()))
+        .collect::<Vec<_>>();
+    let expires_in_secs = expires_in_days.map(|days| f64::from(days) * f64::from(SECS_PER_DAY));
+
+    let created = sqlx::query!(
+        r#"
+            INSERT INTO "personal_access_tokens"(user_id, name, token_hash, scopes, expires_at)
+            VALUES ($1, $2, $3, $4, now() + make_interval(secs => $5))
+            RETURNING
+                token_id,
+                extract(epoch from created_at)::bigint as "created_at!",
+                extract(epoch from expires_at)::bigint as expires_at
+        "#,
+        user_id,
+        name,
+        hash_token(&token),
+        &scope_names[..],
+        expires_in_secs
+    )
+    .fetch_one(&*pg_pool)
+    .await?;
+
+    Ok((
+        http::StatusCode::CREATED,
+        axum::Json(CreatedToken {
+            details: Token {
+                id: created.token_id,
+                name,
+                scopes,
+                created_at: created.created_at,
+                expires_at: created.expires_at,
+                last_used_at: None,
+            },
+            token,
+        }),
+    ))
+}
+
+async fn fetch_tokens(
+    pg_pool: Extension<PgPool>,
+    user_id: session::extractor::UserId,
+) -> Result<axum::Json<Vec<Token>>>
+{
+    let user_id = match user_id {
+        session::extractor::UserId::Found(user_id, session::extractor::AuthMethod::Session) => {
+            user_id
+        }
+        _ => return Err(Error::MustBeAuthenticated),
+    };
+
+    let tokens = sqlx::query!(
+        r#"
+            SELECT
+                token_id,
+                name,
+                scopes,
+                extract(epoch from created_at)::bigint as "created_at!",
+                extract(epoch from expires_at)::bigint as expires_at,
+                extract(epoch from last_used_at)::bigint as last_used_at
+            FROM "personal_access_tokens"
+            WHERE user_id = $1 AND (expires_at IS NULL OR expires_at > now())
+            ORDER BY created_at DESC
+        "#,
+        user_id
+    )
+    .fetch_all(&*pg_pool)
+    .await?
+    .into_iter()
+    .map(|token| Token {
+        id: token.token_id,
+        name: token.name,
+        scopes: parse_scopes(&token.scopes),
+        created_at: token.created_at,
+        expires_at: token.expires_at,
+        last_used_at: token.last_used_at,
+    })
+    .collect();
+
+    Ok(axum::Json(tokens))
+}
+
+async fn revoke_token(
+    pg_pool: Extension<PgPool>,
+    user_id: session::extractor::UserId,
+    Path(token_id): Path<Uuid>,
+) -> Result<http::StatusCode>
+{
+    let user_id = match user_id {
+        session::extractor::UserId::Found(user_id, session::extractor::AuthMethod::Session) => {
+            user_id
+        }
+        _ => return Err(Error::MustBeAuthenticated),
+    };
+
+    let pg_query_res = sqlx::query!(
+        r#"DELETE FROM "personal_access_tokens" WHERE token_id = $1 AND user_id = $2"#,
+        token_id,
+        user_id
+    )
+    .execute(&*pg_pool)
+    .await?;
+    if pg_query_res.rows_affected() == 0 {
+        return Err(Error::TokenNotFound { id: token_id });
+    }
+
+    Ok(http::StatusCode::NO_CONTENT)
+}
+
+/// Revokes every token of the user, such as when whoever made them may not
+/// have been the user
+pub(in crate::http) async fn revoke_user_tokens(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+) -> sqlx::Result<()>
+{
+    let _pg_query_res = sqlx::query!(
+        r#"DELETE FROM "personal_access_tokens" WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// A random bearer token, behind a prefix telling which kind of token it is
+pub(in crate::http) fn generate_token(prefix: &str) -> String
+{
+    let mut token = vec![0u8; TOKEN_LEN];
+    rand::thread_rng().fill_bytes(&mut token);
+
+    format!(
+        "{}{}",
+        prefix,
+        base64::encode_config(token, base64::URL_SAFE_NO_PAD)
+    )
+}
+
+/// Bearer tokens are random enough for a fast hash to do, unlike passwords
+pub(in crate::http) fn hash_token(token: &str) -> String
+{
+    blake3::hash(token.as_bytes()).to_hex().to_string()
+}
+
+/// Scopes which are no longer known are dropped rather than failing the
+/// whole token
+pub(in crate::http) fn parse_scopes(names: &[String]) -> Vec<Scope>
+{
+    names
+        .iter()
+        .filter_map(|name| Scope::from_name(name))
+        .collect()
+}
+
+type Result<T> = ::core::result::Result<T, Error>;
+
+#[derive(Debug, Error)]
+enum Error
+{
+    #[error("{0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("must be authenticated")]
+    MustBeAuthenticated,
+    #[error("token name must be between 1 and {} characters long", MAX_NAME_LEN)]
+    InvalidName,
+    #[error("token must have at least one scope")]
+    NoScopes,
+    #[error("token must expire in between 1 and {} days", MAX_TOKEN_DAYS)]
+    InvalidExpiry,
+    #[error("no token with id {id} was found")]
+    TokenNotFound
+    {
+        id: Uuid
+    },
+}
+
+impl response::IntoResponse for Error
+{
+    fn into_response(self) -> response::Response
+    {
+        let code = match self {
+            Error::InvalidName => api_error::Code::TOKEN_INVALID_NAME,
+            Error::NoScopes => api_error::Code::TOKEN_NO_SCOPES,
+            Error::InvalidExpiry => api_error::Code::TOKEN_INVALID_EXPIRY,
+            Error::MustBeAuthenticated => return http::StatusCode::UNAUTHORIZED.into_response(),
+            Error::TokenNotFound { .. } => return http::StatusCode::NOT_FOUND.into_response(),
+            Error::Sqlx(_) => return http::StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        };
+
+        let payload = json!({
+            "message": self.to_string(),
+            "code": code,
+        });
+
+        (http::StatusCode::UNPROCESSABLE_ENTITY, axum::Json(payload)).into_response()
+    }
+}
@@ -0,0 +1,376 @@
+use std::time::SystemTime;
+
+use axum::{http, response, routing::post, Extension, Router};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use thiserror::Error;
+
+use crate::{
+    http::{api_error, json, session},
+    password, totp,
+};
+
+pub(in crate::http) fn router() -> Router
+{
+    Router::new().route(
+        "/users/me/totp",
+        post(enroll_totp).put(confirm_totp).delete(disable_totp),
+    )
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EnrollTotp
+{
+    current_password: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TotpEnrollment
+{
+    secret: String,
+    uri: String,
+}
+
+/// Starts over with a fresh secret on every call until enrollment is
+/// confirmed, after which the secret stays put
+///
+/// Whoever holds the secret can pass the second factor, so the current
+/// password is asked for, as it is on every step which changes how the user
+/// logs in, lest a hijacked session lock them out
+async fn enroll_totp(
+    pg_pool: Extension<PgPool>,
+    password_hasher: Extension<password::Hasher>,
+    user_id: session::extractor::UserId,
+    json::extractor::Json(req): json::extractor::Json<EnrollTotp>,
+) -> Result<axum::Json<TotpEnrollment>>
+{
+    let user_id = match user_id {
+        session::extractor::UserId::Found(user_id, session::extractor::AuthMethod::Session) => {
+            user_id
+        }
+        _ => return Err(Error::MustBeAuthenticated),
+    };
+
+    verify_password(&pg_pool, &password_hasher, user_id, req.current_password).await?;
+
+    let secret = totp::generate_secret();
+
+    let pg_query_res = sqlx::query!(
+        r#"
+            INSERT INTO "totp"(user_id, secret)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id) DO UPDATE
+            SET secret = excluded.secret
+            WHERE NOT "totp".confirmed
+        "#,
+        user_id,
+        secret
+    )
+    .execute(&*pg_pool)
+    .await?;
+    if pg_query_res.rows_affected() == 0 {
+        return Err(Error::AlreadyEnrolled);
+    }
+
+    let user = sqlx::query!(r#"select username from users where user_id = $1"#, user_id)
+        .fetch_one(&*pg_pool)
+        .await?;
+
+    Ok(axum::Json(TotpEnrollment {
+        secret: totp::encode_secret(&secret),
+        uri: totp::uri(&user.username, &secret),
+    }))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ConfirmTotp
+{
+    current_password: String,
+    code: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RecoveryCodes
+{
+    recovery_codes: Vec<String>,
+}
+
+/// Turns enrollment on once the user proves their authenticator holds the
+/// secret, handing back the recovery codes. They are only ever shown here, as
+/// nothing but their hashes is kept
+async fn confirm_totp(
+    pg_pool: Extension<PgPool>,
+    password_hasher: Extension<password::Hasher>,
+    user_id: session::extractor::UserId,
+    json::extractor::Json(req): json::extractor::Json<ConfirmTotp>,
+) -> Result<axum::Json<RecoveryCodes>>
+{
+    let user_id = match user_id {
+        session::extractor::UserId::Found(user_id, session::extractor::AuthMethod::Session) => {
+            user_id
+        }
+        _ => return Err(Error::MustBeAuthenticated),
+    };
+
+    verify_password(&pg_pool, &password_hasher, user_id, req.current_password).await?;
+
+    let enrollment = sqlx::query!(
+        r#"select secret, confirmed from "totp" where user_id = $1"#,
+        user_id
+    )
+    .fetch_optional(&*pg_pool)
+    .await?
+    .ok_or(Error::NotEnrolled)?;
+    if enrollment.confirmed {
+        return Err(Error::AlreadyEnrolled);
+    }
+
+    let step =
+        totp::verify(&enrollment.secret, &req.code, SystemTime::now()).ok_or(Error::InvalidCode)?;
+
+    let recovery_codes = totp::generate_recovery_codes();
+    let recovery_code_hashes = recovery_codes
+        .iter()
+        .map(|code| totp::hash_recovery_code(code))
+        .collect::<Vec<_>>();
+
+    let mut transaction = pg_pool.begin().await?;
+
+    // Confirming is conditional on the secret being the one the code was
+    // checked against, in case enrollment was started over in the meantime
+    let pg_query_res = sqlx::query!(
+        r#"
+            UPDATE "totp"
+            SET confirmed = true, last_used_step = $3
+            WHERE user_id = $1 AND secret = $2 AND NOT confirmed
+        "#,
+        user_id,
+        enrollment.secret,
+        step as i64
+    )
+    .execute(&mut transaction)
+    .await?;
+    if pg_query_res.rows_affected() == 0 {
+        return Err(Error::InvalidCode);
+    }
+
+    let _pg_query_res = sqlx::query!(
+        r#"DELETE FROM "totp_recovery_codes" WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    let _pg_query_res = sqlx::query!(
+        r#"
+            INSERT INTO "totp_recovery_codes"(user_id, code_hash)
+            SELECT $1, unnest($2::text[])
+        "#,
+        user_id,
+        &recovery_code_hashes[..]
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(axum::Json(RecoveryCodes { recovery_codes }))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DisableTotp
+{
+    current_password: String,
+    code: String,
+}
+
+/// Turns two-factor authentication off, forgetting the secret and recovery
+/// codes. Both factors are asked for, so that neither a hijacked session nor
+/// a leaked password is enough to take the second one away
+async fn disable_totp(
+    pg_pool: Extension<PgPool>,
+    password_hasher: Extension<password::Hasher>,
+    user_id: session::extractor::UserId,
+    json::extractor::Json(req): json::extractor::Json<DisableTotp>,
+) -> Result<http::StatusCode>
+{
+    let user_id = match user_id {
+        session::extractor::UserId::Found(user_id, session::extractor::AuthMethod::Session) => {
+            user_id
+        }
+        _ => return Err(Error::MustBeAuthenticated),
+    };
+    let DisableTotp {
+        current_password,
+        code,
+    } = req;
+
+    verify_password(&pg_pool, &password_hasher, user_id, current_password).await?;
+
+    if !is_enabled(&pg_pool, user_id).await? {
+        return Err(Error::NotEnrolled);
+    }
+    if !use_code(&pg_pool, user_id, &code).await? {
+        return Err(Error::InvalidCode);
+    }
+
+    let mut transaction = pg_pool.begin().await?;
+
+    let _pg_query_res = sqlx::query!(
+        r#"DELETE FROM "totp_recovery_codes" WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    let _pg_query_res = sqlx::query!(r#"DELETE FROM "totp" WHERE user_id = $1"#, user_id)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(http::StatusCode::NO_CONTENT)
+}
+
+/// Checks the password the user entered to confirm it is them making the
+/// change
+async fn verify_password(
+    pg_pool: &PgPool,
+    password_hasher: &password::Hasher,
+    user_id: Uuid,
+    current_password: String,
+) -> Result<()>
+{
+    let user = sqlx::query!(r#"select password from users where user_id = $1"#, user_id)
+        .fetch_one(pg_pool)
+        .await?;
+
+    let password_is_correct = password_hasher
+        .verify(current_password, user.password)
+        .await?;
+    if !password_is_correct {
+        return Err(Error::WrongPassword);
+    }
+
+    Ok(())
+}
+
+/// Whether the user has confirmed enrollment, and so has to provide a code on
+/// every login
+pub(in crate::http) async fn is_enabled(pg_pool: &PgPool, user_id: Uuid) -> sqlx::Result<bool>
+{
+    let enrollment = sqlx::query!(
+        r#"select exists(select 1 from "totp" where user_id = $1 and confirmed) as "enabled!""#,
+        user_id
+    )
+    .fetch_one(pg_pool)
+    .await?;
+
+    Ok(enrollment.enabled)
+}
+
+/// Checks a code from the user's authenticator, which is then used up along
+/// with every code from earlier time steps
+pub(in crate::http) async fn use_code(
+    pg_pool: &PgPool,
+    user_id: Uuid,
+    code: &str,
+) -> sqlx::Result<bool>
+{
+    let enrollment = sqlx::query!(
+        r#"select secret from "totp" where user_id = $1 and confirmed"#,
+        user_id
+    )
+    .fetch_optional(pg_pool)
+    .await?;
+
+    let step = match enrollment
+        .and_then(|enrollment| totp::verify(&enrollment.secret, code, SystemTime::now()))
+    {
+        Some(step) => step,
+        None => return Ok(false),
+    };
+
+    // The step only moves forward, so concurrent logins cannot both get in
+    // with the same code
+    let pg_query_res = sqlx::query!(
+        r#"
+            UPDATE "totp"
+            SET last_used_step = $2
+            WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)
+        "#,
+        user_id,
+        step as i64
+    )
+    .execute(pg_pool)
+    .await?;
+
+    Ok(pg_query_res.rows_affected() == 1)
+}
+
+/// Checks a recovery code, which is used up in the process
+pub(in crate::http) async fn use_recovery_code(
+    pg_pool: &PgPool,
+    user_id: Uuid,
+    recovery_code: &str,
+) -> sqlx::Result<bool>
+{
+    let pg_query_res = sqlx::query!(
+        r#"DELETE FROM "totp_recovery_codes" WHERE user_id = $1 AND code_hash = $2"#,
+        user_id,
+        totp::hash_recovery_code(recovery_code)
+    )
+    .execute(pg_pool)
+    .await?;
+
+    Ok(pg_query_res.rows_affected() == 1)
+}
+
+type Result<T> = ::core::result::Result<T, Error>;
+
+#[derive(Debug, Error)]
+enum Error
+{
+    #[error("{0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("{0}")]
+    Password(#[from] password::Error),
+    #[error("must be authenticated")]
+    MustBeAuthenticated,
+    #[error("the provided password is wrong")]
+    WrongPassword,
+    #[error("two-factor authentication is already enabled")]
+    AlreadyEnrolled,
+    #[error("two-factor authentication enrollment has not been started")]
+    NotEnrolled,
+    #[error("invalid two-factor authentication code")]
+    InvalidCode,
+}
+
+impl response::IntoResponse for Error
+{
+    fn into_response(self) -> response::Response
+    {
+        match self {
+            Error::InvalidCode => {
+                let payload = json!({
+                    "message": self.to_string(),
+                    "code": api_error::Code::INVALID_SECOND_FACTOR,
+                });
+
+                (http::StatusCode::UNPROCESSABLE_ENTITY, axum::Json(payload)).into_response()
+            }
+            Error::MustBeAuthenticated => http::StatusCode::UNAUTHORIZED.into_response(),
+            Error::WrongPassword => http::StatusCode::UNPROCESSABLE_ENTITY.into_response(),
+            Error::AlreadyEnrolled => http::StatusCode::CONFLICT.into_response(),
+            Error::NotEnrolled => http::StatusCode::NOT_FOUND.into_response(),
+            _ => http::StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+}
@@ -1,14 +1,45 @@
-use axum::{http, response, routing::post, Extension, Router};
+use axum::{
+    handler::Handler,
+    http, response,
+    routing::{post, put},
+    Extension, Router,
+};
 use sqlx::PgPool;
 
 use serde::Deserialize;
+use serde_json::json;
 use thiserror::Error;
 
-use crate::{http::json, password};
+use crate::{
+    http::{api_error, email, json, oauth, rate_limit, session, tokens},
+    password, username,
+};
 
-pub(in crate::http) fn router() -> Router
+/// Signing up and changing passwords are limited by `quota`, which is shared
+/// between them. Every other route which sends an email is limited by
+/// `mail_quota`, on top of the limit on emails to each address
+pub(in crate::http) fn router(
+    quota: rate_limit::RateLimitLayer,
+    mail_quota: rate_limit::RateLimitLayer,
+) -> Router
 {
-    Router::new().route("/users", post(create_user))
+    Router::new()
+        .route("/users", post(create_user).layer(quota.clone()))
+        .route("/users/me/password", put(update_password).layer(quota))
+        .route(
+            "/users/me/email",
+            put(update_email).layer(mail_quota.clone()),
+        )
+        .route(
+            "/users/me/email/verification",
+            post(create_email_verification).layer(mail_quota.clone()),
+        )
+        .route("/users/email/verification", put(verify_email))
+        // Only asking for the link sends an email, not following it
+        .route(
+            "/users/password/reset",
+            post(create_password_reset.layer(mail_quota)).put(reset_password),
+        )
 }
 
 #[derive(Deserialize)]
@@ -16,34 +47,513 @@ pub(in crate::http) fn router() -> Router
 struct CreateUser
 {
     username: String,
+    email: String,
     password: String,
 }
 
 async fn create_user(
     pg_pool: Extension<PgPool>,
+    outbox: Extension<email::Outbox>,
+    password_hasher: Extension<password::Hasher>,
+    password_policy: Extension<password::Policy>,
     json::extractor::Json(req): json::extractor::Json<CreateUser>,
 ) -> Result<http::StatusCode>
 {
-    let CreateUser { username, password } = req;
+    let CreateUser {
+        username,
+        email,
+        password,
+    } = req;
 
-    let password = password::hash(password).await?;
+    let username = username::normalize(&username)?;
+    let email = email::parse_address(&email).ok_or(Error::InvalidEmail)?;
 
-    let _pg_query_res = sqlx::query!(
+    password_policy
+        .check(password.clone(), vec![username.clone(), email.clone()])
+        .await?;
+
+    let password = password_hasher.hash(password).await?;
+
+    let mut tx = pg_pool.begin().await?;
+
+    let user = sqlx::query!(
         r#"
-            INSERT INTO "users"(username, password)
-            values ($1, $2)
+            INSERT INTO "users"(username, username_skeleton, email, password)
+            values ($1, $2, $3, $4)
+            RETURNING user_id
         "#,
         username,
+        username::look_alike_form(&username),
+        email,
         password
     )
-    .execute(&*pg_pool)
+    .fetch_one(&mut tx)
     .await
-    .map_err(|err| match err {
-        sqlx::Error::Database(db_err) if db_err.constraint() == Some("users_username_key") => {
-            Error::UsernameTaken
-        }
+    .map_err(map_unique_violation)?;
+
+    // Whether the address is taken is only told to its owner, and only once
+    // the username is known to be free, so that the answer is the same either
+    // way
+    let owner = sqlx::query!(
+        r#"
+            SELECT username
+            FROM "users"
+            WHERE lower(email) = lower($1) AND email_verified_at IS NOT NULL
+        "#,
+        email
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    if let Some(owner) = owner {
+        tx.rollback().await?;
+
+        outbox.send_account_exists(email, &owner.username);
+
+        return Ok(http::StatusCode::NO_CONTENT);
+    }
+
+    let token =
+        email::issue_token(&mut tx, user.user_id, &email, email::Purpose::VerifyEmail).await?;
+
+    tx.commit().await?;
+
+    outbox.send_verification(email, &token);
+
+    Ok(http::StatusCode::NO_CONTENT)
+}
+
+fn map_unique_violation(err: sqlx::Error) -> Error
+{
+    match err {
+        sqlx::Error::Database(db_err) => match db_err.constraint() {
+            Some(
+                "users_username_key" | "users_username_lower_key" | "users_username_skeleton_key",
+            ) => Error::UsernameTaken,
+            Some("users_verified_email_lower_key") => Error::EmailTaken,
+            _ => sqlx::Error::Database(db_err).into(),
+        },
         err => err.into(),
-    })?;
+    }
+}
+
+/// Works out the look-alike form of usernames from before it was kept, which
+/// SQL can't do on its own. Accounts that look like one which already has its
+/// form are left without one, as renaming them would lock their users out
+pub(in crate::http) async fn fill_look_alike_forms(pg_pool: &PgPool) -> sqlx::Result<()>
+{
+    let users =
+        sqlx::query!(r#"SELECT user_id, username FROM "users" WHERE username_skeleton IS NULL"#)
+            .fetch_all(pg_pool)
+            .await?;
+
+    for user in users {
+        let res = sqlx::query!(
+            r#"UPDATE "users" SET username_skeleton = $2 WHERE user_id = $1"#,
+            user.user_id,
+            username::look_alike_form(&user.username)
+        )
+        .execute(pg_pool)
+        .await;
+        match res {
+            Ok(_) => {}
+            Err(sqlx::Error::Database(db_err))
+                if db_err.constraint() == Some("users_username_skeleton_key") =>
+            {
+                tracing::warn!(
+                    "username `{}` looks like another one already taken",
+                    user.username
+                );
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UpdatePassword
+{
+    current_password: String,
+    new_password: String,
+}
+
+async fn update_password(
+    pg_pool: Extension<PgPool>,
+    session_store: Extension<session::Store>,
+    password_hasher: Extension<password::Hasher>,
+    password_policy: Extension<password::Policy>,
+    user_id: session::extractor::UserId,
+    session::extractor::SessionId(current_id): session::extractor::SessionId,
+    json::extractor::Json(req): json::extractor::Json<UpdatePassword>,
+) -> Result<http::StatusCode>
+{
+    let user_id = match user_id {
+        session::extractor::UserId::Found(user_id, session::extractor::AuthMethod::Session) => {
+            user_id
+        }
+        _ => return Err(Error::MustBeAuthenticated),
+    };
+    let UpdatePassword {
+        current_password,
+        new_password,
+    } = req;
+
+    let user = sqlx::query!(
+        r#"select username, password from users where user_id = $1"#,
+        user_id
+    )
+    .fetch_one(&*pg_pool)
+    .await?;
+
+    let password_is_correct = password_hasher
+        .verify(current_password, user.password)
+        .await?;
+    if !password_is_correct {
+        return Err(Error::WrongPassword);
+    }
+
+    password_policy
+        .check(new_password.clone(), vec![user.username])
+        .await?;
+
+    let new_password = password_hasher.hash(new_password).await?;
+
+    let mut tx = pg_pool.begin().await?;
+
+    let _pg_query_res = sqlx::query!(
+        r#"
+            UPDATE "users"
+            SET password = $1
+            WHERE user_id = $2
+        "#,
+        new_password,
+        user_id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    // Whoever else may know the old password could have made tokens with it,
+    // which would outlive the change
+    tokens::revoke_user_tokens(&mut tx, user_id).await?;
+    oauth::revoke_user_grants(&mut tx, user_id).await?;
+
+    tx.commit().await?;
+
+    // They are signed out everywhere as well, while the session that made the
+    // change stays signed in
+    session_store
+        .destroy_user_sessions(user_id, current_id.as_deref())
+        .await?;
+
+    Ok(http::StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UpdateEmail
+{
+    current_password: String,
+    email: String,
+}
+
+/// Replaces the address, which stays unverified until the user follows the
+/// link sent to it. Password resets go to the new address right away, so the
+/// current password is asked for, as with changing it
+async fn update_email(
+    pg_pool: Extension<PgPool>,
+    outbox: Extension<email::Outbox>,
+    password_hasher: Extension<password::Hasher>,
+    user_id: session::extractor::UserId,
+    json::extractor::Json(req): json::extractor::Json<UpdateEmail>,
+) -> Result<http::StatusCode>
+{
+    let user_id = match user_id {
+        session::extractor::UserId::Found(user_id, session::extractor::AuthMethod::Session) => {
+            user_id
+        }
+        _ => return Err(Error::MustBeAuthenticated),
+    };
+    let UpdateEmail {
+        current_password,
+        email,
+    } = req;
+
+    let email = email::parse_address(&email).ok_or(Error::InvalidEmail)?;
+
+    let user = sqlx::query!(r#"select password from users where user_id = $1"#, user_id)
+        .fetch_one(&*pg_pool)
+        .await?;
+
+    let password_is_correct = password_hasher
+        .verify(current_password, user.password)
+        .await?;
+    if !password_is_correct {
+        return Err(Error::WrongPassword);
+    }
+
+    let mut tx = pg_pool.begin().await?;
+
+    let _pg_query_res = sqlx::query!(
+        r#"
+            UPDATE "users"
+            SET email = $1, email_verified_at = NULL
+            WHERE user_id = $2
+        "#,
+        email,
+        user_id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    let token = email::issue_token(&mut tx, user_id, &email, email::Purpose::VerifyEmail).await?;
+
+    tx.commit().await?;
+
+    outbox.send_verification(email, &token);
+
+    Ok(http::StatusCode::NO_CONTENT)
+}
+
+/// Sends the verification link once more, in case the first one got lost or
+/// expired
+async fn create_email_verification(
+    pg_pool: Extension<PgPool>,
+    outbox: Extension<email::Outbox>,
+    address_limiter: Extension<rate_limit::AddressLimiter>,
+    user_id: session::extractor::UserId,
+) -> Result<http::StatusCode>
+{
+    let user_id = match user_id {
+        session::extractor::UserId::Found(user_id, session::extractor::AuthMethod::Session) => {
+            user_id
+        }
+        _ => return Err(Error::MustBeAuthenticated),
+    };
+
+    let user = sqlx::query!(
+        r#"
+            SELECT email as "email!"
+            FROM "users"
+            WHERE user_id = $1 AND email IS NOT NULL AND email_verified_at IS NULL
+        "#,
+        user_id
+    )
+    .fetch_optional(&*pg_pool)
+    .await?
+    .ok_or(Error::NothingToVerify)?;
+
+    address_limiter.hit(&user.email).await?;
+
+    let mut conn = pg_pool.acquire().await?;
+    let token =
+        email::issue_token(&mut conn, user_id, &user.email, email::Purpose::VerifyEmail).await?;
+
+    outbox.send_verification(user.email, &token);
+
+    Ok(http::StatusCode::ACCEPTED)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VerifyEmail
+{
+    token: String,
+}
+
+/// Takes the token from the verification link, which may be followed from a
+/// browser that is not logged in
+async fn verify_email(
+    pg_pool: Extension<PgPool>,
+    json::extractor::Json(req): json::extractor::Json<VerifyEmail>,
+) -> Result<http::StatusCode>
+{
+    let mut tx = pg_pool.begin().await?;
+
+    let (user_id, email) = email::consume_token(&mut tx, &req.token, email::Purpose::VerifyEmail)
+        .await?
+        .ok_or(Error::InvalidEmailToken)?;
+
+    // Someone else may have verified the address since the link was sent, in
+    // which case it is theirs. Only the owner of the inbox gets to know that
+    let pg_query_res = sqlx::query!(
+        r#"
+            UPDATE "users"
+            SET email_verified_at = now()
+            WHERE user_id = $1 AND email = $2
+        "#,
+        user_id,
+        email
+    )
+    .execute(&mut tx)
+    .await
+    .map_err(map_unique_violation)?;
+    if pg_query_res.rows_affected() == 0 {
+        return Err(Error::InvalidEmailToken);
+    }
+
+    email::release_claims(&mut tx, user_id, &email).await?;
+
+    tx.commit().await?;
+
+    Ok(http::StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreatePasswordReset
+{
+    email: String,
+}
+
+/// Sends a reset link if the address belongs to anyone, answering the same
+/// either way so that the endpoint does not tell which addresses have an
+/// account. Once someone has verified the address, only they get one, but
+/// until then every account claiming it does
+async fn create_password_reset(
+    pg_pool: Extension<PgPool>,
+    outbox: Extension<email::Outbox>,
+    address_limiter: Extension<rate_limit::AddressLimiter>,
+    json::extractor::Json(req): json::extractor::Json<CreatePasswordReset>,
+) -> Result<http::StatusCode>
+{
+    let email = match email::parse_address(&req.email) {
+        Some(email) => email,
+        None => return Ok(http::StatusCode::ACCEPTED),
+    };
+
+    // Counted before looking the address up, so that running out happens
+    // the same whether or not it has an account
+    address_limiter.hit(&email).await?;
+
+    let users = sqlx::query!(
+        r#"
+            SELECT user_id, username, email as "email!"
+            FROM "users"
+            WHERE lower(email) = lower($1) AND (
+                email_verified_at IS NOT NULL OR NOT EXISTS (
+                    SELECT 1
+                    FROM "users" as "owner"
+                    WHERE lower(owner.email) = lower($1) AND owner.email_verified_at IS NOT NULL
+                )
+            )
+        "#,
+        email
+    )
+    .fetch_all(&*pg_pool)
+    .await?;
+
+    let mut conn = pg_pool.acquire().await?;
+    for user in users {
+        let token = email::issue_token(
+            &mut conn,
+            user.user_id,
+            &user.email,
+            email::Purpose::ResetPassword,
+        )
+        .await?;
+
+        outbox.send_password_reset(user.email, &user.username, &token);
+    }
+
+    Ok(http::StatusCode::ACCEPTED)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ResetPassword
+{
+    token: String,
+    new_password: String,
+}
+
+async fn reset_password(
+    pg_pool: Extension<PgPool>,
+    session_store: Extension<session::Store>,
+    password_hasher: Extension<password::Hasher>,
+    password_policy: Extension<password::Policy>,
+    json::extractor::Json(req): json::extractor::Json<ResetPassword>,
+) -> Result<http::StatusCode>
+{
+    let ResetPassword {
+        token,
+        new_password,
+    } = req;
+
+    // The token is only used up once the new password is known to be
+    // acceptable, so that picking a weak one does not mean asking for
+    // another link
+    let mut conn = pg_pool.acquire().await?;
+    let (user_id, _email) = email::find_token(&mut conn, &token, email::Purpose::ResetPassword)
+        .await?
+        .ok_or(Error::InvalidEmailToken)?;
+    drop(conn);
+
+    let user = sqlx::query!(
+        r#"select username, email from users where user_id = $1"#,
+        user_id
+    )
+    .fetch_one(&*pg_pool)
+    .await?;
+
+    let mut user_inputs = vec![user.username];
+    user_inputs.extend(user.email);
+    password_policy
+        .check(new_password.clone(), user_inputs)
+        .await?;
+
+    let new_password = password_hasher.hash(new_password).await?;
+
+    let mut tx = pg_pool.begin().await?;
+
+    let (user_id, email) = email::consume_token(&mut tx, &token, email::Purpose::ResetPassword)
+        .await?
+        .ok_or(Error::InvalidEmailToken)?;
+
+    // Getting the link proves the user owns the address, as long as it is
+    // still the one on the account and no one else has verified it since
+    let user = sqlx::query!(
+        r#"
+            UPDATE "users"
+            SET
+                password = $1,
+                email_verified_at = CASE
+                    WHEN EXISTS (
+                        SELECT 1
+                        FROM "users" as "owner"
+                        WHERE
+                            lower(owner.email) = lower($3)
+                            AND owner.email_verified_at IS NOT NULL
+                            AND owner.user_id <> $2
+                    ) THEN email_verified_at
+                    ELSE coalesce(email_verified_at, now())
+                END
+            WHERE user_id = $2 AND email = $3
+            RETURNING email_verified_at IS NOT NULL as "is_verified!"
+        "#,
+        new_password,
+        user_id,
+        email
+    )
+    .fetch_optional(&mut tx)
+    .await
+    .map_err(map_unique_violation)?
+    .ok_or(Error::InvalidEmailToken)?;
+
+    if user.is_verified {
+        email::release_claims(&mut tx, user_id, &email).await?;
+    }
+
+    // Whoever took over the account, if anyone, loses every token they made
+    // with it
+    tokens::revoke_user_tokens(&mut tx, user_id).await?;
+    oauth::revoke_user_grants(&mut tx, user_id).await?;
+
+    tx.commit().await?;
+
+    // They are signed out everywhere as well
+    session_store.destroy_user_sessions(user_id, None).await?;
 
     Ok(http::StatusCode::NO_CONTENT)
 }
@@ -57,18 +567,520 @@ enum Error
     Sqlx(#[from] sqlx::Error),
     #[error("{0}")]
     Password(#[from] password::Error),
+    #[error("{0}")]
+    PasswordPolicy(#[from] password::policy::Error),
+    #[error("{0}")]
+    Session(#[from] session::Error),
+    #[error("{0}")]
+    RateLimit(#[from] rate_limit::Error),
+    #[error("{0}")]
+    Username(#[from] username::Error),
     #[error("username already taken")]
     UsernameTaken,
+    #[error("invalid email address")]
+    InvalidEmail,
+    #[error("email address already taken")]
+    EmailTaken,
+    #[error("there is no unverified email address to verify")]
+    NothingToVerify,
+    #[error("the link is invalid or has expired")]
+    InvalidEmailToken,
+    #[error("the provided password is wrong")]
+    WrongPassword,
+    #[error("must be authenticated")]
+    MustBeAuthenticated,
 }
 
 impl response::IntoResponse for Error
 {
     fn into_response(self) -> response::Response
     {
-        match self {
-            Error::UsernameTaken => http::StatusCode::CONFLICT,
-            _ => http::StatusCode::INTERNAL_SERVER_ERROR,
+        let (code, message) = match self {
+            Error::RateLimit(err) => return err.into_response(),
+            Error::PasswordPolicy(password::policy::Error::Violation(violation)) => {
+                let code = match violation {
+                    password::policy::Violation::TooShort { .. } => {
+                        api_error::Code::PASSWORD_TOO_SHORT
+                    }
+                    password::policy::Violation::TooLong { .. } => {
+                        api_error::Code::PASSWORD_TOO_LONG
+                    }
+                    password::policy::Violation::TooWeak { .. } => {
+                        api_error::Code::PASSWORD_TOO_WEAK
+                    }
+                    password::policy::Violation::Breached => api_error::Code::PASSWORD_BREACHED,
+                };
+                (code, violation.to_string())
+            }
+            Error::Username(err) => {
+                let code = match err {
+                    username::Error::TooShort { .. } => api_error::Code::USERNAME_TOO_SHORT,
+                    username::Error::TooLong { .. } => api_error::Code::USERNAME_TOO_LONG,
+                    username::Error::InvalidCharacters => {
+                        api_error::Code::USERNAME_INVALID_CHARACTERS
+                    }
+                    username::Error::MixedScripts => api_error::Code::USERNAME_MIXED_SCRIPTS,
+                    username::Error::Reserved => api_error::Code::USERNAME_RESERVED,
+                };
+                (code, err.to_string())
+            }
+            Error::InvalidEmail => (api_error::Code::EMAIL_INVALID, self.to_string()),
+            Error::InvalidEmailToken => (api_error::Code::EMAIL_TOKEN_INVALID, self.to_string()),
+            // Only verifying an address can run into it being taken, and only
+            // by whoever reads its inbox, so saying so tells no one else
+            Error::EmailTaken => {
+                let payload = json!({
+                    "message": self.to_string(),
+                    "code": api_error::Code::EMAIL_TAKEN,
+                });
+
+                return (http::StatusCode::CONFLICT, axum::Json(payload)).into_response();
+            }
+            Error::UsernameTaken => return http::StatusCode::CONFLICT.into_response(),
+            Error::NothingToVerify => return http::StatusCode::CONFLICT.into_response(),
+            Error::WrongPassword => return http::StatusCode::UNPROCESSABLE_ENTITY.into_response(),
+            Error::MustBeAuthenticated => return http::StatusCode::UNAUTHORIZED.into_response(),
+            _ => return http::StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        };
+
+        // Rule violations carry the same `{ message, code }` body as malformed
+        // JSON, so clients can tell the user what to fix
+        let payload = json!({
+            "message": message,
+            "code": code,
+        });
+
+        (http::StatusCode::UNPROCESSABLE_ENTITY, axum::Json(payload)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+    use axum::{body::Body, extract::ConnectInfo};
+    use tower::Service;
+
+    use super::*;
+    use crate::mail;
+
+    const PASSWORD: &str = "correct horse battery staple";
+
+    fn app(pg_pool: PgPool, mailer: mail::MemoryMailer) -> Router
+    {
+        let rate_limit_store: rate_limit::Store = Arc::new(rate_limit::MemoryStore::new());
+        let quota = rate_limit::RateLimitLayer::new(
+            rate_limit_store.clone(),
+            rate_limit::TrustedProxies::default(),
+            "users",
+            rate_limit::Quota::new(100, Duration::from_secs(60 * 60)),
+        );
+        let mail_quota = rate_limit::RateLimitLayer::new(
+            rate_limit_store.clone(),
+            rate_limit::TrustedProxies::default(),
+            "mail",
+            rate_limit::Quota::new(100, Duration::from_secs(60 * 60)),
+        );
+        let address_limiter = rate_limit::AddressLimiter::new(
+            rate_limit_store,
+            rate_limit::Quota::new(3, Duration::from_secs(60 * 60)),
+        );
+        let session_store: session::Store = Arc::new(session::MemoryStore::new());
+        let outbox = email::Outbox::new(Arc::new(mailer), "https://bluebird.test");
+        let password_hasher = password::Hasher::new(
+            argon2::Params::new(8, 1, 1, None).unwrap(),
+            Default::default(),
+        );
+        let password_policy = password::Policy::new(8, 128, 0, None);
+
+        router(quota, mail_quota)
+            .layer(Extension(pg_pool))
+            .layer(Extension(session_store))
+            .layer(Extension(outbox))
+            .layer(Extension(password_hasher))
+            .layer(Extension(password_policy))
+            .layer(Extension(address_limiter))
+    }
+
+    async fn send(
+        app: &mut Router,
+        method: http::Method,
+        uri: &str,
+        body: serde_json::Value,
+    ) -> http::StatusCode
+    {
+        let mut req = http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let _prev_value = req
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4000))));
+
+        app.call(req).await.unwrap().status()
+    }
+
+    async fn sign_up(app: &mut Router)
+    {
+        let body = json!({
+            "username": "alice",
+            "email": "alice@example.com",
+            "password": PASSWORD,
+        });
+        assert_eq!(
+            send(app, http::Method::POST, "/users", body).await,
+            http::StatusCode::NO_CONTENT
+        );
+    }
+
+    /// Waits for the email at `index`, which is sent in the background
+    async fn email_at(mailer: &mail::MemoryMailer, index: usize) -> mail::Email
+    {
+        for _ in 0..100 {
+            if let Some(email) = mailer.sent().await.get(index) {
+                return email.clone();
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        panic!("no email was sent at index {}", index);
+    }
+
+    /// Takes the token out of the link in the email at `index`
+    async fn token_from_email(mailer: &mail::MemoryMailer, index: usize) -> String
+    {
+        let email = email_at(mailer, index).await;
+        let (_link, token) = email.body.split_once("token=").unwrap();
+
+        String::from(token.lines().next().unwrap())
+    }
+
+    #[sqlx::test]
+    async fn look_alike_usernames_are_taken(pg_pool: PgPool)
+    {
+        let mailer = mail::MemoryMailer::new();
+        let mut app = app(pg_pool.clone(), mailer);
+
+        sign_up(&mut app).await;
+        for username in ["ALICE", "ａｌｉｃｅ", "a1ice"] {
+            let body = json!({
+                "username": username,
+                "email": "mallory@example.com",
+                "password": PASSWORD,
+            });
+            assert_eq!(
+                send(&mut app, http::Method::POST, "/users", body).await,
+                http::StatusCode::CONFLICT,
+                "{}",
+                username
+            );
+        }
+
+        // Older accounts get theirs filled in, save for the ones which look
+        // like an account that already has one
+        let _pg_query_res = sqlx::query!(
+            r#"
+                INSERT INTO "users"(username, password)
+                VALUES ('bob', ''), ('a1ice', '')
+            "#
+        )
+        .execute(&pg_pool)
+        .await
+        .unwrap();
+        fill_look_alike_forms(&pg_pool).await.unwrap();
+
+        let users =
+            sqlx::query!(r#"SELECT username, username_skeleton FROM "users" ORDER BY username"#)
+                .fetch_all(&pg_pool)
+                .await
+                .unwrap();
+        let skeletons = users
+            .iter()
+            .map(|user| (user.username.as_str(), user.username_skeleton.is_some()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            skeletons,
+            [("a1ice", false), ("alice", true), ("bob", true)]
+        );
+    }
+
+    #[sqlx::test]
+    async fn verification_links_work_once(pg_pool: PgPool)
+    {
+        let mailer = mail::MemoryMailer::new();
+        let mut app = app(pg_pool.clone(), mailer.clone());
+
+        sign_up(&mut app).await;
+        let token = token_from_email(&mailer, 0).await;
+        assert_eq!(mailer.sent().await[0].to, "alice@example.com");
+
+        let body = json!({ "token": token });
+        assert_eq!(
+            send(
+                &mut app,
+                http::Method::PUT,
+                "/users/email/verification",
+                body.clone()
+            )
+            .await,
+            http::StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            send(
+                &mut app,
+                http::Method::PUT,
+                "/users/email/verification",
+                body
+            )
+            .await,
+            http::StatusCode::UNPROCESSABLE_ENTITY
+        );
+
+        let user = sqlx::query!(
+            r#"
+                SELECT email_verified_at IS NOT NULL as "is_verified!"
+                FROM "users"
+                WHERE username = 'alice'
+            "#
+        )
+        .fetch_one(&pg_pool)
+        .await
+        .unwrap();
+        assert!(user.is_verified);
+    }
+
+    #[sqlx::test]
+    async fn signing_up_with_a_verified_address_tells_its_owner(pg_pool: PgPool)
+    {
+        let mailer = mail::MemoryMailer::new();
+        let mut app = app(pg_pool.clone(), mailer.clone());
+
+        sign_up(&mut app).await;
+        let token = token_from_email(&mailer, 0).await;
+        let body = json!({ "token": token });
+        let _status = send(
+            &mut app,
+            http::Method::PUT,
+            "/users/email/verification",
+            body,
+        )
+        .await;
+
+        let body = json!({
+            "username": "mallory",
+            "email": "Alice@example.com",
+            "password": PASSWORD,
+        });
+        assert_eq!(
+            send(&mut app, http::Method::POST, "/users", body).await,
+            http::StatusCode::NO_CONTENT
+        );
+
+        let notice = email_at(&mailer, 1).await;
+        assert_eq!(notice.to, "Alice@example.com");
+        assert_eq!(notice.subject, "You already have an account");
+
+        let user = sqlx::query!(r#"SELECT user_id FROM "users" WHERE username = 'mallory'"#)
+            .fetch_optional(&pg_pool)
+            .await
+            .unwrap();
+        assert!(user.is_none());
+    }
+
+    #[sqlx::test]
+    async fn unverified_claims_do_not_keep_the_owner_out(pg_pool: PgPool)
+    {
+        let mailer = mail::MemoryMailer::new();
+        let mut app = app(pg_pool.clone(), mailer.clone());
+
+        let body = json!({
+            "username": "mallory",
+            "email": "alice@example.com",
+            "password": PASSWORD,
+        });
+        assert_eq!(
+            send(&mut app, http::Method::POST, "/users", body).await,
+            http::StatusCode::NO_CONTENT
+        );
+        let squatter_token = token_from_email(&mailer, 0).await;
+
+        sign_up(&mut app).await;
+        let token = token_from_email(&mailer, 1).await;
+        let body = json!({ "token": token });
+        assert_eq!(
+            send(
+                &mut app,
+                http::Method::PUT,
+                "/users/email/verification",
+                body
+            )
+            .await,
+            http::StatusCode::NO_CONTENT
+        );
+
+        // The other claim is dropped, along with the link sent for it
+        let body = json!({ "token": squatter_token });
+        assert_eq!(
+            send(
+                &mut app,
+                http::Method::PUT,
+                "/users/email/verification",
+                body
+            )
+            .await,
+            http::StatusCode::UNPROCESSABLE_ENTITY
+        );
+        let user = sqlx::query!(r#"SELECT email FROM "users" WHERE username = 'mallory'"#)
+            .fetch_one(&pg_pool)
+            .await
+            .unwrap();
+        assert_eq!(user.email, None);
+    }
+
+    #[sqlx::test]
+    async fn reset_links_work_once(pg_pool: PgPool)
+    {
+        let mailer = mail::MemoryMailer::new();
+        let mut app = app(pg_pool.clone(), mailer.clone());
+
+        sign_up(&mut app).await;
+        let _verification_token = token_from_email(&mailer, 0).await;
+
+        let body = json!({ "email": "alice@example.com" });
+        assert_eq!(
+            send(&mut app, http::Method::POST, "/users/password/reset", body).await,
+            http::StatusCode::ACCEPTED
+        );
+        let token = token_from_email(&mailer, 1).await;
+
+        let body = json!({ "token": token, "newPassword": "a whole new password" });
+        assert_eq!(
+            send(
+                &mut app,
+                http::Method::PUT,
+                "/users/password/reset",
+                body.clone()
+            )
+            .await,
+            http::StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            send(&mut app, http::Method::PUT, "/users/password/reset", body).await,
+            http::StatusCode::UNPROCESSABLE_ENTITY
+        );
+
+        let user = sqlx::query!(
+            r#"
+                SELECT password, email_verified_at IS NOT NULL as "is_verified!"
+                FROM "users"
+                WHERE username = 'alice'
+            "#
+        )
+        .fetch_one(&pg_pool)
+        .await
+        .unwrap();
+        let password_hasher = password::Hasher::new(
+            argon2::Params::new(8, 1, 1, None).unwrap(),
+            Default::default(),
+        );
+        assert!(password_hasher
+            .verify(String::from("a whole new password"), user.password)
+            .await
+            .unwrap());
+        // Following the link proves the address is the user's
+        assert!(user.is_verified);
+    }
+
+    #[sqlx::test]
+    async fn reset_emails_are_limited_per_address(pg_pool: PgPool)
+    {
+        let mailer = mail::MemoryMailer::new();
+        let mut app = app(pg_pool, mailer);
+
+        // However it is spelled, and whether or not anyone has it
+        for address in [
+            "nobody@example.com",
+            "Nobody@example.com",
+            "NOBODY@example.com",
+        ] {
+            let body = json!({ "email": address });
+            assert_eq!(
+                send(&mut app, http::Method::POST, "/users/password/reset", body).await,
+                http::StatusCode::ACCEPTED
+            );
         }
-        .into_response()
+
+        let body = json!({ "email": "nobody@example.com" });
+        assert_eq!(
+            send(&mut app, http::Method::POST, "/users/password/reset", body).await,
+            http::StatusCode::TOO_MANY_REQUESTS
+        );
+        let body = json!({ "email": "somebody@example.com" });
+        assert_eq!(
+            send(&mut app, http::Method::POST, "/users/password/reset", body).await,
+            http::StatusCode::ACCEPTED
+        );
+    }
+
+    #[sqlx::test]
+    async fn expired_reset_links_are_refused(pg_pool: PgPool)
+    {
+        let mailer = mail::MemoryMailer::new();
+        let mut app = app(pg_pool.clone(), mailer.clone());
+
+        sign_up(&mut app).await;
+        let _verification_token = token_from_email(&mailer, 0).await;
+
+        let body = json!({ "email": "alice@example.com" });
+        let _status = send(&mut app, http::Method::POST, "/users/password/reset", body).await;
+        let token = token_from_email(&mailer, 1).await;
+
+        let _pg_query_res =
+            sqlx::query!(r#"UPDATE "email_tokens" SET expires_at = now() - interval '1 second'"#)
+                .execute(&pg_pool)
+                .await
+                .unwrap();
+
+        let body = json!({ "token": token, "newPassword": "a whole new password" });
+        assert_eq!(
+            send(&mut app, http::Method::PUT, "/users/password/reset", body).await,
+            http::StatusCode::UNPROCESSABLE_ENTITY
+        );
+    }
+
+    #[sqlx::test]
+    async fn new_reset_links_replace_earlier_ones(pg_pool: PgPool)
+    {
+        let mailer = mail::MemoryMailer::new();
+        let mut app = app(pg_pool, mailer.clone());
+
+        sign_up(&mut app).await;
+        let _verification_token = token_from_email(&mailer, 0).await;
+
+        let body = json!({ "email": "alice@example.com" });
+        let _status = send(
+            &mut app,
+            http::Method::POST,
+            "/users/password/reset",
+            body.clone(),
+        )
+        .await;
+        let first_token = token_from_email(&mailer, 1).await;
+        let _status = send(&mut app, http::Method::POST, "/users/password/reset", body).await;
+        let second_token = token_from_email(&mailer, 2).await;
+
+        let body = json!({ "token": first_token, "newPassword": "a whole new password" });
+        assert_eq!(
+            send(&mut app, http::Method::PUT, "/users/password/reset", body).await,
+            http::StatusCode::UNPROCESSABLE_ENTITY
+        );
+        let body = json!({ "token": second_token, "newPassword": "a whole new password" });
+        assert_eq!(
+            send(&mut app, http::Method::PUT, "/users/password/reset", body).await,
+            http::StatusCode::NO_CONTENT
+        );
     }
 }
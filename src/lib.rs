@@ -19,4 +19,10 @@ pub mod config;
 
 pub mod http;
 
+pub mod mail;
+
 mod password;
+
+mod totp;
+
+mod username;
@@ -0,0 +1,38 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use lettre::{message::Mailbox, AsyncFileTransport, AsyncTransport, Tokio1Executor};
+
+use crate::mail;
+
+/// Writes every email as an `.eml` file to a directory instead of sending it,
+/// which makes it suitable for development only
+#[derive(Debug, Clone)]
+pub struct FileMailer
+{
+    transport: AsyncFileTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl FileMailer
+{
+    pub fn new(dir: PathBuf, from: Mailbox) -> Self
+    {
+        FileMailer {
+            transport: AsyncFileTransport::new(dir),
+            from,
+        }
+    }
+}
+
+#[async_trait]
+impl mail::Mailer for FileMailer
+{
+    async fn send(&self, email: mail::Email) -> mail::Result<()>
+    {
+        let message = mail::build_message(&self.from, email)?;
+        let _id = self.transport.send(message).await?;
+
+        Ok(())
+    }
+}
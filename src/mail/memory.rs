@@ -0,0 +1,44 @@
+use std::sync::Arc;
+
+use async_lock::Mutex;
+use async_trait::async_trait;
+
+use crate::mail;
+
+/// Keeps every email in the memory of the current process instead of sending
+/// it, so that tests can read back what would have been sent
+#[derive(Debug, Clone)]
+pub struct MemoryMailer
+{
+    outbox: Arc<Mutex<Vec<mail::Email>>>,
+}
+
+impl MemoryMailer
+{
+    pub fn new() -> Self
+    {
+        MemoryMailer {
+            outbox: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Every email sent so far, oldest first
+    pub async fn sent(&self) -> Vec<mail::Email>
+    {
+        self.outbox.lock().await.clone()
+    }
+}
+
+#[async_trait]
+impl mail::Mailer for MemoryMailer
+{
+    async fn send(&self, email: mail::Email) -> mail::Result<()>
+    {
+        // Addresses are still checked, so that tests catch the same mistakes
+        // delivery would
+        let _address = email.to.parse::<lettre::Address>()?;
+        self.outbox.lock().await.push(email);
+
+        Ok(())
+    }
+}
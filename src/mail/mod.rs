@@ -0,0 +1,57 @@
+use std::{fmt, sync::Arc};
+
+use async_trait::async_trait;
+use lettre::message::{header::ContentType, Mailbox};
+use thiserror::Error;
+
+mod file;
+mod memory;
+mod smtp;
+
+pub use self::{file::FileMailer, memory::MemoryMailer, smtp::SmtpMailer};
+
+/// The mailer shared by every request handler
+pub type Shared = Arc<dyn Mailer>;
+
+/// A plain text email to a single recipient
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Email
+{
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: fmt::Debug + Send + Sync
+{
+    async fn send(&self, email: Email) -> Result<()>;
+}
+
+/// Turns the email into a message lettre's transports can deliver
+fn build_message(from: &Mailbox, email: Email) -> Result<lettre::Message>
+{
+    let message = lettre::Message::builder()
+        .from(from.clone())
+        .to(email.to.parse()?)
+        .subject(email.subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(email.body)?;
+
+    Ok(message)
+}
+
+pub type Result<T> = ::core::result::Result<T, Error>;
+
+#[derive(Debug, Error)]
+pub enum Error
+{
+    #[error("invalid recipient address: {0}")]
+    Address(#[from] lettre::address::AddressError),
+    #[error("failed to build the message: {0}")]
+    Message(#[from] lettre::error::Error),
+    #[error("failed to send the message over SMTP: {0}")]
+    Smtp(#[from] lettre::transport::smtp::Error),
+    #[error("failed to write the message to a file: {0}")]
+    File(#[from] lettre::transport::file::Error),
+}
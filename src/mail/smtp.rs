@@ -0,0 +1,41 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use lettre::{message::Mailbox, AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+
+use crate::mail;
+
+/// Delivers emails through an SMTP relay, which may just as well be a
+/// stand-in server such as MailHog when running locally
+#[derive(Debug, Clone)]
+pub struct SmtpMailer
+{
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer
+{
+    /// Takes the relay as an `smtp://` or `smtps://` URL, credentials
+    /// included
+    pub fn new(url: &str, timeout: Duration, from: Mailbox) -> mail::Result<Self>
+    {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::from_url(url)?
+            .timeout(Some(timeout))
+            .build();
+
+        Ok(SmtpMailer { transport, from })
+    }
+}
+
+#[async_trait]
+impl mail::Mailer for SmtpMailer
+{
+    async fn send(&self, email: mail::Email) -> mail::Result<()>
+    {
+        let message = mail::build_message(&self.from, email)?;
+        let _response = self.transport.send(message).await?;
+
+        Ok(())
+    }
+}
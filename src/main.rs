@@ -1,12 +1,13 @@
-use std::time::Duration;
+use std::sync::Arc;
 
 use sqlx::postgres::PgPoolOptions;
 
 use thiserror::Error;
 
 use bluebird::{
-    config::{self, Config},
-    http::{self, session},
+    config::{self, Config, MailBackend, SessionBackend},
+    http::{self, rate_limit, session},
+    mail,
 };
 
 #[tokio::main]
@@ -17,16 +18,47 @@ async fn main() -> Result<()>
     let config = Config::init()?;
 
     let pg_pool = PgPoolOptions::new()
-        .max_connections(5)
-        .acquire_timeout(Duration::from_secs(3))
+        .min_connections(config.postgres_min_connections())
+        .max_connections(config.postgres_max_connections())
+        .acquire_timeout(config.postgres_acquire_timeout())
         .connect(config.postgres_url())
         .await?;
     sqlx::migrate!().run(&pg_pool).await?;
 
-    let redis_client = redis::Client::open("redis://127.0.0.1/")?;
-    let session_store = session::Store::new(redis_client);
+    let (session_store, rate_limit_store): (session::Store, rate_limit::Store) =
+        match config.session_backend() {
+            SessionBackend::Redis => {
+                let redis_client = redis::Client::open(config.redis_url())?;
+                let session_store = session::RedisStore::connect(
+                    redis_client.clone(),
+                    config.redis_pool_size(),
+                    config.redis_timeout(),
+                )
+                .await?;
+                let rate_limit_store =
+                    rate_limit::RedisStore::connect(redis_client, config.redis_timeout()).await?;
+                (Arc::new(session_store), Arc::new(rate_limit_store))
+            }
+            SessionBackend::Memory => (
+                Arc::new(session::MemoryStore::new()),
+                Arc::new(rate_limit::MemoryStore::new()),
+            ),
+        };
 
-    bluebird::http::serve(config.port(), pg_pool, session_store).await?;
+    let mailer: mail::Shared = match config.mail_backend() {
+        MailBackend::Smtp => Arc::new(mail::SmtpMailer::new(
+            config.mail_smtp_url(),
+            config.mail_smtp_timeout(),
+            config.mail_from().clone(),
+        )?),
+        MailBackend::File => Arc::new(mail::FileMailer::new(
+            config.mail_dir().to_path_buf(),
+            config.mail_from().clone(),
+        )),
+        MailBackend::Memory => Arc::new(mail::MemoryMailer::new()),
+    };
+
+    bluebird::http::serve(&config, pg_pool, session_store, rate_limit_store, mailer).await?;
 
     Ok(())
 }
@@ -45,6 +77,12 @@ enum Error
     #[error("{0}")]
     Redis(#[from] redis::RedisError),
     #[error("{0}")]
+    Session(#[from] session::Error),
+    #[error("{0}")]
+    RateLimit(#[from] rate_limit::Error),
+    #[error("{0}")]
+    Mail(#[from] mail::Error),
+    #[error("{0}")]
     Hyper(#[from] hyper::Error),
     #[error("{0}")]
     Http(#[from] http::Error),
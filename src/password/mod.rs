@@ -0,0 +1,190 @@
+use std::sync::Arc;
+
+use axum::{http, response};
+use tokio::{sync::OnceCell, task};
+
+use argon2::{
+    password_hash::{self, SaltString},
+    Algorithm, Argon2, Params, ParamsBuilder, PasswordHash, PasswordHasher, PasswordVerifier,
+    Version,
+};
+use thiserror::Error;
+
+use crate::config::Peppers;
+
+pub(crate) mod policy;
+
+pub(crate) use policy::Policy;
+
+// Whatever the dummy hash is computed from does not matter, as long as it is
+// computed the same way as real hashes
+const DUMMY_PASSWORD: &str = "bluebird dummy password";
+
+/// Hashes passwords with Argon2id and the current pepper, while still being
+/// able to verify hashes computed with older parameters or peppers
+#[derive(Debug, Clone)]
+pub(crate) struct Hasher
+{
+    params: Params,
+    peppers: Peppers,
+    dummy_hash: Arc<OnceCell<String>>,
+}
+
+impl Hasher
+{
+    pub(crate) fn new(params: Params, peppers: Peppers) -> Self
+    {
+        Hasher {
+            params,
+            peppers,
+            dummy_hash: Arc::new(OnceCell::new()),
+        }
+    }
+
+    pub(crate) async fn hash(&self, password: String) -> Result<String>
+    {
+        let params = self.params.clone();
+        let peppers = self.peppers.clone();
+
+        task::spawn_blocking(move || {
+            let salt = SaltString::generate(rand::thread_rng());
+
+            let hashed_password = match peppers.current() {
+                Some((key_id, secret)) => {
+                    let mut builder = ParamsBuilder::new();
+                    let _builder = builder
+                        .m_cost(params.m_cost())?
+                        .t_cost(params.t_cost())?
+                        .p_cost(params.p_cost())?
+                        .keyid(key_id.as_bytes())?;
+
+                    Argon2::new_with_secret(
+                        secret,
+                        Algorithm::Argon2id,
+                        Version::V0x13,
+                        builder.params()?,
+                    )?
+                    .hash_password(password.as_bytes(), &salt)?
+                    .to_string()
+                }
+                None => Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password(password.as_bytes(), &salt)?
+                    .to_string(),
+            };
+
+            Ok(hashed_password)
+        })
+        .await?
+    }
+
+    pub(crate) async fn verify(&self, password: String, hash: String) -> Result<bool>
+    {
+        let peppers = self.peppers.clone();
+
+        task::spawn_blocking(move || {
+            let hash = PasswordHash::new(&hash).map_err(Error::from)?;
+            let key_id = key_id(&hash)?;
+
+            // The algorithm, version and parameters are all read back from the
+            // hash itself, only the secret has to be picked beforehand
+            let is_correct = match key_id {
+                Some(key_id) => {
+                    let secret = peppers
+                        .get(&key_id)
+                        .ok_or(Error::UnknownPepper { key_id })?;
+
+                    Argon2::new_with_secret(
+                        secret,
+                        Algorithm::default(),
+                        Version::default(),
+                        Params::default(),
+                    )?
+                    .verify_password(password.as_bytes(), &hash)
+                }
+                None => Argon2::default().verify_password(password.as_bytes(), &hash),
+            };
+
+            match is_correct {
+                Ok(()) => Ok(true),
+                Err(password_hash::Error::Password) => Ok(false),
+                Err(err) => Err(err)?,
+            }
+        })
+        .await?
+    }
+
+    /// Verifies the password against a fixed hash computed with the current
+    /// parameters, which takes as long as verifying against a real one. This
+    /// is for when there is no real hash to verify against, so that the lack
+    /// of one does not show in how long a request takes
+    ///
+    /// The hash is only computed the first time it is needed
+    pub(crate) async fn verify_dummy(&self, password: String) -> Result<()>
+    {
+        let dummy_hash = self
+            .dummy_hash
+            .get_or_try_init(|| self.hash(String::from(DUMMY_PASSWORD)))
+            .await?;
+
+        let _is_correct = self.verify(password, dummy_hash.clone()).await?;
+
+        Ok(())
+    }
+
+    /// Whether the hash was computed with anything other than the current
+    /// algorithm, parameters and pepper, in which case it should be recomputed
+    /// the next time the password is known
+    pub(crate) fn needs_rehash(&self, hash: &str) -> Result<bool>
+    {
+        let hash = PasswordHash::new(hash)?;
+        let hash_params = Params::try_from(&hash)?;
+        let current_key_id = self.peppers.current().map(|(key_id, _secret)| key_id);
+
+        Ok(hash.algorithm != Algorithm::Argon2id.ident()
+            || hash.version != Some(Version::V0x13.into())
+            || hash_params.m_cost() != self.params.m_cost()
+            || hash_params.t_cost() != self.params.t_cost()
+            || hash_params.p_cost() != self.params.p_cost()
+            || key_id(&hash)?.as_deref() != current_key_id)
+    }
+}
+
+/// The ID of the pepper the hash was computed with, if any
+fn key_id(hash: &PasswordHash<'_>) -> Result<Option<String>>
+{
+    let params = Params::try_from(hash)?;
+    let key_id = params.keyid();
+
+    if key_id.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(String::from_utf8_lossy(key_id).into_owned()))
+    }
+}
+
+type Result<T> = ::core::result::Result<T, Error>;
+
+#[derive(Debug, Error)]
+#[allow(variant_size_differences)]
+pub(crate) enum Error
+{
+    #[error("{0}")]
+    PasswordHash(#[from] password_hash::Error),
+    #[error("{0}")]
+    Argon2(#[from] argon2::Error),
+    #[error("no pepper with key ID {key_id} is configured")]
+    UnknownPepper
+    {
+        key_id: String
+    },
+    #[error("{0}")]
+    TaskJoin(#[from] task::JoinError),
+}
+
+impl response::IntoResponse for Error
+{
+    fn into_response(self) -> response::Response
+    {
+        http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    }
+}
@@ -0,0 +1,328 @@
+use std::{
+    cmp::Ordering,
+    fs::File,
+    io::{self, BufRead, BufReader, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use tokio::task;
+
+use sha1::{Digest, Sha1};
+use thiserror::Error;
+
+/// Rules a new password has to follow before it gets hashed
+#[derive(Debug, Clone)]
+pub(crate) struct Policy
+{
+    min_length: usize,
+    max_length: usize,
+    min_strength: u8,
+    breached_list: Option<PathBuf>,
+}
+
+impl Policy
+{
+    pub(crate) fn new(
+        min_length: usize,
+        max_length: usize,
+        min_strength: u8,
+        breached_list: Option<PathBuf>,
+    ) -> Self
+    {
+        Policy {
+            min_length,
+            max_length,
+            min_strength,
+            breached_list,
+        }
+    }
+
+    /// Checks the password against the policy, where `user_inputs` are the
+    /// other things the user told us about themselves, such as their username,
+    /// which make for a weak password
+    pub(crate) async fn check(&self, password: String, user_inputs: Vec<String>) -> Result<()>
+    {
+        let policy = self.clone();
+
+        task::spawn_blocking(move || -> Result<()> {
+            let length = password.chars().count();
+            if length < policy.min_length {
+                return Err(Violation::TooShort {
+                    min: policy.min_length,
+                }
+                .into());
+            }
+            if length > policy.max_length {
+                return Err(Violation::TooLong {
+                    max: policy.max_length,
+                }
+                .into());
+            }
+
+            let user_inputs = user_inputs.iter().map(String::as_str).collect::<Vec<_>>();
+            // NOTE: The only error zxcvbn reports is for a blank password,
+            // which the minimum length check above already rules out as long
+            // as the minimum is not zero
+            let strength = zxcvbn::zxcvbn(&password, &user_inputs)
+                .map(|entropy| entropy.score())
+                .unwrap_or(0);
+            if strength < policy.min_strength {
+                return Err(Violation::TooWeak {
+                    strength,
+                    min: policy.min_strength,
+                }
+                .into());
+            }
+
+            if let Some(breached_list) = &policy.breached_list {
+                let hash = format!("{:X}", Sha1::digest(password.as_bytes()));
+                if is_listed(breached_list, &hash)? {
+                    return Err(Violation::Breached.into());
+                }
+            }
+
+            Ok(())
+        })
+        .await?
+    }
+}
+
+/// Looks the SHA-1 hash up in a list of uppercase hex hashes sorted in
+/// ascending order, one per line and optionally followed by `:` and a count,
+/// like the ones published by Have I Been Pwned. Such lists are far too large
+/// to be read whole, so they are binary searched straight from disk
+fn is_listed(path: &Path, hash: &str) -> io::Result<bool>
+{
+    let mut file = BufReader::new(File::open(path)?);
+    let mut low = 0;
+    let mut high = file.get_ref().metadata()?.len();
+
+    while low < high {
+        let mid = low + (high - low) / 2;
+
+        let ordering = match first_line_from(&mut file, mid)? {
+            Some(line) => {
+                let listed_hash = line.split(':').next().unwrap_or_default().trim();
+                listed_hash.cmp(hash)
+            }
+            None => Ordering::Greater,
+        };
+
+        match ordering {
+            Ordering::Equal => return Ok(true),
+            Ordering::Less => low = mid + 1,
+            Ordering::Greater => high = mid,
+        }
+    }
+
+    Ok(false)
+}
+
+/// The first line starting at or after `offset`
+fn first_line_from(file: &mut BufReader<File>, offset: u64) -> io::Result<Option<String>>
+{
+    let mut line = String::new();
+
+    if offset > 0 {
+        // Stepping back a byte makes sure a line starting right at `offset` is
+        // not skipped over along with the one before it
+        let _position = file.seek(SeekFrom::Start(offset - 1))?;
+        let _read = file.read_line(&mut line)?;
+        line.clear();
+    } else {
+        let _position = file.seek(SeekFrom::Start(0))?;
+    }
+
+    match file.read_line(&mut line)? {
+        0 => Ok(None),
+        _ => Ok(Some(line)),
+    }
+}
+
+type Result<T> = ::core::result::Result<T, Error>;
+
+#[derive(Debug, Error)]
+pub(crate) enum Violation
+{
+    #[error("password must be at least {min} characters long")]
+    TooShort
+    {
+        min: usize
+    },
+    #[error("password must be at most {max} characters long")]
+    TooLong
+    {
+        max: usize
+    },
+    #[error("password is too easy to guess, scoring {strength} out of the required {min}")]
+    TooWeak
+    {
+        strength: u8, min: u8
+    },
+    #[error("password has appeared in a data breach")]
+    Breached,
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum Error
+{
+    #[error("{0}")]
+    Violation(#[from] Violation),
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("{0}")]
+    TaskJoin(#[from] task::JoinError),
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::{fs, process};
+
+    use super::*;
+
+    /// A breach list written out to a temporary file, which is removed again
+    /// once the test is done with it
+    struct BreachedList
+    {
+        path: PathBuf,
+    }
+
+    impl BreachedList
+    {
+        fn new(name: &str, lines: &[String]) -> Self
+        {
+            let path =
+                std::env::temp_dir().join(format!("bluebird-{}-{}.txt", name, process::id()));
+            fs::write(&path, lines.join("\r\n")).unwrap();
+
+            BreachedList { path }
+        }
+    }
+
+    impl Drop for BreachedList
+    {
+        fn drop(&mut self)
+        {
+            let _res = fs::remove_file(&self.path);
+        }
+    }
+
+    fn hash(password: &str) -> String
+    {
+        format!("{:X}", Sha1::digest(password.as_bytes()))
+    }
+
+    /// The hashes of a few passwords in the order a list has them
+    fn sorted_hashes() -> Vec<String>
+    {
+        let mut hashes = (0..9)
+            .map(|n| hash(&format!("password{}", n)))
+            .collect::<Vec<_>>();
+        hashes.sort();
+
+        hashes
+    }
+
+    /// Every other hash followed by a count, as lists may have either
+    fn with_counts(hashes: &[String]) -> Vec<String>
+    {
+        hashes
+            .iter()
+            .enumerate()
+            .map(|(i, hash)| match i % 2 {
+                0 => format!("{}:{}", hash, i + 1),
+                _ => hash.clone(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_listed_hash_is_found()
+    {
+        let hashes = sorted_hashes();
+        let list = BreachedList::new("every-listed", &with_counts(&hashes));
+
+        // The first, last and all those in between, with or without a count
+        for hash in &hashes {
+            assert!(is_listed(&list.path, hash).unwrap(), "{}", hash);
+        }
+    }
+
+    #[test]
+    fn unlisted_hashes_are_not_found()
+    {
+        let hashes = sorted_hashes();
+        let listed = hashes
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| i % 2 == 0)
+            .map(|(_, hash)| hash.clone())
+            .collect::<Vec<_>>();
+        let list = BreachedList::new("unlisted", &with_counts(&listed));
+
+        // Those left out fall between listed ones
+        for hash in hashes.iter().skip(1).step_by(2) {
+            assert!(!is_listed(&list.path, hash).unwrap(), "{}", hash);
+        }
+        // Before the first and after the last
+        assert!(!is_listed(&list.path, &"0".repeat(40)).unwrap());
+        assert!(!is_listed(&list.path, &"F".repeat(40)).unwrap());
+        // Counts are not part of the hash
+        let with_count = format!("{}:1", listed[0]);
+        assert!(!is_listed(&list.path, &with_count).unwrap());
+        assert!(!is_listed(&list.path, &listed[0][..39]).unwrap());
+    }
+
+    #[test]
+    fn lists_of_one_line_are_searched()
+    {
+        let hashes = sorted_hashes();
+        let list = BreachedList::new("one-line", &[format!("{}:42", hashes[4])]);
+
+        assert!(is_listed(&list.path, &hashes[4]).unwrap());
+        assert!(!is_listed(&list.path, &hashes[0]).unwrap());
+        assert!(!is_listed(&list.path, &hashes[8]).unwrap());
+
+        let empty = BreachedList::new("empty", &[]);
+        assert!(!is_listed(&empty.path, &hashes[4]).unwrap());
+    }
+
+    #[test]
+    fn lines_are_read_from_the_next_start()
+    {
+        let lines = [
+            String::from("AAA:1"),
+            String::from("BBB"),
+            String::from("CCC:3"),
+        ];
+        let list = BreachedList::new("next-start", &lines);
+        let mut file = BufReader::new(File::open(&list.path).unwrap());
+
+        let mut line_at = |offset| first_line_from(&mut file, offset).unwrap();
+        assert_eq!(line_at(0).as_deref(), Some("AAA:1\r\n"));
+        // Anywhere within a line, including its line break, moves on to the
+        // next one
+        assert_eq!(line_at(1).as_deref(), Some("BBB\r\n"));
+        assert_eq!(line_at(6).as_deref(), Some("BBB\r\n"));
+        assert_eq!(line_at(7).as_deref(), Some("BBB\r\n"));
+        assert_eq!(line_at(8).as_deref(), Some("CCC:3"));
+        assert_eq!(line_at(12).as_deref(), Some("CCC:3"));
+        assert_eq!(line_at(13), None);
+        assert_eq!(line_at(100), None);
+    }
+
+    #[tokio::test]
+    async fn breached_passwords_are_refused()
+    {
+        let list = BreachedList::new("breached", &with_counts(&sorted_hashes()));
+        let policy = Policy::new(8, 128, 0, Some(list.path.clone()));
+
+        let res = policy.check(String::from("password3"), Vec::new()).await;
+        assert!(matches!(res, Err(Error::Violation(Violation::Breached))));
+        assert!(policy
+            .check(String::from("password9"), Vec::new())
+            .await
+            .is_ok());
+    }
+}
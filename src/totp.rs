@@ -0,0 +1,219 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use rand::{Rng, RngCore};
+use sha1::Sha1;
+
+const ISSUER: &str = "Bluebird";
+const SECRET_LEN: usize = 20;
+const DIGITS: u32 = 6;
+const PERIOD_SECS: u64 = 30;
+// Codes from the steps right before and after the current one are accepted
+// too, to make up for clock drift and for the time it takes to type them in
+const SKEW_STEPS: u64 = 1;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_LEN: usize = 10;
+// Lowercase letters and digits, save for the ones easily mistaken for others
+const RECOVERY_CODE_ALPHABET: &[char] = &[
+    'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'j', 'k', 'm', 'n', 'p', 'q', 'r', 's', 't', 'u', 'v',
+    'w', 'x', 'y', 'z', '2', '3', '4', '5', '6', '7', '8', '9',
+];
+
+pub(crate) fn generate_secret() -> Vec<u8>
+{
+    let mut secret = vec![0u8; SECRET_LEN];
+    rand::thread_rng().fill_bytes(&mut secret);
+    secret
+}
+
+/// The secret in the unpadded base32 form authenticator apps expect when it
+/// is typed in by hand
+pub(crate) fn encode_secret(secret: &[u8]) -> String
+{
+    base32::encode(base32::Alphabet::RFC4648 { padding: false }, secret)
+}
+
+/// The `otpauth://` URI authenticator apps read the secret from, usually
+/// through a QR code
+pub(crate) fn uri(account: &str, secret: &[u8]) -> String
+{
+    let issuer = utf8_percent_encode(ISSUER, NON_ALPHANUMERIC);
+    let account = utf8_percent_encode(account, NON_ALPHANUMERIC);
+
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1\
+         &digits={digits}&period={period}",
+        issuer = issuer,
+        account = account,
+        secret = encode_secret(secret),
+        digits = DIGITS,
+        period = PERIOD_SECS,
+    )
+}
+
+/// Checks the code against the secret, handing back the time step it belongs
+/// to, which callers should record to turn away any code from the same step
+/// or an earlier one afterwards
+pub(crate) fn verify(secret: &[u8], code: &str, now: SystemTime) -> Option<u64>
+{
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    // SAFETY: The code was just checked to be made of a few digits only
+    let code = code.parse::<u32>().unwrap();
+
+    let current_step = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / PERIOD_SECS;
+
+    (current_step.saturating_sub(SKEW_STEPS)..=current_step + SKEW_STEPS)
+        .find(|step| hotp(secret, *step) == code)
+}
+
+/// HOTP as defined by RFC 4226, which TOTP runs over the number of periods
+/// elapsed since the Unix epoch
+fn hotp(secret: &[u8], counter: u64) -> u32
+{
+    // SAFETY: HMAC accepts keys of any length
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).unwrap();
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = usize::from(digest[digest.len() - 1] & 0x0f);
+    let truncated = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+
+    truncated % 10u32.pow(DIGITS)
+}
+
+/// Single-use codes which stand in for a TOTP code when the authenticator is
+/// out of reach, formatted as two dash-separated halves for readability
+pub(crate) fn generate_recovery_codes() -> Vec<String>
+{
+    let mut rng = rand::thread_rng();
+
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code = (0..RECOVERY_CODE_LEN)
+                .map(|_| RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())])
+                .collect::<String>();
+            let (first_half, second_half) = code.split_at(RECOVERY_CODE_LEN / 2);
+
+            format!("{}-{}", first_half, second_half)
+        })
+        .collect()
+}
+
+/// Recovery codes are random enough for a fast hash to do, unlike passwords.
+/// Case, dashes and whitespace are ignored, as they are easy to get wrong when
+/// typing a code in
+pub(crate) fn hash_recovery_code(code: &str) -> String
+{
+    let code = code
+        .chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect::<String>();
+
+    blake3::hash(code.as_bytes()).to_hex().to_string()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::time::Duration;
+
+    use super::*;
+
+    // The secret both RFCs use for their SHA-1 test vectors
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    fn at(secs: u64) -> SystemTime
+    {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn code(counter: u64) -> String
+    {
+        format!("{:06}", hotp(RFC_SECRET, counter))
+    }
+
+    #[test]
+    fn hotp_matches_rfc_4226()
+    {
+        // Appendix D
+        let expected = [
+            755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489,
+        ];
+
+        for (counter, expected) in expected.into_iter().enumerate() {
+            assert_eq!(hotp(RFC_SECRET, counter as u64), expected);
+        }
+    }
+
+    #[test]
+    fn verify_matches_rfc_6238()
+    {
+        // Appendix B, cut down to the last six of its eight digits, which is
+        // what truncating to six digits leaves
+        let expected = [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ];
+
+        for (secs, expected) in expected {
+            assert_eq!(
+                verify(RFC_SECRET, expected, at(secs)),
+                Some(secs / PERIOD_SECS)
+            );
+        }
+    }
+
+    #[test]
+    fn codes_are_accepted_one_step_either_way()
+    {
+        let now = at(1111111111);
+        let step = 1111111111 / PERIOD_SECS;
+
+        assert_eq!(verify(RFC_SECRET, &code(step - 1), now), Some(step - 1));
+        assert_eq!(verify(RFC_SECRET, &code(step), now), Some(step));
+        assert_eq!(verify(RFC_SECRET, &code(step + 1), now), Some(step + 1));
+        assert_eq!(verify(RFC_SECRET, &code(step - 2), now), None);
+        assert_eq!(verify(RFC_SECRET, &code(step + 2), now), None);
+    }
+
+    #[test]
+    fn replayed_codes_resolve_to_the_step_already_used()
+    {
+        let step = 1111111111 / PERIOD_SECS;
+        let first_use = verify(RFC_SECRET, &code(step), at(step * PERIOD_SECS)).unwrap();
+
+        // Still within the window a period later, yet the step it hands back
+        // is no later than the one recorded, which is what gets it refused
+        let replay = verify(RFC_SECRET, &code(step), at((step + 1) * PERIOD_SECS)).unwrap();
+        assert!(replay <= first_use);
+
+        let next_code = verify(RFC_SECRET, &code(step + 1), at((step + 1) * PERIOD_SECS)).unwrap();
+        assert!(next_code > first_use);
+    }
+
+    #[test]
+    fn malformed_codes_are_refused()
+    {
+        let now = at(59);
+
+        assert_eq!(verify(RFC_SECRET, " 287082 ", now), Some(1));
+        for malformed in ["", "28708", "2870820", "28708a", "+87082", "-87082"] {
+            assert_eq!(verify(RFC_SECRET, malformed, now), None);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use thiserror::Error;
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::{skeleton, MixedScript};
+
+const MIN_LENGTH: usize = 3;
+const MAX_LENGTH: usize = 32;
+
+/// Names that could pass for the service itself or collide with routes
+const RESERVED: &[&str] = &[
+    "admin",
+    "administrator",
+    "api",
+    "auth",
+    "bluebird",
+    "help",
+    "me",
+    "moderator",
+    "root",
+    "support",
+    "system",
+    "users",
+];
+
+/// Brings the username to the single form it is stored and looked up in, then
+/// checks that form against the username rules
+///
+/// Normalization applies NFKC and full case folding, so that `Alice`, `alice`
+/// and `ａｌｉｃｅ` all end up as the same account
+pub(crate) fn normalize(username: &str) -> Result<String>
+{
+    // Case folding can undo NFKC, hence the second pass
+    let username = username.trim().nfkc().collect::<String>();
+    let username = caseless::default_case_fold_str(&username)
+        .nfkc()
+        .collect::<String>();
+
+    validate(&username)?;
+
+    Ok(username)
+}
+
+/// The form two usernames share when they only look alike, such as `rn` and
+/// `m`, which is what is kept unique on top of the username itself
+pub(crate) fn look_alike_form(username: &str) -> String
+{
+    skeleton(username).collect()
+}
+
+fn validate(username: &str) -> Result<()>
+{
+    let length = username.chars().count();
+    if length < MIN_LENGTH {
+        return Err(Error::TooShort { min: MIN_LENGTH });
+    }
+    if length > MAX_LENGTH {
+        return Err(Error::TooLong { max: MAX_LENGTH });
+    }
+
+    let mut chars = username.chars();
+    // SAFETY: The length check above rules out an empty username
+    let first = chars.next().unwrap();
+    if !first.is_alphanumeric() || !chars.all(|c| c.is_alphanumeric() || c == '_' || c == '-') {
+        return Err(Error::InvalidCharacters);
+    }
+
+    // Mixing scripts is how lookalikes such as a Cyrillic `а` in `аlice` get
+    // through, while names written in a single script of their own are fine
+    if !username.is_single_script() {
+        return Err(Error::MixedScripts);
+    }
+
+    let username_skeleton = look_alike_form(username);
+    let is_reserved = RESERVED
+        .iter()
+        .any(|reserved| look_alike_form(reserved) == username_skeleton);
+    if is_reserved {
+        return Err(Error::Reserved);
+    }
+
+    Ok(())
+}
+
+type Result<T> = ::core::result::Result<T, Error>;
+
+#[derive(Debug, Error)]
+pub(crate) enum Error
+{
+    #[error("username must be at least {min} characters long")]
+    TooShort
+    {
+        min: usize
+    },
+    #[error("username must be at most {max} characters long")]
+    TooLong
+    {
+        max: usize
+    },
+    #[error(
+        "username must start with a letter or digit and contain only letters, digits, `_` and `-`"
+    )]
+    InvalidCharacters,
+    #[error("username must not mix characters from different scripts")]
+    MixedScripts,
+    #[error("username is reserved")]
+    Reserved,
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn usernames_are_folded_to_one_form()
+    {
+        for username in [
+            "alice",
+            "Alice",
+            "ALICE",
+            "ａｌｉｃｅ",
+            "ＡＬＩＣＥ",
+            "  alice  ",
+        ] {
+            assert_eq!(normalize(username).unwrap(), "alice", "{}", username);
+        }
+        assert_eq!(normalize("Straße").unwrap(), "strasse");
+        assert_eq!(normalize("ｂｏｂ１２").unwrap(), "bob12");
+    }
+
+    #[test]
+    fn lengths_are_counted_in_characters()
+    {
+        assert!(matches!(normalize("ab"), Err(Error::TooShort { min: 3 })));
+        assert!(matches!(
+            normalize("  ab  "),
+            Err(Error::TooShort { min: 3 })
+        ));
+        assert_eq!(normalize("éèê").unwrap(), "éèê");
+        assert_eq!(normalize(&"é".repeat(32)).unwrap(), "é".repeat(32));
+        assert!(matches!(
+            normalize(&"a".repeat(33)),
+            Err(Error::TooLong { max: 32 })
+        ));
+    }
+
+    #[test]
+    fn only_letters_digits_underscores_and_dashes_are_allowed()
+    {
+        assert_eq!(normalize("al_ice-99").unwrap(), "al_ice-99");
+        for username in ["_alice", "-alice", "al ice", "al.ice", "alice!", "al@ce"] {
+            assert!(
+                matches!(normalize(username), Err(Error::InvalidCharacters)),
+                "{}",
+                username
+            );
+        }
+    }
+
+    #[test]
+    fn scripts_must_not_be_mixed()
+    {
+        assert_eq!(normalize("Иван").unwrap(), "иван");
+        // A Cyrillic `а` in front of Latin letters
+        assert!(matches!(normalize("\u{430}lice"), Err(Error::MixedScripts)));
+    }
+
+    #[test]
+    fn reserved_names_are_refused_along_with_their_look_alikes()
+    {
+        for username in ["admin", "Admin", "ＡＤＭＩＮ", "adrnin", "rnoderator"] {
+            assert!(
+                matches!(normalize(username), Err(Error::Reserved)),
+                "{}",
+                username
+            );
+        }
+        assert!(normalize("administrators").is_ok());
+    }
+
+    #[test]
+    fn look_alikes_share_a_form()
+    {
+        assert_eq!(look_alike_form("alice"), look_alike_form("a1ice"));
+        assert_eq!(look_alike_form("modern"), look_alike_form("modem"));
+        assert_ne!(look_alike_form("alice"), look_alike_form("alicia"));
+    }
+}